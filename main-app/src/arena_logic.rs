@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_rand::prelude::*;
+use bevy_smud::prelude::*;
+use rand::prelude::*;
+use rand_chacha::rand_core::RngCore;
+use snake_api_lib::{
+    api::StepResult,
+    arena::Arena,
+    common::Direction,
+    level::Level,
+    simulator::{GreedyPlayer, PlayerTrait},
+};
+
+use crate::{
+    AppState,
+    bot_logic::Agent,
+    constants::{ARENA_SNAKE_COLOURS, WALL_COLOUR},
+    endscreen::EndScreenState,
+    game_logic::{FinishedGame, GameEnd, PlayTime, Rivals, draw_cell, food_colour, keyboard_dir},
+    menu::{MenuMessage, MenuOptions},
+    replay_logic::LastReplay,
+    setup::WinDimension,
+};
+
+/// Snakes sharing the board, the player drives the first one
+const ARENA_SNAKES: usize = 4;
+
+/// The player and the trained agent, in "Human vs AI" games
+const VERSUS_SNAKES: usize = 2;
+
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct ArenaState {
+    pub(crate) arena: Arena,
+    pub(crate) player_dir: Direction,
+    /// Moves of the bots, drawn from the session seed like the food
+    pub(crate) bots_rng: SmallRng,
+}
+
+#[derive(Clone, PartialEq, Eq, Resource, Default)]
+pub(crate) struct ShaderResourceArena(pub(crate) Handle<Shader>);
+
+/// Snakes and food, redrawn on every step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+pub(crate) struct ArenaCellComponent;
+
+pub struct ArenaPlugin;
+
+impl Plugin for ArenaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(AppState::Arena),
+            arena_setup.after(crate::setup::setup),
+        )
+        .add_systems(
+            Update,
+            set_keyboard_dir
+                .run_if(in_state(AppState::Arena))
+                .run_if(resource_exists::<ArenaState>),
+        )
+        .add_systems(
+            FixedUpdate,
+            (step_arena, ui_arena)
+                .chain()
+                .run_if(in_state(AppState::Arena))
+                .run_if(resource_exists::<ArenaState>),
+        )
+        .add_systems(OnExit(AppState::Arena), cleanup_arena);
+    }
+}
+
+fn cleanup_arena(mut commands: Commands) {
+    commands.remove_resource::<ArenaState>();
+    commands.remove_resource::<ShaderResourceArena>();
+    commands.remove_resource::<PlayTime>();
+}
+
+fn set_keyboard_dir(mut res: ResMut<ArenaState>, key: Res<ButtonInput<KeyCode>>) {
+    if let Some(dir) = keyboard_dir(&key) {
+        res.player_dir = dir;
+    }
+}
+
+/// Bots follow the trained agent when there is one, the greedy player otherwise
+fn step_arena(
+    mut commands: Commands,
+    mut arena_state: ResMut<ArenaState>,
+    mut speed: ResMut<Time<Fixed>>,
+    mut play_time: ResMut<PlayTime>,
+    mut end: GameEnd,
+) {
+    let ArenaState {
+        arena,
+        player_dir,
+        bots_rng,
+    } = &mut *arena_state;
+    let dirs = (0..arena.snakes.len())
+        .map(|i| {
+            if i == 0 {
+                *player_dir
+            } else if arena.snakes[i].is_alive() {
+                match end.agent.as_deref_mut() {
+                    Some(agent) => agent.choose_dir(&arena.view(i), bots_rng),
+                    None => GreedyPlayer.choose_dir(&arena.view(i), bots_rng),
+                }
+            } else {
+                arena.snakes[i].snake.direction
+            }
+        })
+        .collect::<Vec<_>>();
+    let results = arena.next(&dirs).unwrap();
+    play_time.0 += speed.timestep().as_secs_f32();
+    speed.set_timestep(
+        Duration::try_from_secs_f32(arena.tick_secs()).expect("Should be valid time"),
+    );
+    if results[0].is_terminal() {
+        // Scored like a single game, the player's own snake, apples and steps
+        commands.insert_resource(FinishedGame {
+            game: arena.view(0),
+            result: results[0],
+            key: end.options.score_key(),
+            secs: play_time.0,
+            agent: None,
+            confidence: None,
+            rivals: Some(Rivals {
+                scores: arena.snakes[1..].iter().map(|s| s.score).collect(),
+                agent: end.agent.as_ref().map(|agent| agent.name.clone()),
+                confidence: end.agent.and_then(|agent| agent.mean_confidence()),
+            }),
+        });
+        end.next_state.set(AppState::EndScreen);
+        let next_sub = if matches!(results[0], StepResult::Win { .. }) {
+            EndScreenState::Win
+        } else {
+            EndScreenState::Lose
+        };
+        end.next_state_sub.set(next_sub)
+    }
+}
+
+fn ui_arena(
+    arena_state: Res<ArenaState>,
+    win_dim: Res<WinDimension>,
+    sdf_res: Res<ShaderResourceArena>,
+    query_cells: Query<Entity, With<ArenaCellComponent>>,
+    mut commands: Commands,
+) {
+    for ent in query_cells.iter() {
+        commands.entity(ent).despawn();
+    }
+    let arena = &arena_state.arena;
+    for (arena_snake, colour) in arena.snakes.iter().zip(ARENA_SNAKE_COLOURS) {
+        if !arena_snake.is_alive() {
+            continue;
+        }
+        for cell in arena_snake.snake.cells() {
+            commands
+                .spawn(draw_cell(
+                    cell,
+                    *win_dim,
+                    sdf_res.0.clone(),
+                    colour.into(),
+                    AppState::Arena,
+                ))
+                .insert(ArenaCellComponent);
+        }
+    }
+    for food in arena.apples.iter() {
+        commands
+            .spawn(draw_cell(
+                food.pos,
+                *win_dim,
+                sdf_res.0.clone(),
+                food_colour(food.kind),
+                AppState::Arena,
+            ))
+            .insert(ArenaCellComponent);
+    }
+}
+
+fn arena_setup(
+    mut commands: Commands,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut win_dim: ResMut<WinDimension>,
+    options: Res<MenuOptions>,
+    agent: Option<Res<Agent>>,
+    mut next_state: ResMut<NextState<AppState>>,
+    mut rng: Single<&mut ChaCha8Rng, With<GlobalRng>>,
+) {
+    let seed = options.seed().unwrap_or_else(|| rng.next_u64());
+    info!("Starting arena with seed {seed}");
+    let level = Level::empty(options.board);
+    let snakes = if agent.is_some() {
+        VERSUS_SNAKES
+    } else {
+        ARENA_SNAKES
+    };
+    let arena = match Arena::new(seed, Some(options.game_options()), level, snakes) {
+        Ok(arena) => arena,
+        Err(e) => {
+            commands.insert_resource(MenuMessage(e.to_string()));
+            next_state.set(AppState::Menu);
+            return;
+        }
+    };
+    win_dim.set_board(arena.level.board());
+
+    let sdf = shaders.add_sdf_expr(win_dim.generate_sdf_string());
+    for wall in arena.level.walls() {
+        commands.spawn(draw_cell(
+            wall,
+            *win_dim,
+            sdf.clone(),
+            WALL_COLOUR.into(),
+            AppState::Arena,
+        ));
+    }
+    // Arena games have no replay, drop the one of an earlier game
+    commands.remove_resource::<LastReplay>();
+    commands.remove_resource::<FinishedGame>();
+    let player_dir = arena.snakes[0].snake.direction;
+    // The bots get their own stream so their moves do not shift the food
+    let bots_rng = SmallRng::seed_from_u64(seed.wrapping_add(1));
+    commands.insert_resource(ArenaState {
+        arena,
+        player_dir,
+        bots_rng,
+    });
+    commands.insert_resource(ShaderResourceArena(sdf));
+    commands.insert_resource(PlayTime::default());
+}
@@ -1,64 +1,191 @@
-use std::sync::{Arc, Mutex};
-
-use bevy::prelude::*;
-use bevy_rand::{global::GlobalRng, prelude::ChaCha8Rng};
-use burn::{
-    backend::NdArray,
-    prelude::*,
-    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+use std::{
+    cell::RefCell,
+    path::PathBuf,
+    sync::{Arc, Mutex},
 };
-use rand::{SeedableRng, rngs::SmallRng};
-use rand_chacha::rand_core::RngCore;
+
+use anyhow::{Result as ARes, anyhow, bail};
+use bevy::{prelude::*, window::FileDragAndDrop};
+use burn::{backend::NdArray, prelude::*};
+use rand::rngs::SmallRng;
 use rl_evo_train::{
-    data::PlayerModel,
-    model::{Model, ModelConfig, StateRepr},
+    data::{DatasetGeneratorConfig, PlayerModel},
+    model::{ActorCritic, Model, ModelConfig, Policy, StateRepr, load_weights},
+};
+use snake_api_lib::{
+    api::GameAPI,
+    common::Direction,
+    observation::{FrameStack, ObservationConfig},
 };
-use snake_api_lib::simulator::PlayerTrait;
 
-use crate::game_logic::{GameState, step_snake};
+use crate::{AppState, game_logic::GameState, menu::MenuMessage};
 
 pub struct BotAgent;
 
+type MyBackend = NdArray<f32, i32>;
+
+/// Weights of either trainer, the evolution one shares the DQN model
+enum AgentModel {
+    Dqn(Box<Model<MyBackend>>),
+    Ppo(Box<ActorCritic<MyBackend>>),
+}
+
+impl Policy<MyBackend> for AgentModel {
+    fn scores(&self, state: StateRepr<MyBackend>) -> Tensor<MyBackend, 2> {
+        match self {
+            Self::Dqn(model) => model.scores(state),
+            Self::Ppo(model) => model.scores(state),
+        }
+    }
+}
+
+/// Trained model driving the snake of the game in progress
 #[derive(Resource)]
-pub struct Agent(pub Arc<Mutex<Model<MyBackend>>>);
+pub(crate) struct Agent {
+    model: Arc<Mutex<AgentModel>>,
+    observation: ObservationConfig,
+    /// Frames seen so far, for models trained on stacked frames
+    history: FrameStack,
+    /// Name of the weights file
+    pub(crate) name: String,
+    /// Moves chosen so far and the sum of their confidences
+    decisions: u32,
+    confidence: f32,
+}
 
-type MyBackend = NdArray<f32, i32>;
+impl Agent {
+    pub(crate) fn choose_dir(&mut self, game: &GameAPI, rng: &mut SmallRng) -> Direction {
+        let device = Default::default();
+        let model = self.model.lock().expect("should be lockable");
+        let mut player = PlayerModel::<MyBackend, _>::new(&*model, &device, &self.observation);
+        player.history = RefCell::new(self.history.clone());
+        let (dir, confidence) = player.choose_dir_with_confidence(game, rng);
+        self.history = player.history.into_inner();
+        self.decisions += 1;
+        self.confidence += confidence;
+        dir
+    }
+
+    /// Average share of its softmax the model gave the moves it made
+    pub(crate) fn mean_confidence(&self) -> Option<f32> {
+        (self.decisions > 0).then(|| self.confidence / self.decisions as f32)
+    }
+}
 
-fn load_file() -> Agent {
-    // Load model in full precision from MessagePack file
-    // let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
-    //
-    // let device = Default::default();
-    // let model: Model<MyBackend> = ModelConfig::new(4, 1024)
-    //     .init(&device)
-    //     .load_file("/tmp/burn-tutorial/model.mpk", &recorder, &device)
-    //     .expect("Should be able to load the model weights from the provided file");
-    // Agent(Arc::new(Mutex::new(model)))
-    todo!("Fix")
+/// Where the "Watch AI" mode loads its model from, `--model` on the command line or a
+/// file dropped on the menu
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct AgentSource {
+    pub(crate) model: Option<PathBuf>,
+    /// Training config, the model sees games through its `observation`
+    pub(crate) config: PathBuf,
 }
 
+impl AgentSource {
+    pub(crate) fn from_args() -> Self {
+        let arg = |name: &str| std::env::args().skip_while(|arg| arg != name).nth(1);
+        Self {
+            model: arg("--model").map(PathBuf::from),
+            config: arg("--config")
+                .unwrap_or_else(|| "./config.json".to_owned())
+                .into(),
+        }
+    }
+}
+
+/// Loads the weights of `source` sized by the `ModelConfig` saved next to them, the
+/// `.json` file of the same name the trainers write
+pub(crate) fn load_agent(source: &AgentSource) -> ARes<Agent> {
+    let Some(path) = &source.model else {
+        bail!("No model, pass --model or drop a .mpk file on the window");
+    };
+    if !path.exists() {
+        bail!("No model at {}", path.display());
+    }
+    let config_path = path.with_extension("json");
+    let config = ModelConfig::load(&config_path)
+        .map_err(|e| anyhow!("Cannot read {}: {e}", config_path.display()))?;
+    let observation = if source.config.exists() {
+        let dgb: DatasetGeneratorConfig =
+            serde_json::from_str(&std::fs::read_to_string(&source.config)?)
+                .map_err(|e| anyhow!("Cannot read {}: {e}", source.config.display()))?;
+        dgb.observation
+    } else {
+        ObservationConfig::default()
+    };
+    if !config.fits(&observation) {
+        bail!(
+            "{} was not trained on the observations of {}",
+            path.display(),
+            source.config.display()
+        );
+    }
+
+    let device = Default::default();
+    let model = match load_weights(config.init::<MyBackend>(&device), path, &device) {
+        Ok(model) => AgentModel::Dqn(Box::new(model)),
+        // Actor critics have policy and value heads in place of the last layer
+        Err(dqn) => {
+            match load_weights(
+                config.init_actor_critic::<MyBackend>(&device),
+                path,
+                &device,
+            ) {
+                Ok(model) => AgentModel::Ppo(Box::new(model)),
+                Err(ppo) => bail!("Not a DQN model: {dqn}\nNor an actor critic: {ppo}"),
+            }
+        }
+    };
+    Ok(Agent {
+        model: Arc::new(Mutex::new(model)),
+        observation,
+        history: observation.stack(),
+        name: path
+            .file_stem()
+            .map_or_else(|| "AI".to_owned(), |s| s.to_string_lossy().into_owned()),
+        decisions: 0,
+        confidence: 0.,
+    })
+}
+
+/// Moves of the agent, drawn from the session seed like the food so a seed replays
+/// the whole game
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct AgentRng(pub(crate) SmallRng);
+
 pub fn set_dir_agent(
-    agent: Res<Agent>,
+    mut agent: ResMut<Agent>,
     mut game_state: ResMut<GameState>,
-    mut rng: Single<&mut ChaCha8Rng, With<GlobalRng>>,
+    mut rng: ResMut<AgentRng>,
+) {
+    let dir = agent.choose_dir(&game_state.0, &mut rng.0);
+    game_state.0.update_direction(dir);
+}
+
+/// Picks up model files dropped on the window while in the menu
+fn drop_model(
+    mut commands: Commands,
+    mut events: MessageReader<FileDragAndDrop>,
+    mut source: ResMut<AgentSource>,
 ) {
-    // let device = Default::default();
-    // let ag = agent.0.lock().expect("should be lockable");
-    // let player = PlayerModel {
-    //     model: &ag,
-    //     active_mode: false,
-    //     eps: 0.,
-    //     device: &device,
-    // };
-    //
-    // let dir = player.choose_dir(&game_state.0, &mut SmallRng::seed_from_u64(rng.next_u64()));
-    // game_state.0.update_direction(dir);
+    for event in events.read() {
+        if let FileDragAndDrop::DroppedFile { path_buf, .. } = event {
+            info!("Model set to {}", path_buf.display());
+            source.model = Some(path_buf.clone());
+            commands.remove_resource::<MenuMessage>();
+        }
+    }
+}
+
+fn cleanup_agent(mut commands: Commands) {
+    commands.remove_resource::<Agent>();
 }
 
 impl Plugin for BotAgent {
     fn build(&self, app: &mut App) {
-        // todo
-        // let agent = load_file();
-        // app.insert_resource(agent);
+        app.insert_resource(AgentSource::from_args())
+            .add_systems(Update, drop_model.run_if(in_state(AppState::Menu)))
+            .add_systems(OnExit(AppState::Game), cleanup_agent)
+            .add_systems(OnExit(AppState::Arena), cleanup_agent);
     }
 }
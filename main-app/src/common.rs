@@ -49,10 +49,18 @@ pub(crate) fn label_bundle(
 }
 
 pub(crate) fn draw_button(text: String, asset_server: &AssetServer) -> impl Bundle {
+    draw_button_sized(text, 250., asset_server)
+}
+
+pub(crate) fn draw_button_sized(
+    text: String,
+    width: f32,
+    asset_server: &AssetServer,
+) -> impl Bundle {
     (
         Button,
         Node {
-            width: Val::Px(250.),
+            width: Val::Px(width),
             height: Val::Px(65.),
             border: UiRect::all(Val::Px(5.)),
             // horizontally center child text
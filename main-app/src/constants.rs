@@ -1,13 +1,23 @@
 use bevy::color::{
-    palettes::tailwind::{PURPLE_400, TEAL_400, YELLOW_100, YELLOW_500}, Srgba
+    Srgba,
+    palettes::tailwind::{
+        AMBER_400, LIME_400, ORANGE_400, PINK_400, PURPLE_400, ROSE_400, SKY_400, SLATE_500,
+        TEAL_400, YELLOW_100, YELLOW_500,
+    },
 };
 
 pub(crate) const FRAME_MUL: f32 = 1.2;
 pub(crate) const BLOCK_Z: f32 = 10.;
 pub(crate) static SNAKE_COLOUR: Srgba = TEAL_400;
+/// Arena snakes, the first one is the player
+pub(crate) static ARENA_SNAKE_COLOURS: [Srgba; 4] = [TEAL_400, ORANGE_400, LIME_400, PINK_400];
 pub(crate) static APPLE_COLOUR: Srgba = PURPLE_400;
+pub(crate) static BONUS_COLOUR: Srgba = AMBER_400;
+pub(crate) static SHRINK_COLOUR: Srgba = ROSE_400;
+pub(crate) static TIMED_COLOUR: Srgba = SKY_400;
+pub(crate) static WALL_COLOUR: Srgba = SLATE_500;
 pub(crate) const TEXT_COLOR: Srgba = Srgba {
     alpha: 0.2,
     ..YELLOW_100
 };
-pub(crate) const TEXT_COLOR_TITLE: Srgba = YELLOW_500;
\ No newline at end of file
+pub(crate) const TEXT_COLOR_TITLE: Srgba = YELLOW_500;
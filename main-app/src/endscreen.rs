@@ -1,17 +1,43 @@
-use bevy::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::{
+    input::{
+        ButtonState,
+        keyboard::{Key, KeyboardInput},
+    },
+    prelude::*,
+};
+use snake_api_lib::{api::StepResult, highscore::ScoreEntry};
 
 use crate::{
     AppState,
     common::{draw_button, label_bundle},
     constants::TEXT_COLOR_TITLE,
+    game_logic::FinishedGame,
+    leaderboard::HighScoreBook,
+    replay_logic::LastReplay,
 };
 
+/// Longest name of the high score tables
+const MAX_NAME_CHARS: usize = 16;
+
 pub(crate) struct EndScreenPlugin;
 
 impl Plugin for EndScreenPlugin {
     fn build(&self, app: &mut App) {
         app.add_sub_state::<EndScreenState>()
-            .add_systems(OnEnter(AppState::EndScreen), draw_ui);
+            .add_systems(
+                OnEnter(AppState::EndScreen),
+                (start_name_entry, draw_ui).chain(),
+            )
+            .add_systems(
+                Update,
+                (type_name, draw_name.run_if(resource_changed::<NameEntry>))
+                    .chain()
+                    .run_if(in_state(AppState::EndScreen))
+                    .run_if(resource_exists::<NameEntry>),
+            )
+            .add_systems(OnExit(AppState::EndScreen), cleanup_name_entry);
     }
 }
 
@@ -23,10 +49,173 @@ pub(crate) enum EndScreenState {
     Lose,
 }
 
+/// Name typed for a new high score, the last player by default
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct NameEntry {
+    name: String,
+    /// Rank from 0 once saved
+    saved: Option<Option<usize>>,
+}
+
+#[derive(Debug, Clone, Copy, Component)]
+pub(crate) struct NameUi;
+
+/// Only games that make it into their high score table ask for a name
+fn start_name_entry(
+    mut commands: Commands,
+    finished: Option<Res<FinishedGame>>,
+    book: Res<HighScoreBook>,
+) {
+    let Some(finished) = finished else {
+        return;
+    };
+    if !book.scores.qualifies(&finished.key, finished.game.score) {
+        return;
+    }
+    let name = finished
+        .agent
+        .as_deref()
+        .or(book.scores.last_player())
+        .unwrap_or_default()
+        .to_owned();
+    commands.insert_resource(NameEntry { name, saved: None });
+}
+
+fn cleanup_name_entry(mut commands: Commands) {
+    commands.remove_resource::<NameEntry>();
+}
+
+/// Letters type the name, Backspace erases one and Enter saves it
+fn type_name(
+    mut keys: MessageReader<KeyboardInput>,
+    mut entry: ResMut<NameEntry>,
+    mut book: ResMut<HighScoreBook>,
+    finished: Res<FinishedGame>,
+) {
+    for key in keys.read() {
+        if key.state != ButtonState::Pressed || entry.saved.is_some() {
+            continue;
+        }
+        match &key.logical_key {
+            Key::Character(chars) => {
+                for c in chars.chars().filter(|c| !c.is_control()) {
+                    if entry.name.chars().count() < MAX_NAME_CHARS {
+                        entry.name.push(c);
+                    }
+                }
+            }
+            Key::Space if entry.name.chars().count() < MAX_NAME_CHARS => entry.name.push(' '),
+            Key::Backspace => {
+                entry.name.pop();
+            }
+            Key::Enter => save_score(&mut entry, &mut book, &finished),
+            _ => {}
+        }
+    }
+}
+
+fn save_score(entry: &mut NameEntry, book: &mut HighScoreBook, finished: &FinishedGame) {
+    let name = match entry.name.trim() {
+        "" => "Player".to_owned(),
+        name => name.to_owned(),
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let rank = book.record(
+        finished.key.clone(),
+        ScoreEntry {
+            name: name.clone(),
+            score: finished.game.score,
+            apples: finished.game.num_of_apples,
+            steps: finished.game.steps,
+            timestamp,
+        },
+    );
+    entry.name = name;
+    entry.saved = Some(rank);
+}
+
+fn draw_name(entry: Res<NameEntry>, mut text: Single<&mut Text, With<NameUi>>) {
+    text.0 = match entry.saved {
+        None => format!("Name: {}_", entry.name),
+        Some(Some(rank)) => format!("Saved {} at #{}", entry.name, rank + 1),
+        Some(None) => "The table filled up in the meantime".to_owned(),
+    };
+}
+
+fn on_click_save(
+    _: On<Pointer<Click>>,
+    mut entry: ResMut<NameEntry>,
+    mut book: ResMut<HighScoreBook>,
+    finished: Res<FinishedGame>,
+) {
+    if entry.saved.is_none() {
+        save_score(&mut entry, &mut book, &finished);
+    }
+}
+
+/// One line per statistic of the finished game
+fn stats_text(finished: &FinishedGame) -> String {
+    let game = &finished.game;
+    let level = match finished.result {
+        StepResult::Lost { level_reached, .. } => level_reached,
+        _ => game.mode,
+    };
+    let secs = finished.secs as u32;
+    let mut lines = vec![
+        format!("Score: {}", game.score),
+        format!("Apples: {}", game.num_of_apples),
+        format!("Length: {}", game.snake.cells().len()),
+        format!("Steps: {}", game.steps),
+        format!("Time: {}:{:02}", secs / 60, secs % 60),
+        format!("Level reached: {level}"),
+    ];
+    // In arena games the other snakes have moved on since, the cause is unknown
+    if matches!(finished.result, StepResult::Lost { .. })
+        && finished.rivals.is_none()
+        && let Some(cause) = game.death_cause()
+    {
+        lines.push(format!("Cause of death: {cause}"));
+    }
+    if finished.secs > 0. {
+        lines.push(format!(
+            "Apples per minute: {:.1}",
+            game.num_of_apples as f32 * 60. / finished.secs
+        ));
+    }
+    if let Some(agent) = &finished.agent {
+        lines.push(format!("Agent: {agent}"));
+    }
+    if let Some(confidence) = finished.confidence {
+        lines.push(format!("Average confidence: {:.0}%", confidence * 100.));
+    }
+    if let Some(rivals) = &finished.rivals {
+        let place = 1 + rivals.scores.iter().filter(|s| **s > game.score).count();
+        lines.push(format!("Place: {place} of {}", rivals.scores.len() + 1));
+        let scores = rivals
+            .scores
+            .iter()
+            .map(u128::to_string)
+            .collect::<Vec<_>>();
+        lines.push(format!("Rival scores: {}", scores.join(", ")));
+        if let Some(agent) = &rivals.agent {
+            lines.push(format!("Opponent: {agent}"));
+        }
+        if let Some(confidence) = rivals.confidence {
+            lines.push(format!("Opponent confidence: {:.0}%", confidence * 100.));
+        }
+    }
+    lines.join("\n")
+}
+
 fn draw_ui(
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     end_state: Res<State<EndScreenState>>,
+    last_replay: Option<Res<LastReplay>>,
+    name_entry: Option<Res<NameEntry>>,
+    finished: Option<Res<FinishedGame>>,
 ) {
     commands
         .spawn((
@@ -45,7 +234,7 @@ fn draw_ui(
                 "Game Over".to_owned(),
                 &asset_server,
                 Some(TEXT_COLOR_TITLE.into()),
-                Some(150.),
+                Some(100.),
             ));
 
             let label = match end_state.get() {
@@ -57,15 +246,77 @@ fn draw_ui(
                 label.to_owned(),
                 &asset_server,
                 Some(TEXT_COLOR_TITLE.into()),
-                Some(100.),
+                Some(60.),
             ));
 
             builder
-                .spawn(draw_button("Back to menu".to_owned(), &asset_server))
-                .observe(on_click);
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    column_gap: Val::Px(80.),
+                    ..Default::default()
+                })
+                .with_children(|row| {
+                    if let Some(finished) = &finished {
+                        row.spawn(label_bundle(
+                            stats_text(finished),
+                            &asset_server,
+                            Some(Color::srgb(0.9, 0.9, 0.9)),
+                            Some(25.),
+                        ));
+                    }
+
+                    let (Some(entry), Some(finished)) = (name_entry, finished) else {
+                        return;
+                    };
+                    row.spawn(Node {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        row_gap: Val::Px(20.),
+                        ..Default::default()
+                    })
+                    .with_children(|column| {
+                        column.spawn(label_bundle(
+                            format!("New high score! {}", finished.game.score),
+                            &asset_server,
+                            Some(TEXT_COLOR_TITLE.into()),
+                            Some(40.),
+                        ));
+                        column
+                            .spawn(label_bundle(
+                                format!("Name: {}_", entry.name),
+                                &asset_server,
+                                Some(Color::srgb(0.9, 0.9, 0.9)),
+                                Some(40.),
+                            ))
+                            .insert(NameUi);
+                        column
+                            .spawn(draw_button("Save score".to_owned(), &asset_server))
+                            .observe(on_click_save);
+                    });
+                });
+
+            builder
+                .spawn(Node {
+                    flex_direction: FlexDirection::Row,
+                    column_gap: Val::Px(40.),
+                    ..Default::default()
+                })
+                .with_children(|row| {
+                    if last_replay.is_some() {
+                        row.spawn(draw_button("Watch replay".to_owned(), &asset_server))
+                            .observe(on_click_replay);
+                    }
+                    row.spawn(draw_button("Back to menu".to_owned(), &asset_server))
+                        .observe(on_click);
+                });
         });
 }
 
 fn on_click(_: On<Pointer<Click>>, mut state: ResMut<NextState<AppState>>) {
     state.set(AppState::Menu);
 }
+
+fn on_click_replay(_: On<Pointer<Click>>, mut state: ResMut<NextState<AppState>>) {
+    state.set(AppState::Replay);
+}
@@ -1,6 +1,6 @@
 use std::time::Duration;
 
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 use bevy_rand::prelude::*;
 use bevy_smud::prelude::*;
 use rand::prelude::*;
@@ -8,32 +8,122 @@ use rand_chacha::rand_core::RngCore;
 use snake_api_lib::{
     api::{GameAPI, StepResult},
     common::{Coord, Direction},
+    food::FoodKind,
+    highscore::ScoreKey,
+    history::UndoHistory,
+    replay::ReplayRecorder,
 };
 
 use crate::{
     AppState,
-    bot_logic::set_dir_agent,
+    bot_logic::{Agent, AgentRng, set_dir_agent},
     common::Position,
-    constants::{APPLE_COLOUR, BLOCK_Z, FRAME_MUL, SNAKE_COLOUR},
+    constants::{
+        APPLE_COLOUR, BLOCK_Z, BONUS_COLOUR, FRAME_MUL, SHRINK_COLOUR, SNAKE_COLOUR, TIMED_COLOUR,
+        WALL_COLOUR,
+    },
     endscreen::EndScreenState,
+    menu::{GameMode, MenuOptions},
+    replay_logic::LastReplay,
     setup::WinDimension,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
-pub(crate) struct AppleComponent;
+pub(crate) struct AppleComponent(FoodKind);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
-pub(crate) struct SnakeHeadComponent;
+pub(crate) struct WallComponent;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
-pub(crate) struct SnakeTailComponent;
+pub(crate) struct SnakeComponent;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component, Default)]
-pub(crate) struct NextEl(Option<Entity>);
-
-#[derive(Debug, Clone, Copy, Resource)]
+#[derive(Debug, Clone, Resource)]
 pub(crate) struct GameState(pub(crate) GameAPI);
 
+/// Steps a practice game can rewind
+const PRACTICE_UNDO_DEPTH: usize = 50;
+
+/// Practice game state, a fatal move is rewound and the game paused until the next key
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct Practice {
+    history: UndoHistory,
+    paused: bool,
+}
+
+/// Moves of the game in progress
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct ReplayRecording(pub(crate) ReplayRecorder);
+
+/// Seconds the game in progress has been running, pauses left out
+#[derive(Debug, Clone, Copy, Default, Resource)]
+pub(crate) struct PlayTime(pub(crate) f32);
+
+/// Last game as it ended, kept for the end screen
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct FinishedGame {
+    pub(crate) game: GameAPI,
+    pub(crate) result: StepResult,
+    pub(crate) key: ScoreKey,
+    pub(crate) secs: f32,
+    /// Name of the model that played, in AI games
+    pub(crate) agent: Option<String>,
+    /// Average confidence of the model in its moves, in AI games
+    pub(crate) confidence: Option<f32>,
+    /// The other snakes, in arena and Human vs AI games
+    pub(crate) rivals: Option<Rivals>,
+}
+
+/// How the other snakes of an arena game did
+#[derive(Debug, Clone)]
+pub(crate) struct Rivals {
+    pub(crate) scores: Vec<u128>,
+    /// Model driving the other snake, in Human vs AI games
+    pub(crate) agent: Option<String>,
+    pub(crate) confidence: Option<f32>,
+}
+
+/// What a game needs to hand over to the end screen
+#[derive(SystemParam)]
+pub(crate) struct GameEnd<'w> {
+    pub(crate) next_state: ResMut<'w, NextState<AppState>>,
+    pub(crate) next_state_sub: ResMut<'w, NextState<EndScreenState>>,
+    pub(crate) options: Res<'w, MenuOptions>,
+    pub(crate) agent: Option<ResMut<'w, Agent>>,
+}
+
+impl GameEnd<'_> {
+    /// Keeps the game and its replay for the end screen and switches to it
+    fn finish(
+        &mut self,
+        commands: &mut Commands,
+        game: &GameAPI,
+        result: StepResult,
+        recording: &ReplayRecorder,
+        secs: f32,
+    ) {
+        commands.insert_resource(LastReplay(recording.replay().clone()));
+        commands.insert_resource(FinishedGame {
+            game: game.clone(),
+            result,
+            key: self.options.score_key(),
+            secs,
+            agent: self.agent.as_ref().map(|agent| agent.name.clone()),
+            confidence: self
+                .agent
+                .as_ref()
+                .and_then(|agent| agent.mean_confidence()),
+            rivals: None,
+        });
+        self.next_state.set(AppState::EndScreen);
+        let next_sub = if matches!(result, StepResult::Win { .. }) {
+            EndScreenState::Win
+        } else {
+            EndScreenState::Lose
+        };
+        self.next_state_sub.set(next_sub)
+    }
+}
+
 pub struct GamePlugin;
 
 #[derive(Clone, PartialEq, Eq, Resource, Default)]
@@ -46,11 +136,24 @@ impl Plugin for GamePlugin {
                 OnEnter(AppState::Game),
                 game_setup.after(crate::setup::setup),
             )
-            .add_systems(Update, set_keyboard_dir.run_if(in_state(AppState::Game)))
+            .add_systems(
+                Update,
+                set_keyboard_dir
+                    .run_if(in_state(AppState::Game))
+                    .run_if(not(resource_exists::<Agent>)),
+            )
+            .add_systems(
+                Update,
+                practice_controls
+                    .after(set_keyboard_dir)
+                    .run_if(in_state(AppState::Game))
+                    .run_if(resource_exists::<Practice>),
+            )
             .add_systems(
                 FixedUpdate,
-                // (set_dir_agent, step_snake).run_if(in_state(AppState::Game)),
-                (step_snake).run_if(in_state(AppState::Game)),
+                (set_dir_agent.run_if(resource_exists::<Agent>), step_snake)
+                    .chain()
+                    .run_if(in_state(AppState::Game)),
             )
             .add_systems(
                 FixedUpdate,
@@ -64,109 +167,157 @@ impl Plugin for GamePlugin {
 
 fn cleanup_game(mut commands: Commands) {
     commands.remove_resource::<GameState>();
+    commands.remove_resource::<ReplayRecording>();
     commands.remove_resource::<ShaderResourceSnake>();
+    commands.remove_resource::<Practice>();
+    commands.remove_resource::<PlayTime>();
+    commands.remove_resource::<AgentRng>();
 }
 
 pub fn step_snake(
+    mut commands: Commands,
     mut snake_state: ResMut<GameState>,
-    mut rng: Single<&mut ChaCha8Rng, With<GlobalRng>>,
-    mut next_state: ResMut<NextState<AppState>>,
-    mut next_state_sub: ResMut<NextState<EndScreenState>>,
+    mut recording: ResMut<ReplayRecording>,
+    mut practice: Option<ResMut<Practice>>,
     mut speed: ResMut<Time<Fixed>>,
+    mut play_time: ResMut<PlayTime>,
+    mut end: GameEnd,
 ) {
-    let s = snake_state
-        .0
-        .next(&mut SmallRng::seed_from_u64(rng.next_u64()))
-        .unwrap();
+    if let Some(practice) = practice.as_deref_mut() {
+        if practice.paused {
+            return;
+        }
+        practice.history.push(&snake_state.0);
+    }
+    let s = snake_state.0.next().unwrap();
+    if let Some(practice) = practice.as_deref_mut()
+        && matches!(s, StepResult::Lost { .. })
+    {
+        practice.history.undo(&mut snake_state.0);
+        practice.paused = true;
+        return;
+    }
+    recording.0.record(&snake_state.0, s);
+    play_time.0 += speed.timestep().as_secs_f32();
     speed.set_timestep(
-        Duration::try_from_secs_f32(snake_state.0.mode.to_time_speed())
-            .expect("Should be valid time"),
+        Duration::try_from_secs_f32(snake_state.0.tick_secs()).expect("Should be valid time"),
     );
-    if s != StepResult::Base {
-        next_state.set(AppState::EndScreen);
-        let next_sub = if matches!(s, StepResult::Win { .. }) {
-            EndScreenState::Win
-        } else {
-            EndScreenState::Lose
-        };
-        next_state_sub.set(next_sub)
+    if s.is_terminal() {
+        end.finish(&mut commands, &snake_state.0, s, &recording.0, play_time.0);
     }
 }
 
-fn ui_snake(
+pub(crate) fn ui_snake(
     snake_state: Res<GameState>,
+    app_state: Res<State<AppState>>,
     win_dim: Res<WinDimension>,
     sdf_res: Res<ShaderResourceSnake>,
-    mut query_head: Query<
-        (&mut NextEl, Entity),
-        (With<SnakeHeadComponent>, Without<SnakeTailComponent>),
-    >,
-    mut query_pos: Query<
-        (&mut NextEl, Entity, &Position),
-        Or<(
-            (With<SnakeTailComponent>, Without<SnakeHeadComponent>),
-            (With<SnakeTailComponent>, With<SnakeHeadComponent>),
-        )>,
-    >,
+    query_pos: Query<(&Position, Entity), With<SnakeComponent>>,
     mut commands: Commands,
 ) {
-    {
-        let mut qlens = query_pos
-                .transmute_lens_filtered::<(&mut NextEl, Entity),
-                    (With<SnakeTailComponent>, With<SnakeHeadComponent>)>();
-        let mut query = qlens.query();
-        let x = query.single_mut().ok();
-        let Some(mut head) = query_head.single_mut().ok().or(x) else {
-            panic!("Head mising");
-        };
-
-        commands.entity(head.1).remove::<SnakeHeadComponent>();
-        let ent = commands
-            .spawn(draw_cell(
-                snake_state.0.snake.head,
-                *win_dim,
-                sdf_res.0.clone(),
-                SNAKE_COLOUR.into(),
-            ))
-            .insert((NextEl(None), SnakeHeadComponent))
-            .id();
-        head.0.0 = Some(ent);
+    let cells = snake_state.0.snake.cells();
+    for (pos, ent) in query_pos.iter() {
+        if !cells.contains(&pos.0) {
+            commands.entity(ent).despawn();
+        }
     }
-
-    let (tail_next, tail_ent, tail_pos) = query_pos.iter().next().expect("Tail to exist");
-    if tail_pos.0 != snake_state.0.snake.tail {
-        // If food not eaten
-        if let Some(next_ent) = tail_next.0 {
-            commands.entity(next_ent).insert(SnakeTailComponent);
-            commands.entity(tail_ent).despawn();
-        } else {
-            error!("Tail next not assigned")
+    for cell in cells {
+        if !query_pos.iter().any(|(pos, _)| pos.0 == cell) {
+            commands
+                .spawn(draw_cell(
+                    cell,
+                    *win_dim,
+                    sdf_res.0.clone(),
+                    SNAKE_COLOUR.into(),
+                    *app_state.get(),
+                ))
+                .insert(SnakeComponent);
         }
     }
 }
 
-fn ui_apple(
+pub(crate) fn ui_apple(
     snake_state: Res<GameState>,
+    app_state: Res<State<AppState>>,
     win_dim: Res<WinDimension>,
     sdf_res: Res<ShaderResourceSnake>,
-    query_pos: Single<(&Position, Entity), With<AppleComponent>>,
+    query_pos: Query<(&Position, &AppleComponent, Entity)>,
     mut commands: Commands,
 ) {
-    let (apple_pos, apple_ent) = *query_pos;
-    if apple_pos.0 != snake_state.0.apples {
-        commands.entity(apple_ent).despawn();
-        commands
-            .spawn(draw_cell(
-                snake_state.0.apples,
-                *win_dim,
-                sdf_res.0.clone(),
-                APPLE_COLOUR.into(),
-            ))
-            .insert(AppleComponent);
+    let apples = &snake_state.0.apples;
+    for (apple_pos, apple, apple_ent) in query_pos.iter() {
+        if !apples
+            .iter()
+            .any(|f| f.pos == apple_pos.0 && f.kind == apple.0)
+        {
+            commands.entity(apple_ent).despawn();
+        }
+    }
+    for food in apples.iter() {
+        if !query_pos
+            .iter()
+            .any(|(pos, apple, _)| pos.0 == food.pos && apple.0 == food.kind)
+        {
+            commands
+                .spawn(draw_cell(
+                    food.pos,
+                    *win_dim,
+                    sdf_res.0.clone(),
+                    food_colour(food.kind),
+                    *app_state.get(),
+                ))
+                .insert(AppleComponent(food.kind));
+        }
     }
 }
 
+pub(crate) fn food_colour(kind: FoodKind) -> Color {
+    match kind {
+        FoodKind::Apple => APPLE_COLOUR,
+        FoodKind::Bonus => BONUS_COLOUR,
+        FoodKind::Shrink => SHRINK_COLOUR,
+        FoodKind::Timed => TIMED_COLOUR,
+    }
+    .into()
+}
+
 fn set_keyboard_dir(mut res: ResMut<GameState>, key: Res<ButtonInput<KeyCode>>) {
+    if let Some(dir) = keyboard_dir(&key) {
+        res.0.update_direction(dir);
+    }
+}
+
+/// Backspace or Z rewinds a step, any direction resumes the game and Escape ends
+/// it, since a practice game is never lost
+fn practice_controls(
+    mut commands: Commands,
+    mut practice: ResMut<Practice>,
+    mut game_state: ResMut<GameState>,
+    mut recording: ResMut<ReplayRecording>,
+    key: Res<ButtonInput<KeyCode>>,
+    play_time: Res<PlayTime>,
+    mut end: GameEnd,
+) {
+    if key.just_pressed(KeyCode::Escape) {
+        let game = &game_state.0;
+        let result = StepResult::Lost {
+            num_steps: game.steps as usize,
+            number_of_fruits: game.num_of_apples as usize,
+            snake_size: game.snake.size,
+            level_reached: game.mode,
+        };
+        end.finish(&mut commands, game, result, &recording.0, play_time.0);
+    } else if key.any_just_pressed([KeyCode::Backspace, KeyCode::KeyZ]) {
+        if practice.history.undo(&mut game_state.0) {
+            recording.0.rewind(1);
+        }
+        practice.paused = true;
+    } else if keyboard_dir(&key).is_some() {
+        practice.paused = false;
+    }
+}
+
+pub(crate) fn keyboard_dir(key: &ButtonInput<KeyCode>) -> Option<Direction> {
     let dir = if key.any_just_pressed([KeyCode::ArrowLeft, KeyCode::KeyA]) {
         Direction::Left
     } else if key.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
@@ -176,16 +327,17 @@ fn set_keyboard_dir(mut res: ResMut<GameState>, key: Res<ButtonInput<KeyCode>>)
     } else if key.any_just_pressed([KeyCode::ArrowRight, KeyCode::KeyS]) {
         Direction::Right
     } else {
-        return;
+        return None;
     };
-    res.0.update_direction(dir);
+    Some(dir)
 }
 
-fn draw_cell(
+pub(crate) fn draw_cell(
     coord: Coord,
     win_dims: WinDimension,
     sdf_handle: Handle<Shader>,
     color: Color,
+    state: AppState,
 ) -> impl Bundle {
     let trans = win_dims.from_coord_to_pos(coord);
     let frame_dim = {
@@ -201,7 +353,7 @@ fn draw_cell(
             ..default()
         },
         Position(coord),
-        DespawnOnExit(AppState::Game),
+        DespawnOnExit(state),
         Transform::from_xyz(trans.x, trans.y, BLOCK_Z),
     )
 }
@@ -209,29 +361,69 @@ fn draw_cell(
 pub(crate) fn game_setup(
     mut commands: Commands,
     mut shaders: ResMut<Assets<Shader>>,
-    win_dim: Res<WinDimension>,
+    mut win_dim: ResMut<WinDimension>,
+    options: Res<MenuOptions>,
+    mut rng: Single<&mut ChaCha8Rng, With<GlobalRng>>,
 ) {
-    let game_api = GameAPI::new(None, None);
-    commands.insert_resource(GameState(game_api));
+    let seed = options.seed().unwrap_or_else(|| rng.next_u64());
+    info!("Starting game with seed {seed}");
+    let game_api = options.builder(seed).build(None);
+    win_dim.set_board(game_api.board());
 
     let sdf = shaders.add_sdf_expr(win_dim.generate_sdf_string());
-    let position_head = game_api.snake.head;
-    let position_apple = game_api.apples;
-    commands.insert_resource(ShaderResourceSnake(sdf.clone()));
-    commands
-        .spawn(draw_cell(
-            position_head,
-            *win_dim,
-            sdf.clone(),
-            SNAKE_COLOUR.into(),
-        ))
-        .insert((SnakeHeadComponent, SnakeTailComponent, NextEl(None)));
-    commands
-        .spawn(draw_cell(
-            position_apple,
-            *win_dim,
-            sdf.clone(),
-            APPLE_COLOUR.into(),
-        ))
-        .insert(AppleComponent);
+    spawn_board(&mut commands, &game_api, *win_dim, &sdf, AppState::Game);
+    commands.insert_resource(ReplayRecording(ReplayRecorder::new(&game_api)));
+    if options.mode == GameMode::Practice {
+        commands.insert_resource(Practice {
+            history: UndoHistory::new(PRACTICE_UNDO_DEPTH),
+            paused: false,
+        });
+    }
+    commands.insert_resource(GameState(game_api));
+    commands.insert_resource(ShaderResourceSnake(sdf));
+    commands.insert_resource(PlayTime::default());
+    commands.insert_resource(AgentRng(SmallRng::seed_from_u64(seed.wrapping_add(1))));
+}
+
+/// Draws the snake, food and walls of a game that was not rendered yet
+pub(crate) fn spawn_board(
+    commands: &mut Commands,
+    game_api: &GameAPI,
+    win_dim: WinDimension,
+    sdf: &Handle<Shader>,
+    state: AppState,
+) {
+    for cell in game_api.snake.cells() {
+        commands
+            .spawn(draw_cell(
+                cell,
+                win_dim,
+                sdf.clone(),
+                SNAKE_COLOUR.into(),
+                state,
+            ))
+            .insert(SnakeComponent);
+    }
+    for food in game_api.apples.iter() {
+        commands
+            .spawn(draw_cell(
+                food.pos,
+                win_dim,
+                sdf.clone(),
+                food_colour(food.kind),
+                state,
+            ))
+            .insert(AppleComponent(food.kind));
+    }
+    for wall in game_api.level.walls() {
+        commands
+            .spawn(draw_cell(
+                wall,
+                win_dim,
+                sdf.clone(),
+                WALL_COLOUR.into(),
+                state,
+            ))
+            .insert(WallComponent);
+    }
 }
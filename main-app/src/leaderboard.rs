@@ -0,0 +1,134 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use snake_api_lib::highscore::{HighScores, ScoreEntry, ScoreKey};
+
+use crate::{
+    AppState,
+    common::{draw_button, label_bundle},
+    constants::TEXT_COLOR_TITLE,
+    menu::MenuOptions,
+};
+
+pub(crate) struct LeaderboardPlugin;
+
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(HighScoreBook::load())
+            .add_systems(OnEnter(AppState::Leaderboard), draw_ui)
+            .add_systems(Update, back_to_menu.run_if(in_state(AppState::Leaderboard)));
+    }
+}
+
+/// High scores of every player, stored under the user data dir
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct HighScoreBook {
+    pub(crate) scores: HighScores,
+    path: PathBuf,
+}
+
+impl HighScoreBook {
+    fn load() -> Self {
+        let path = dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("snake-ai")
+            .join("highscores.json");
+        let scores = HighScores::load(&path).unwrap_or_else(|e| {
+            warn!("Cannot read the high scores at {}: {e}", path.display());
+            HighScores::default()
+        });
+        Self { scores, path }
+    }
+
+    /// Adds `entry` and saves every table, returning its rank from 0 if it was kept
+    pub(crate) fn record(&mut self, key: ScoreKey, entry: ScoreEntry) -> Option<usize> {
+        let rank = self.scores.insert(key, entry);
+        if let Err(e) = self.scores.save(&self.path) {
+            warn!(
+                "Cannot save the high scores to {}: {e}",
+                self.path.display()
+            );
+        }
+        rank
+    }
+}
+
+/// Table of the options selected in the menu
+fn draw_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    book: Res<HighScoreBook>,
+    options: Res<MenuOptions>,
+) {
+    let key = options.score_key();
+    let entries = book.scores.table(&key);
+    commands
+        .spawn((
+            DespawnOnExit(AppState::Leaderboard),
+            Node {
+                width: Val::Percent(100.),
+                height: Val::Percent(100.),
+                flex_direction: FlexDirection::Column,
+                justify_content: JustifyContent::SpaceEvenly,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+        ))
+        .with_children(|builder| {
+            builder.spawn(label_bundle(
+                "High scores".to_owned(),
+                &asset_server,
+                None,
+                Some(100.),
+            ));
+            builder.spawn(label_bundle(
+                format!(
+                    "{} - {}x{} - {}",
+                    key.mode, key.board.rows, key.board.cols, key.speed
+                ),
+                &asset_server,
+                Some(TEXT_COLOR_TITLE.into()),
+                Some(30.),
+            ));
+
+            let rows = if entries.is_empty() {
+                "No scores yet".to_owned()
+            } else {
+                entries
+                    .iter()
+                    .enumerate()
+                    .map(|(rank, e)| {
+                        format!(
+                            "{:>2}. {:<16} {:>7}   {} apples, {} steps",
+                            rank + 1,
+                            e.name,
+                            e.score,
+                            e.apples,
+                            e.steps
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            };
+            builder.spawn(label_bundle(
+                rows,
+                &asset_server,
+                Some(Color::srgb(0.9, 0.9, 0.9)),
+                Some(25.),
+            ));
+
+            builder
+                .spawn(draw_button("Back to menu".to_owned(), &asset_server))
+                .observe(on_click);
+        });
+}
+
+fn on_click(_: On<Pointer<Click>>, mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::Menu);
+}
+
+fn back_to_menu(key: Res<ButtonInput<KeyCode>>, mut next_state: ResMut<NextState<AppState>>) {
+    if key.just_pressed(KeyCode::Escape) {
+        next_state.set(AppState::Menu);
+    }
+}
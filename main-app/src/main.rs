@@ -3,16 +3,20 @@ use bevy_rand::prelude::*;
 use bevy_smud::prelude::*;
 
 use crate::{
-    bot_logic::BotAgent, endscreen::EndScreenPlugin, game_logic::GamePlugin, menu::MenuPlugin,
-    setup::CameraPlugin,
+    arena_logic::ArenaPlugin, bot_logic::BotAgent, endscreen::EndScreenPlugin,
+    game_logic::GamePlugin, leaderboard::LeaderboardPlugin, menu::MenuPlugin,
+    replay_logic::ReplayPlugin, setup::CameraPlugin,
 };
 
+pub(crate) mod arena_logic;
 pub(crate) mod bot_logic;
 pub(crate) mod common;
 pub(crate) mod constants;
 pub(crate) mod endscreen;
 pub(crate) mod game_logic;
+pub(crate) mod leaderboard;
 pub(crate) mod menu;
+pub(crate) mod replay_logic;
 pub(crate) mod setup;
 pub(crate) mod ui_handling;
 
@@ -21,7 +25,10 @@ pub(crate) enum AppState {
     #[default]
     Menu,
     Game,
+    Arena,
     EndScreen,
+    Replay,
+    Leaderboard,
 }
 
 fn main() {
@@ -30,7 +37,7 @@ fn main() {
         .add_plugins(EntropyPlugin::<ChaCha8Rng>::new())
         .init_state::<AppState>()
         .add_plugins((CameraPlugin, MenuPlugin, EndScreenPlugin, BotAgent))
-        .add_plugins(GamePlugin)
+        .add_plugins((GamePlugin, ArenaPlugin, ReplayPlugin, LeaderboardPlugin))
         .add_plugins(ui_handling::UiPlugin)
         .run();
 }
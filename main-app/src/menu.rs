@@ -1,24 +1,196 @@
+use std::fmt::Display;
+
 use bevy::prelude::*;
+use snake_api_lib::{
+    api::{GameAPIBuilder, GameOptions, Speed},
+    common::{BoardSize, Topology},
+    highscore::ScoreKey,
+};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
 use crate::{
     AppState,
-    common::{draw_button, label_bundle},
+    bot_logic::{AgentSource, load_agent},
+    common::{draw_button, draw_button_sized, label_bundle},
+    constants::TEXT_COLOR_TITLE,
+    replay_logic::LastReplay,
 };
 
 pub struct MenuPlugin;
 
 const STATE: AppState = AppState::Menu;
 
+/// Boards the menu cycles through, rows by columns
+const BOARD_SIZES: [(usize, usize); 4] = [(8, 8), (12, 12), (16, 16), (24, 24)];
+
+/// Digits of the largest `u64`
+const MAX_SEED_DIGITS: usize = 20;
+
 impl Plugin for MenuPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(OnEnter(AppState::Menu), draw_ui.run_if(in_state(STATE)))
-            .add_systems(Update, draw_ui.run_if(in_state(STATE)));
+        let (options, message) = MenuOptions::from_args();
+        if let Some(message) = message {
+            app.insert_resource(message);
+        }
+        app.insert_resource(options)
+            .add_systems(OnEnter(STATE), draw_ui)
+            .add_systems(
+                Update,
+                (edit_seed, (clear_ui, draw_ui).chain().run_if(menu_changed))
+                    .chain()
+                    .run_if(in_state(STATE)),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, EnumIter)]
+pub(crate) enum GameMode {
+    #[default]
+    Human,
+    /// Fatal moves are rewound instead of ending the game
+    Practice,
+    /// A trained model plays alone
+    Ai,
+    /// The player against a trained model on the same board
+    HumanVsAi,
+    /// The player against greedy bots
+    Arena,
+    /// The last finished game
+    Replay,
+}
+
+impl GameMode {
+    fn uses_agent(self) -> bool {
+        matches!(self, Self::Ai | Self::HumanVsAi)
+    }
+}
+
+impl Display for GameMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Human => "Human",
+            Self::Practice => "Practice",
+            Self::Ai => "Watch AI",
+            Self::HumanVsAi => "Human vs AI",
+            Self::Arena => "Arena",
+            Self::Replay => "Replay",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// Choices made in the menu, the next game is set up from them
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct MenuOptions {
+    pub(crate) mode: GameMode,
+    pub(crate) speed: Speed,
+    pub(crate) board: BoardSize,
+    pub(crate) wrap: bool,
+    /// Digits typed so far, a fresh seed is drawn per game when empty
+    pub(crate) seed: String,
+    editing_seed: bool,
+}
+
+impl MenuOptions {
+    /// Defaults, with the seed given on the command line with `--seed`. A seed that
+    /// is not a number comes back as a message for the menu, games then draw their own.
+    pub(crate) fn from_args() -> (Self, Option<MenuMessage>) {
+        let arg = std::env::args().skip_while(|arg| arg != "--seed").nth(1);
+        let (seed, message) = match arg.map(|seed| (seed.parse::<u64>(), seed)) {
+            Some((Ok(seed), _)) => (seed.to_string(), None),
+            Some((Err(e), seed)) => {
+                warn!("Ignoring --seed {seed}: {e}");
+                let message = format!("--seed {seed} is not an unsigned integer, seeds are random");
+                (String::new(), Some(MenuMessage(message)))
+            }
+            None => (String::new(), None),
+        };
+        let options = Self {
+            mode: GameMode::default(),
+            speed: Speed::default(),
+            board: BoardSize::default(),
+            wrap: false,
+            seed,
+            editing_seed: false,
+        };
+        (options, message)
+    }
+
+    pub(crate) fn seed(&self) -> Option<u64> {
+        self.seed.parse().ok()
+    }
+
+    pub(crate) fn game_options(&self) -> GameOptions {
+        let topology = if self.wrap {
+            Topology::Toroidal
+        } else {
+            Topology::Bounded
+        };
+        GameOptions::new(self.board)
+            .with_topology(topology)
+            .with_start_speed(self.speed)
+    }
+
+    /// High score table of games played with these options
+    pub(crate) fn score_key(&self) -> ScoreKey {
+        ScoreKey {
+            board: self.board,
+            mode: self.mode.to_string(),
+            speed: self.speed,
+        }
+    }
+
+    pub(crate) fn builder(&self, seed: u64) -> GameAPIBuilder {
+        GameAPIBuilder::default()
+            .with_selected_game_options(self.game_options())
+            .with_seed(seed)
     }
 }
 
-fn draw_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
+/// Why the last game could not start or an option was ignored, shown in the menu
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct MenuMessage(pub(crate) String);
+
+#[derive(Debug, Clone, Copy, Component)]
+struct MenuRoot;
+
+/// Value after `value`, back to the first one after the last
+fn cycle<T: IntoEnumIterator + PartialEq>(value: T) -> T {
+    let mut all = T::iter().collect::<Vec<_>>();
+    let indx = all.iter().position(|v| *v == value).unwrap_or(0);
+    all.swap_remove((indx + 1) % all.len())
+}
+
+fn menu_changed(
+    options: Res<MenuOptions>,
+    source: Res<AgentSource>,
+    message: Option<Res<MenuMessage>>,
+) -> bool {
+    options.is_changed() || source.is_changed() || message.is_some_and(|m| m.is_changed())
+}
+
+fn clear_ui(mut commands: Commands, roots: Query<Entity, With<MenuRoot>>) {
+    for root in roots.iter() {
+        commands.entity(root).despawn();
+    }
+}
+
+fn draw_ui(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    options: Res<MenuOptions>,
+    source: Res<AgentSource>,
+    message: Option<Res<MenuMessage>>,
+) {
+    let seed = match (options.seed.as_str(), options.editing_seed) {
+        (seed, true) => format!("Seed: {seed}_"),
+        ("", false) => "Seed: random".to_owned(),
+        (seed, false) => format!("Seed: {seed}"),
+    };
     commands
         .spawn((
+            MenuRoot,
             DespawnOnExit(AppState::Menu),
             Node {
                 width: Val::Percent(100.),
@@ -34,15 +206,165 @@ fn draw_ui(mut commands: Commands, asset_server: Res<AssetServer>) {
                 "Snake AI".to_owned(),
                 &asset_server,
                 None,
-                None,
+                Some(100.),
             ));
 
+            let option_button = |text| draw_button_sized(text, 400., &asset_server);
+            builder
+                .spawn(option_button(format!("Mode: {}", options.mode)))
+                .observe(on_click_mode);
+            builder
+                .spawn(option_button(format!("Difficulty: {}", options.speed)))
+                .observe(on_click_speed);
+            builder
+                .spawn(option_button(format!(
+                    "Board: {}x{}",
+                    options.board.rows, options.board.cols
+                )))
+                .observe(on_click_board);
+            builder
+                .spawn(option_button(format!(
+                    "Wrap around: {}",
+                    if options.wrap { "On" } else { "Off" }
+                )))
+                .observe(on_click_wrap);
+            builder.spawn(option_button(seed)).observe(on_click_seed);
+
             builder
                 .spawn(draw_button("Start Game!".to_owned(), &asset_server))
-                .observe(on_click);
+                .observe(on_click_start);
+
+            builder
+                .spawn(draw_button("High scores".to_owned(), &asset_server))
+                .observe(on_click_leaderboard);
+
+            if options.mode.uses_agent() {
+                let model = source.model.as_ref().map_or_else(
+                    || "Drop a model file to play against it".to_owned(),
+                    |path| format!("Model: {}", path.display()),
+                );
+                builder.spawn(label_bundle(
+                    model,
+                    &asset_server,
+                    Some(TEXT_COLOR_TITLE.into()),
+                    Some(20.),
+                ));
+            }
+
+            if let Some(message) = message {
+                builder.spawn(label_bundle(
+                    message.0.clone(),
+                    &asset_server,
+                    Some(Color::srgb(0.9, 0.3, 0.3)),
+                    Some(20.),
+                ));
+            }
         });
 }
 
-fn on_click(_: On<Pointer<Click>>, mut next_state: ResMut<NextState<AppState>>) {
-    next_state.set(AppState::Game);
+fn on_click_leaderboard(_: On<Pointer<Click>>, mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::Leaderboard);
+}
+
+fn on_click_mode(_: On<Pointer<Click>>, mut options: ResMut<MenuOptions>) {
+    options.mode = cycle(options.mode);
+}
+
+fn on_click_speed(_: On<Pointer<Click>>, mut options: ResMut<MenuOptions>) {
+    options.speed = cycle(options.speed);
+}
+
+fn on_click_board(_: On<Pointer<Click>>, mut options: ResMut<MenuOptions>) {
+    let indx = BOARD_SIZES
+        .iter()
+        .position(|(rows, cols)| (options.board.rows, options.board.cols) == (*rows, *cols))
+        .map_or(0, |indx| (indx + 1) % BOARD_SIZES.len());
+    let (rows, cols) = BOARD_SIZES[indx];
+    options.board = BoardSize::new(rows, cols).expect("Menu boards should be large enough");
+}
+
+fn on_click_wrap(_: On<Pointer<Click>>, mut options: ResMut<MenuOptions>) {
+    options.wrap = !options.wrap;
+}
+
+fn on_click_seed(_: On<Pointer<Click>>, mut options: ResMut<MenuOptions>) {
+    options.editing_seed = !options.editing_seed;
+}
+
+/// Digits type the seed, Backspace erases one and Enter or Escape are done
+fn edit_seed(mut options: ResMut<MenuOptions>, key: Res<ButtonInput<KeyCode>>) {
+    if !options.editing_seed {
+        return;
+    }
+    if key.any_just_pressed([KeyCode::Enter, KeyCode::NumpadEnter, KeyCode::Escape]) {
+        options.editing_seed = false;
+    } else if key.just_pressed(KeyCode::Backspace) {
+        options.seed.pop();
+    }
+    for digit in key.get_just_pressed().filter_map(key_digit) {
+        let mut seed = format!("{}{digit}", options.seed);
+        if seed.len() > MAX_SEED_DIGITS || seed.parse::<u64>().is_err() {
+            continue;
+        }
+        if seed.starts_with('0') {
+            seed = digit.to_string();
+        }
+        options.seed = seed;
+    }
+}
+
+fn key_digit(key: &KeyCode) -> Option<char> {
+    let digit = match key {
+        KeyCode::Digit0 | KeyCode::Numpad0 => 0,
+        KeyCode::Digit1 | KeyCode::Numpad1 => 1,
+        KeyCode::Digit2 | KeyCode::Numpad2 => 2,
+        KeyCode::Digit3 | KeyCode::Numpad3 => 3,
+        KeyCode::Digit4 | KeyCode::Numpad4 => 4,
+        KeyCode::Digit5 | KeyCode::Numpad5 => 5,
+        KeyCode::Digit6 | KeyCode::Numpad6 => 6,
+        KeyCode::Digit7 | KeyCode::Numpad7 => 7,
+        KeyCode::Digit8 | KeyCode::Numpad8 => 8,
+        KeyCode::Digit9 | KeyCode::Numpad9 => 9,
+        _ => return None,
+    };
+    char::from_digit(digit, 10)
+}
+
+fn on_click_start(
+    _: On<Pointer<Click>>,
+    mut commands: Commands,
+    options: Res<MenuOptions>,
+    source: Res<AgentSource>,
+    last_replay: Option<Res<LastReplay>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let next = match options.mode {
+        GameMode::Human | GameMode::Practice => AppState::Game,
+        GameMode::Ai | GameMode::HumanVsAi => {
+            match load_agent(&source) {
+                Ok(agent) => {
+                    info!("Playing with {}", agent.name);
+                    commands.insert_resource(agent);
+                }
+                Err(e) => {
+                    warn!("Cannot load the model: {e}");
+                    commands.insert_resource(MenuMessage(e.to_string()));
+                    return;
+                }
+            }
+            if options.mode == GameMode::Ai {
+                AppState::Game
+            } else {
+                AppState::Arena
+            }
+        }
+        GameMode::Arena => AppState::Arena,
+        GameMode::Replay if last_replay.is_none() => {
+            commands.insert_resource(MenuMessage("No finished game to replay yet".to_owned()));
+            return;
+        }
+        GameMode::Replay => AppState::Replay,
+    };
+    commands.remove_resource::<MenuMessage>();
+    next_state.set(next);
 }
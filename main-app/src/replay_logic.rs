@@ -0,0 +1,312 @@
+use bevy::{prelude::*, ui::RelativeCursorPosition};
+use bevy_smud::prelude::*;
+use snake_api_lib::{api::GameAPI, replay::Replay};
+
+use crate::{
+    AppState,
+    common::draw_button,
+    constants::{SNAKE_COLOUR, TEXT_COLOR_TITLE},
+    game_logic::{GameState, ShaderResourceSnake, spawn_board, ui_apple, ui_snake},
+    setup::WinDimension,
+};
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 8.;
+
+/// Recording of the last finished game, kept for the end screen
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct LastReplay(pub(crate) Replay);
+
+/// Every state of the replay and where the playback is
+#[derive(Debug, Clone, Resource)]
+pub(crate) struct ReplayViewer {
+    states: Vec<GameAPI>,
+    cursor: usize,
+    shown: usize,
+    playing: bool,
+    speed: f32,
+    elapsed: f32,
+}
+
+impl ReplayViewer {
+    fn last(&self) -> usize {
+        self.states.len() - 1
+    }
+
+    fn seek(&mut self, cursor: usize) {
+        self.cursor = cursor.min(self.last());
+        self.elapsed = 0.;
+    }
+
+    fn progress(&self) -> f32 {
+        if self.last() == 0 {
+            1.
+        } else {
+            self.cursor as f32 / self.last() as f32
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Component)]
+pub(crate) struct TimelineBar;
+
+#[derive(Debug, Clone, Copy, Component)]
+pub(crate) struct TimelineFill;
+
+#[derive(Debug, Clone, Copy, Component)]
+pub(crate) struct ReplayInfoUi;
+
+pub(crate) struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            OnEnter(AppState::Replay),
+            (replay_setup, replay_ui_setup).after(crate::setup::setup),
+        )
+        .add_systems(
+            Update,
+            (replay_controls, timeline_seek, replay_tick, sync_game_state)
+                .chain()
+                .run_if(in_state(AppState::Replay))
+                .run_if(resource_exists::<ReplayViewer>),
+        )
+        .add_systems(
+            Update,
+            (ui_apple, ui_snake)
+                .after(sync_game_state)
+                .run_if(in_state(AppState::Replay))
+                .run_if(resource_exists_and_changed::<GameState>),
+        )
+        .add_systems(
+            Update,
+            draw_timeline
+                .after(sync_game_state)
+                .run_if(in_state(AppState::Replay))
+                .run_if(resource_exists_and_changed::<ReplayViewer>),
+        )
+        .add_systems(OnExit(AppState::Replay), cleanup_replay);
+    }
+}
+
+fn cleanup_replay(mut commands: Commands) {
+    commands.remove_resource::<ReplayViewer>();
+    commands.remove_resource::<GameState>();
+    commands.remove_resource::<ShaderResourceSnake>();
+}
+
+fn replay_setup(
+    mut commands: Commands,
+    mut shaders: ResMut<Assets<Shader>>,
+    mut win_dim: ResMut<WinDimension>,
+    last_replay: Option<Res<LastReplay>>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let Some(last_replay) = last_replay else {
+        error!("No replay to watch");
+        next_state.set(AppState::Menu);
+        return;
+    };
+    let mut states = vec![];
+    for state in last_replay.0.play() {
+        match state {
+            Ok(state) => states.push(state),
+            Err(e) => {
+                warn!("Replay out of sync, showing what was verified: {e}");
+                break;
+            }
+        }
+    }
+    let Some(first) = states.first().cloned() else {
+        next_state.set(AppState::Menu);
+        return;
+    };
+    win_dim.set_board(first.board());
+    let sdf = shaders.add_sdf_expr(win_dim.generate_sdf_string());
+    spawn_board(&mut commands, &first, *win_dim, &sdf, AppState::Replay);
+    commands.insert_resource(GameState(first));
+    commands.insert_resource(ShaderResourceSnake(sdf));
+    commands.insert_resource(ReplayViewer {
+        states,
+        cursor: 0,
+        shown: 0,
+        playing: true,
+        speed: 1.,
+        elapsed: 0.,
+    });
+}
+
+fn replay_ui_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn((
+            DespawnOnExit(AppState::Replay),
+            Node {
+                position_type: PositionType::Absolute,
+                bottom: Val::Px(10.),
+                left: Val::Percent(10.),
+                width: Val::Percent(80.),
+                flex_direction: FlexDirection::Column,
+                row_gap: Val::Px(5.),
+                ..default()
+            },
+        ))
+        .with_children(|builder| {
+            builder.spawn((
+                Text::new(""),
+                TextFont {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 25.0,
+                    ..default()
+                },
+                TextColor(TEXT_COLOR_TITLE.into()),
+                TextShadow::default(),
+                ReplayInfoUi,
+            ));
+            builder
+                .spawn((
+                    Node {
+                        width: Val::Percent(100.),
+                        height: Val::Px(12.),
+                        ..default()
+                    },
+                    BackgroundColor(Color::srgb(0.15, 0.15, 0.15)),
+                    RelativeCursorPosition::default(),
+                    TimelineBar,
+                ))
+                .with_children(|bar| {
+                    bar.spawn((
+                        Node {
+                            width: Val::Percent(0.),
+                            height: Val::Percent(100.),
+                            ..default()
+                        },
+                        BackgroundColor(SNAKE_COLOUR.into()),
+                        TimelineFill,
+                    ));
+                });
+            builder.spawn((
+                Text::new(
+                    "Space play/pause, Left/Right step, Up/Down speed, Home/End jump, Esc menu",
+                ),
+                TextFont {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 18.0,
+                    ..default()
+                },
+                TextColor(Color::srgb(0.7, 0.7, 0.7)),
+            ));
+        });
+
+    commands
+        .spawn((
+            DespawnOnExit(AppState::Replay),
+            Node {
+                position_type: PositionType::Absolute,
+                top: Val::Px(10.),
+                right: Val::Px(10.),
+                ..default()
+            },
+        ))
+        .with_children(|builder| {
+            builder
+                .spawn(draw_button("Back to menu".to_owned(), &asset_server))
+                .observe(on_click);
+        });
+}
+
+fn on_click(_: On<Pointer<Click>>, mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::Menu);
+}
+
+fn replay_controls(
+    key: Res<ButtonInput<KeyCode>>,
+    mut viewer: ResMut<ReplayViewer>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    if key.just_pressed(KeyCode::Escape) {
+        next_state.set(AppState::Menu);
+    } else if key.just_pressed(KeyCode::Space) {
+        if !viewer.playing && viewer.cursor == viewer.last() {
+            viewer.seek(0);
+        }
+        viewer.playing = !viewer.playing;
+    } else if key.any_just_pressed([KeyCode::ArrowRight, KeyCode::KeyD]) {
+        viewer.playing = false;
+        let cursor = viewer.cursor + 1;
+        viewer.seek(cursor);
+    } else if key.any_just_pressed([KeyCode::ArrowLeft, KeyCode::KeyA]) {
+        viewer.playing = false;
+        let cursor = viewer.cursor.saturating_sub(1);
+        viewer.seek(cursor);
+    } else if key.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW]) {
+        viewer.speed = (viewer.speed * 2.).min(MAX_SPEED);
+    } else if key.any_just_pressed([KeyCode::ArrowDown, KeyCode::KeyS]) {
+        viewer.speed = (viewer.speed / 2.).max(MIN_SPEED);
+    } else if key.just_pressed(KeyCode::Home) {
+        viewer.seek(0);
+    } else if key.just_pressed(KeyCode::End) {
+        let last = viewer.last();
+        viewer.seek(last);
+    }
+}
+
+/// Clicking the timeline jumps to that point of the game
+fn timeline_seek(
+    mouse: Res<ButtonInput<MouseButton>>,
+    bar: Single<&RelativeCursorPosition, With<TimelineBar>>,
+    mut viewer: ResMut<ReplayViewer>,
+) {
+    if !mouse.just_pressed(MouseButton::Left) || !bar.cursor_over() {
+        return;
+    }
+    if let Some(pos) = bar.normalized {
+        // Centered on the node, -0.5 is the left edge
+        let fraction = (pos.x + 0.5).clamp(0., 1.);
+        let cursor = (fraction * viewer.last() as f32).round() as usize;
+        viewer.seek(cursor);
+    }
+}
+
+fn replay_tick(time: Res<Time>, mut viewer: ResMut<ReplayViewer>) {
+    if !viewer.playing {
+        return;
+    }
+    viewer.elapsed += time.delta_secs() * viewer.speed;
+    loop {
+        let step_secs = viewer.states[viewer.cursor].tick_secs();
+        if viewer.cursor == viewer.last() {
+            viewer.playing = false;
+            break;
+        }
+        if viewer.elapsed < step_secs {
+            break;
+        }
+        viewer.elapsed -= step_secs;
+        viewer.cursor += 1;
+    }
+}
+
+fn sync_game_state(mut viewer: ResMut<ReplayViewer>, mut game_state: ResMut<GameState>) {
+    if viewer.shown == viewer.cursor {
+        return;
+    }
+    viewer.shown = viewer.cursor;
+    game_state.0 = viewer.states[viewer.cursor].clone();
+}
+
+fn draw_timeline(
+    viewer: Res<ReplayViewer>,
+    mut fill: Single<&mut Node, With<TimelineFill>>,
+    mut info: Single<&mut Text, With<ReplayInfoUi>>,
+) {
+    fill.width = Val::Percent(viewer.progress() * 100.);
+    let game = &viewer.states[viewer.cursor];
+    info.0 = format!(
+        "Step {}/{}  Score {}  Speed {}x  {}",
+        viewer.cursor,
+        viewer.last(),
+        game.score,
+        viewer.speed,
+        if viewer.playing { "Playing" } else { "Paused" }
+    );
+}
@@ -1,19 +1,29 @@
 use bevy::{prelude::*, window::WindowResized};
 use bevy_smud::prelude::*;
-use snake_api_lib::common::{GRID_X, GRID_Y};
+use snake_api_lib::common::BoardSize;
 
-use crate::{AppState, common::*, game_logic::ShaderResourceSnake};
+use crate::{
+    AppState, arena_logic::ShaderResourceArena, common::*, game_logic::ShaderResourceSnake,
+};
 
 #[derive(Debug, Clone, Copy, Resource, PartialEq)]
-pub struct WinDimension(f32, f32);
+pub struct WinDimension(f32, f32, BoardSize);
 
 impl WinDimension {
     pub fn window_dims(self) -> (f32, f32) {
         (self.0, self.1)
     }
 
+    pub fn board(self) -> BoardSize {
+        self.2
+    }
+
+    pub fn set_board(&mut self, board: BoardSize) {
+        self.2 = board;
+    }
+
     pub fn cell_dims(self) -> (f32, f32) {
-        (self.0 / GRID_Y as f32, self.1 / GRID_X as f32)
+        (self.0 / self.2.cols as f32, self.1 / self.2.rows as f32)
     }
 
     pub fn generate_sdf_string(self) -> String {
@@ -24,7 +34,11 @@ impl WinDimension {
 
 pub(crate) fn setup(mut commands: Commands, win: Single<&Window>) {
     commands.spawn(Camera2d);
-    commands.insert_resource(WinDimension(win.width(), win.height()));
+    commands.insert_resource(WinDimension(
+        win.width(),
+        win.height(),
+        BoardSize::default(),
+    ));
 }
 
 fn update_win(
@@ -32,19 +46,21 @@ fn update_win(
     mut event_reader: MessageReader<WindowResized>,
     mut win_dims: ResMut<WinDimension>,
     mut snake_shader: Option<ResMut<ShaderResourceSnake>>,
+    mut arena_shader: Option<ResMut<ShaderResourceArena>>,
     mut shaders: ResMut<Assets<Shader>>,
     mut query_shapes: Query<(&mut SmudShape, &mut Transform, &Position)>,
 ) {
     for WindowResized { width, height, .. } in event_reader.read() {
         win_dims.0 = *width;
         win_dims.1 = *height;
-        let snake_shader = match snake_shader {
-            Some(ref mut s) if *app_state == AppState::Game => s,
+        let shader = match (app_state.get(), &mut snake_shader, &mut arena_shader) {
+            (AppState::Game | AppState::Replay, Some(s), _) => &mut s.0,
+            (AppState::Arena, _, Some(s)) => &mut s.0,
             _ => continue,
         };
 
         let new_handle = shaders.add_sdf_expr(win_dims.generate_sdf_string());
-        snake_shader.0 = new_handle.clone();
+        *shader = new_handle.clone();
         for (mut shape, mut trans, pos) in query_shapes.iter_mut() {
             shape.sdf = new_handle.clone();
             let (w, h) = win_dims.cell_dims();
@@ -58,10 +74,11 @@ fn update_win(
 
 fn debug_grid(mut gizmo: Gizmos, win_dims: Res<WinDimension>) {
     let (cell_w, cell_h) = win_dims.cell_dims();
+    let board = win_dims.board();
     gizmo
         .grid_2d(
             Isometry2d::IDENTITY,
-            UVec2::new(GRID_Y as u32, GRID_X as u32),
+            UVec2::new(board.cols as u32, board.rows as u32),
             Vec2::new(cell_w, cell_h),
             // Dark gray
             LinearRgba::gray(0.05),
@@ -74,7 +91,14 @@ pub struct CameraPlugin;
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup)
-            .add_systems(Update, debug_grid.run_if(in_state(AppState::Game)))
+            .add_systems(
+                Update,
+                debug_grid.run_if(
+                    in_state(AppState::Game)
+                        .or(in_state(AppState::Replay))
+                        .or(in_state(AppState::Arena)),
+                ),
+            )
             .add_systems(Update, update_win);
     }
 }
@@ -1,6 +1,14 @@
 use bevy::prelude::*;
+use snake_api_lib::api::Speed;
 
-use crate::{AppState, constants::TEXT_COLOR, game_logic::GameState};
+use crate::{
+    AppState,
+    constants::{TEXT_COLOR, TEXT_COLOR_TITLE},
+    game_logic::GameState,
+};
+
+/// Seconds the level up banner takes to fade out
+const LEVEL_UP_SECS: f32 = 1.5;
 
 pub(crate) struct UiPlugin;
 
@@ -9,10 +17,17 @@ impl Plugin for UiPlugin {
         app.add_systems(OnEnter(AppState::Game), ui_setup)
             .add_systems(
                 Update,
-                (draw_direction, draw_score, draw_difficulty, draw_time)
+                (
+                    draw_direction,
+                    draw_score,
+                    draw_difficulty,
+                    draw_time,
+                    draw_seed,
+                )
                     .run_if(resource_exists_and_changed::<GameState>)
                     .run_if(in_state(AppState::Game)),
-            );
+            )
+            .add_systems(Update, fade_level_up.run_if(in_state(AppState::Game)));
     }
 }
 
@@ -28,6 +43,13 @@ pub(crate) struct TimeUi;
 #[derive(Debug, Clone, Copy, Component)]
 pub(crate) struct DiffUi;
 
+#[derive(Debug, Clone, Copy, Component)]
+pub(crate) struct SeedUi;
+
+/// Banner shown when the game speeds up, fading out as its timer runs
+#[derive(Debug, Clone, Component)]
+pub(crate) struct LevelUpUi(Timer);
+
 fn ui_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     let mut c: Color = TEXT_COLOR.into();
     c.set_alpha(0.1);
@@ -123,6 +145,26 @@ fn ui_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         TimeUi,
         DespawnOnExit(AppState::Game),
     ));
+
+    commands.spawn((
+        Text::new("Seed: "),
+        TextFont {
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+            font_size: 25.0,
+            ..default()
+        },
+        TextColor(c),
+        TextShadow::default(),
+        TextLayout::new_with_justify(Justify::Center),
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(5.),
+            left: Val::Percent(45.),
+            ..default()
+        },
+        SeedUi,
+        DespawnOnExit(AppState::Game),
+    ));
 }
 
 fn draw_direction(game: Res<GameState>, mut query_text: Single<&mut Text, With<DirUi>>) {
@@ -133,10 +175,64 @@ fn draw_score(game: Res<GameState>, mut query_text: Single<&mut Text, With<Score
     query_text.0 = format!("Score: {}", game.0.score);
 }
 
-fn draw_difficulty(game: Res<GameState>, mut query_text: Single<&mut Text, With<DiffUi>>) {
-    query_text.0 = format!("Difficulty: {}", game.0.mode);
+/// Also announces level ups, the speed of the last game is forgotten on its first step
+fn draw_difficulty(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    game: Res<GameState>,
+    mut shown: Local<Option<Speed>>,
+    mut query_text: Single<&mut Text, With<DiffUi>>,
+) {
+    let mode = game.0.mode;
+    let level_up = game.0.steps > 0 && shown.is_some_and(|shown| shown < mode);
+    *shown = Some(mode);
+    query_text.0 = format!("Difficulty: {mode}");
+    if !level_up {
+        return;
+    }
+    commands.spawn((
+        Text::new(format!("Level up! {mode}")),
+        TextFont {
+            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+            font_size: 80.0,
+            ..default()
+        },
+        TextColor(TEXT_COLOR_TITLE.into()),
+        TextShadow::default(),
+        TextLayout::new_with_justify(Justify::Center),
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(40.),
+            width: Val::Percent(100.),
+            ..default()
+        },
+        LevelUpUi(Timer::from_seconds(LEVEL_UP_SECS, TimerMode::Once)),
+        DespawnOnExit(AppState::Game),
+    ));
+}
+
+fn fade_level_up(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut LevelUpUi, &mut TextColor, &mut Node)>,
+) {
+    for (ent, mut banner, mut colour, mut node) in query.iter_mut() {
+        banner.0.tick(time.delta());
+        if banner.0.is_finished() {
+            commands.entity(ent).despawn();
+            continue;
+        }
+        let left = 1. - banner.0.fraction();
+        colour.0.set_alpha(left);
+        // Drifts up as it fades
+        node.top = Val::Percent(30. + 10. * left);
+    }
 }
 
 fn draw_time(game: Res<GameState>, mut query_text: Single<&mut Text, With<TimeUi>>) {
     query_text.0 = format!("Time: {}", game.0.steps);
 }
+
+fn draw_seed(game: Res<GameState>, mut query_text: Single<&mut Text, With<SeedUi>>) {
+    query_text.0 = format!("Seed: {}", game.0.seed());
+}
@@ -0,0 +1,156 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result as ARes;
+use serde::{Deserialize, Serialize};
+
+/// Checkpoint written at the end of an episode, scored by its evaluation
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointEntry {
+    pub episode: usize,
+    pub score: f32,
+}
+
+/// Checkpoints of a run, one directory each under `checkpoints/`.
+///
+/// The latest checkpoint is always kept so that runs can resume from it, along with
+/// the `keep_best` best scored ones. Files only needed to resume, like the optimizer
+/// state, are dropped from every checkpoint but the latest.
+#[derive(Debug, Clone)]
+pub struct Checkpoints {
+    dir: PathBuf,
+    keep_best: usize,
+    /// Files removed from checkpoints once a newer one is written
+    resume_only: Vec<String>,
+}
+
+impl Checkpoints {
+    pub fn new(artifact_dir: impl AsRef<Path>, keep_best: usize) -> Self {
+        Self::named(artifact_dir, "checkpoints", keep_best)
+    }
+
+    /// Checkpoints under `name` rather than `checkpoints/`, for trainers sharing an
+    /// artifact dir
+    pub fn named(artifact_dir: impl AsRef<Path>, name: &str, keep_best: usize) -> Self {
+        Self {
+            dir: artifact_dir.as_ref().join(name),
+            keep_best,
+            resume_only: vec![],
+        }
+    }
+
+    pub fn with_resume_only(mut self, files: &[&str]) -> Self {
+        self.resume_only = files.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    fn episode_dir(&self, episode: usize) -> PathBuf {
+        self.dir.join(format!("ep-{episode:06}"))
+    }
+
+    /// Directory of the checkpoint of `episode`, created if missing
+    pub fn path(&self, episode: usize) -> ARes<PathBuf> {
+        let path = self.episode_dir(episode);
+        fs::create_dir_all(&path)?;
+        Ok(path)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index.json")
+    }
+
+    /// Every kept checkpoint, oldest first
+    pub fn entries(&self) -> ARes<Vec<CheckpointEntry>> {
+        match fs::read_to_string(self.index_path()) {
+            Ok(index) => Ok(serde_json::from_str(&index)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn latest(&self) -> ARes<Option<CheckpointEntry>> {
+        Ok(self.entries()?.into_iter().max_by_key(|e| e.episode))
+    }
+
+    pub fn best(&self) -> ARes<Option<CheckpointEntry>> {
+        Ok(self
+            .entries()?
+            .into_iter()
+            .max_by(|a, b| a.score.total_cmp(&b.score)))
+    }
+
+    /// Records a checkpoint fully written to `path(entry.episode)`, then removes
+    /// the ones no longer kept. A crash before this leaves the previous index intact.
+    pub fn commit(&self, entry: CheckpointEntry) -> ARes<()> {
+        let mut entries = self.entries()?;
+        entries.retain(|e| e.episode != entry.episode);
+        entries.push(entry);
+        entries.sort_by(|a, b| b.score.total_cmp(&a.score));
+        let (kept, dropped): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .enumerate()
+            .partition(|(rank, e)| *rank < self.keep_best || e.episode == entry.episode);
+        let mut kept = kept.into_iter().map(|(_, e)| e).collect::<Vec<_>>();
+        kept.sort_by_key(|e| e.episode);
+
+        fs::create_dir_all(&self.dir)?;
+        let tmp = self.dir.join("index.json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&kept)?)?;
+        fs::rename(tmp, self.index_path())?;
+
+        for (_, e) in dropped {
+            fs::remove_dir_all(self.episode_dir(e.episode)).ok();
+        }
+        for e in kept.iter().filter(|e| e.episode != entry.episode) {
+            for file in &self.resume_only {
+                fs::remove_file(self.episode_dir(e.episode).join(file)).ok();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_best_and_the_latest() {
+        let dir = std::env::temp_dir().join(format!("checkpoints-{}", std::process::id()));
+        let checkpoints = Checkpoints::new(&dir, 2).with_resume_only(&["buffer.bin"]);
+        for (episode, score) in [(1, 5.), (2, 1.), (3, 9.), (4, 0.)] {
+            let path = checkpoints.path(episode).expect("Writable temp dir");
+            fs::write(path.join("buffer.bin"), []).expect("Writable temp dir");
+            checkpoints
+                .commit(CheckpointEntry { episode, score })
+                .expect("Writable temp dir");
+        }
+        let episodes = checkpoints
+            .entries()
+            .expect("Readable index")
+            .iter()
+            .map(|e| e.episode)
+            .collect::<Vec<_>>();
+        assert_eq!(episodes, vec![1, 3, 4]);
+        assert_eq!(
+            checkpoints
+                .latest()
+                .expect("Readable index")
+                .map(|e| e.episode),
+            Some(4)
+        );
+        assert_eq!(
+            checkpoints
+                .best()
+                .expect("Readable index")
+                .map(|e| e.episode),
+            Some(3)
+        );
+        assert!(!dir.join("checkpoints/ep-000002").exists());
+        assert!(!dir.join("checkpoints/ep-000003/buffer.bin").exists());
+        assert!(dir.join("checkpoints/ep-000004/buffer.bin").exists());
+        fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Result as ARes, anyhow, bail};
+use burn::{
+    backend::{Autodiff, NdArray, Wgpu, wgpu::WgpuDevice},
+    optim::AdamConfig,
+    prelude::*,
+    record::{
+        BinFileRecorder, FullPrecisionSettings, NamedMpkFileRecorder, PrettyJsonFileRecorder,
+    },
+    tensor::backend::AutodiffBackend,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rand::prelude::*;
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use snake_api_lib::simulator::{Environment, PlayerTrait, SimulatorOptions, SnakeEnv};
+
+use crate::{
+    data::{DatasetGeneratorConfig, PlayerModel},
+    evolution::{self, EvolutionConfig},
+    model::{ModelConfig, Policy, load_weights},
+    ppo::{self, PpoConfig},
+    training::{self, TrainingConfig},
+};
+
+/// Trains snake agents and inspects the trained ones
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+    /// Directory the runs write their configs, models and checkpoints to
+    #[arg(long, global = true, default_value = "/tmp/burn-tutorial/")]
+    pub artifact_dir: String,
+    /// Dataset generator config, as JSON
+    #[arg(long, global = true, default_value = "./config.json")]
+    pub config: PathBuf,
+    /// Backend of the networks, evolution always runs on the CPU
+    #[arg(long, global = true, value_enum, default_value_t)]
+    pub backend: BackendKind,
+    /// Seed of the trainer, or of the games played by `eval` and `play`
+    #[arg(long, global = true)]
+    pub seed: Option<u64>,
+    /// Dotted override such as `rew_config.gamma_factor=0.9`, keys under
+    /// `training.` go to the config of the trainer
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    pub overrides: Vec<Override>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Trains a new agent, clearing the artifact dir
+    Train {
+        #[arg(long, value_enum, default_value_t)]
+        algo: Algo,
+    },
+    /// Continues a run from what it saved in the artifact dir
+    Resume {
+        #[arg(long, value_enum, default_value_t)]
+        algo: Algo,
+    },
+    /// Plays a fixed set of benchmark games greedily with a trained model
+    Eval {
+        #[command(flatten)]
+        model: ModelArgs,
+        #[arg(long, default_value_t = 100)]
+        episodes: usize,
+        /// Also writes the report as JSON to this file
+        #[arg(long)]
+        json: Option<PathBuf>,
+    },
+    /// Writes a trained model in another format, along with its config
+    Export {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Weights file to write, the recorder sets its extension. The config is
+        /// written next to it with a `.json` one, JSON weights go to `.weights.json`.
+        #[arg(long)]
+        out: PathBuf,
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+    },
+    /// Shows a trained model playing a game in the terminal
+    Play {
+        #[command(flatten)]
+        model: ModelArgs,
+        /// Pause between two frames
+        #[arg(long, default_value_t = 150)]
+        delay_ms: u64,
+    },
+}
+
+/// Trained model to load
+#[derive(Debug, Args)]
+pub struct ModelArgs {
+    /// Algorithm that trained the model
+    #[arg(long, value_enum, default_value_t)]
+    pub algo: Algo,
+    /// Weights to load, the trainer output in the artifact dir by default.
+    /// The model config is read from the same path with a `.json` extension.
+    #[arg(long)]
+    pub model: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum BackendKind {
+    Ndarray,
+    #[default]
+    Wgpu,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Algo {
+    /// Double DQN with prioritized replay
+    #[default]
+    Dqn,
+    /// Actor critic trained with PPO
+    Ppo,
+    /// Neuroevolution of the Q-network
+    Evo,
+}
+
+impl Algo {
+    /// Weights the trainer writes to the artifact dir
+    pub fn model_file(self) -> &'static str {
+        match self {
+            Self::Dqn => "model.mpk",
+            Self::Ppo => "ppo-model.mpk",
+            Self::Evo => "evo-model.mpk",
+        }
+    }
+
+    /// Config the trainer writes to the artifact dir
+    pub fn config_file(self) -> &'static str {
+        match self {
+            Self::Dqn => "training.json",
+            Self::Ppo => "ppo.json",
+            Self::Evo => "evolution.json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ExportFormat {
+    /// Named MessagePack, what the trainers write
+    #[default]
+    Mpk,
+    /// Compact binary
+    Bin,
+    /// Pretty printed JSON
+    Json,
+}
+
+/// Value put at a dotted path of a config, parsed as JSON or taken as a plain string
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    pub path: String,
+    pub value: Value,
+}
+
+impl FromStr for Override {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> ARes<Self> {
+        let (path, value) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected KEY=VALUE, got `{s}`"))?;
+        Ok(Self {
+            path: path.trim().to_string(),
+            value: serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into())),
+        })
+    }
+}
+
+/// Sets the fields of `config` named by `overrides`, unknown fields are an error
+pub fn apply_overrides<T: Serialize + DeserializeOwned>(
+    config: &T,
+    overrides: &[Override],
+) -> ARes<T> {
+    let mut tree = serde_json::to_value(config)?;
+    for Override { path, value } in overrides {
+        let mut node = &mut tree;
+        for key in path.split('.') {
+            node = node
+                .as_object_mut()
+                .and_then(|fields| fields.get_mut(key))
+                .ok_or_else(|| anyhow!("No field `{key}` in `{path}`"))?;
+        }
+        *node = value.clone();
+    }
+    Ok(serde_json::from_value(tree)?)
+}
+
+impl ModelArgs {
+    fn weights(&self, artifact_dir: &str) -> PathBuf {
+        self.model
+            .clone()
+            .unwrap_or_else(|| format!("{artifact_dir}{}", self.algo.model_file()).into())
+    }
+
+    fn config(&self, artifact_dir: &str) -> ARes<ModelConfig> {
+        let path = self.weights(artifact_dir).with_extension("json");
+        ModelConfig::load(&path).map_err(|e| anyhow!("Cannot read {}: {e}", path.display()))
+    }
+}
+
+impl Cli {
+    pub fn run(self) -> ARes<()> {
+        match self.backend {
+            BackendKind::Ndarray => self.run_with::<NdArray>(Default::default()),
+            BackendKind::Wgpu => self.run_with::<Wgpu<f32, i32>>(WgpuDevice::default()),
+        }
+    }
+
+    fn artifact_dir(&self) -> String {
+        match self.artifact_dir.ends_with('/') {
+            true => self.artifact_dir.clone(),
+            false => format!("{}/", self.artifact_dir),
+        }
+    }
+
+    /// Dataset generator config with every override outside of `training.`
+    fn dataset_config(&self) -> ARes<DatasetGeneratorConfig> {
+        let dgb: DatasetGeneratorConfig =
+            serde_json::from_str(&std::fs::read_to_string(&self.config)?)?;
+        let overrides = self
+            .overrides
+            .iter()
+            .filter(|o| !o.path.starts_with("training."))
+            .cloned()
+            .collect::<Vec<_>>();
+        apply_overrides(&dgb, &overrides)
+    }
+
+    /// Trainer config with the seed and the `training.` overrides, on top of the
+    /// saved one when resuming
+    fn trainer_config<C: Config>(&self, default: C, algo: Algo, resume: bool) -> ARes<C> {
+        let config = if resume {
+            let path = format!("{}{}", self.artifact_dir(), algo.config_file());
+            C::load(&path).map_err(|e| anyhow!("Cannot read {path}: {e}"))?
+        } else {
+            default
+        };
+        let seed = self.seed.map(|seed| Override {
+            path: "seed".into(),
+            value: seed.into(),
+        });
+        let overrides = seed
+            .into_iter()
+            .chain(self.overrides.iter().filter_map(|o| {
+                Some(Override {
+                    path: o.path.strip_prefix("training.")?.to_string(),
+                    value: o.value.clone(),
+                })
+            }))
+            .collect::<Vec<_>>();
+        apply_overrides(&config, &overrides)
+    }
+
+    fn run_with<B: Backend>(&self, device: B::Device) -> ARes<()> {
+        let dgb = self.dataset_config()?;
+        let dir = self.artifact_dir();
+        match &self.command {
+            Command::Train { algo } => self.train::<Autodiff<B>>(*algo, device, dgb, false),
+            Command::Resume { algo } => self.train::<Autodiff<B>>(*algo, device, dgb, true),
+            Command::Eval {
+                model,
+                episodes,
+                json,
+            } => {
+                let (config, path) = (model.config(&dir)?, model.weights(&dir));
+                self.check_fits(&config, &path, &dgb)?;
+                match model.algo {
+                    Algo::Ppo => {
+                        let net =
+                            load_weights(config.init_actor_critic::<B>(&device), &path, &device)?;
+                        let player = PlayerModel::new(&net, &device, &dgb.observation);
+                        self.eval(&player, &dgb, *episodes, json.as_deref())
+                    }
+                    _ => {
+                        let net = load_weights(config.init::<B>(&device), &path, &device)?;
+                        let player = PlayerModel::new(&net, &device, &dgb.observation);
+                        self.eval(&player, &dgb, *episodes, json.as_deref())
+                    }
+                }
+            }
+            Command::Export { model, out, format } => {
+                let (config, path) = (model.config(&dir)?, model.weights(&dir));
+                match model.algo {
+                    Algo::Ppo => {
+                        let net =
+                            load_weights(config.init_actor_critic::<B>(&device), &path, &device)?;
+                        export(net, &config, out, *format)
+                    }
+                    _ => {
+                        let net = load_weights(config.init::<B>(&device), &path, &device)?;
+                        export(net, &config, out, *format)
+                    }
+                }
+            }
+            Command::Play { model, delay_ms } => {
+                let (config, path) = (model.config(&dir)?, model.weights(&dir));
+                self.check_fits(&config, &path, &dgb)?;
+                let delay = Duration::from_millis(*delay_ms);
+                match model.algo {
+                    Algo::Ppo => {
+                        let net =
+                            load_weights(config.init_actor_critic::<B>(&device), &path, &device)?;
+                        self.play(&net, &device, &dgb, delay)
+                    }
+                    _ => {
+                        let net = load_weights(config.init::<B>(&device), &path, &device)?;
+                        self.play(&net, &device, &dgb, delay)
+                    }
+                }
+            }
+        }
+    }
+
+    fn train<B: AutodiffBackend>(
+        &self,
+        algo: Algo,
+        device: B::Device,
+        dgb: DatasetGeneratorConfig,
+        resume: bool,
+    ) -> ARes<()> {
+        let dir = self.artifact_dir();
+        match algo {
+            Algo::Dqn => {
+                let default = TrainingConfig::new(ModelConfig::new(4, 512), AdamConfig::new());
+                let config = self.trainer_config(default, algo, resume)?;
+                training::run::<B>(&dir, device, dgb, config, resume)?;
+            }
+            Algo::Ppo => {
+                let default = PpoConfig::new(ModelConfig::new(4, 256), AdamConfig::new());
+                let config = self.trainer_config(default, algo, resume)?;
+                ppo::run::<B>(&dir, device, dgb, config, resume)?;
+            }
+            // Evolution runs on the CPU, every genome of a generation on its own thread
+            Algo::Evo => {
+                let default = EvolutionConfig::new(ModelConfig::new(4, 64));
+                let config = self.trainer_config(default, algo, resume)?;
+                evolution::run::<NdArray>(&dir, Default::default(), dgb, config, resume)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Models only play the observations they were trained on
+    fn check_fits(
+        &self,
+        config: &ModelConfig,
+        path: &Path,
+        dgb: &DatasetGeneratorConfig,
+    ) -> ARes<()> {
+        if !config.fits(&dgb.observation) {
+            bail!(
+                "{} was not trained on the observations of {}",
+                path.display(),
+                self.config.display()
+            );
+        }
+        Ok(())
+    }
+
+    fn simulator_options(dgb: &DatasetGeneratorConfig) -> SimulatorOptions {
+        SimulatorOptions {
+            number_of_iterations: dgb.sim_config.episode_limit.unwrap_or(1000),
+        }
+    }
+
+    /// Plays the benchmark games, printing the report and writing it to `json` if set
+    fn eval(
+        &self,
+        player: &impl PlayerTrait,
+        dgb: &DatasetGeneratorConfig,
+        episodes: usize,
+        json: Option<&Path>,
+    ) -> ARes<()> {
+        let evaluator = dgb.sim_config.evaluator(episodes, self.seed.unwrap_or(0));
+        let report = evaluator.evaluate(player)?;
+        print!("{report}");
+        if let Some(path) = json {
+            std::fs::write(path, report.to_json()?)?;
+        }
+        Ok(())
+    }
+
+    fn play<B: Backend, M: Policy<B>>(
+        &self,
+        model: &M,
+        device: &B::Device,
+        dgb: &DatasetGeneratorConfig,
+        delay: Duration,
+    ) -> ARes<()> {
+        let player = PlayerModel::new(model, device, &dgb.observation);
+        let mut env = SnakeEnv::new(
+            dgb.sim_config.game_builder(),
+            Some(Self::simulator_options(dgb).number_of_iterations),
+        )
+        .with_reward_fn(dgb.rew_config.reward_fn.clone());
+        let mut rng = SmallRng::seed_from_u64(self.seed.unwrap_or(0));
+        env.reset(rng.next_u64());
+        let mut total = 0.;
+        loop {
+            let dir = player.choose_dir(env.game(), &mut rng);
+            let step = env.step(dir)?;
+            total += step.reward;
+            println!("{}", env.game());
+            println!(
+                "Move {} Apples {} Steps {} Return {:.2}",
+                dir, step.info.num_of_apples, step.info.steps, total
+            );
+            if step.terminated || step.truncated {
+                println!("{:?}", step.info.result);
+                return Ok(());
+            }
+            thread::sleep(delay);
+        }
+    }
+}
+
+fn export<B: Backend, M: Module<B>>(
+    model: M,
+    config: &ModelConfig,
+    out: &Path,
+    format: ExportFormat,
+) -> ARes<()> {
+    let saved = match format {
+        ExportFormat::Mpk => model.save_file(
+            out.to_path_buf(),
+            &NamedMpkFileRecorder::<FullPrecisionSettings>::new(),
+        ),
+        ExportFormat::Bin => model.save_file(
+            out.to_path_buf(),
+            &BinFileRecorder::<FullPrecisionSettings>::new(),
+        ),
+        // Keeps the weights from overwriting the config next to them
+        ExportFormat::Json => model.save_file(
+            out.with_extension("weights.json"),
+            &PrettyJsonFileRecorder::<FullPrecisionSettings>::new(),
+        ),
+    };
+    saved.map_err(|e| anyhow!("Cannot write {}: {e}", out.display()))?;
+    config.save(out.with_extension("json"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_set_nested_fields() {
+        let config = TrainingConfig::new(ModelConfig::new(4, 512), AdamConfig::new());
+        let overrides = ["model.hidden_size=64", "learning_rate=0.01", "seed=7"]
+            .map(|o| o.parse::<Override>().expect("Valid override"));
+        let config = apply_overrides(&config, &overrides).expect("Known fields");
+        assert_eq!(
+            serde_json::to_value(&config.model).expect("Serializable config")["hidden_size"],
+            64
+        );
+        assert_eq!(config.learning_rate, 0.01);
+        assert_eq!(config.seed, 7);
+
+        let typo = ["model.hiden_size=64"
+            .parse::<Override>()
+            .expect("Valid override")];
+        assert!(apply_overrides(&config, &typo).is_err());
+        assert!("learning_rate".parse::<Override>().is_err());
+    }
+
+    #[test]
+    fn invalid_overrides_fail_before_training() {
+        let config = TrainingConfig::new(ModelConfig::new(4, 512), AdamConfig::new());
+        assert!(config.validate().is_ok());
+        for invalid in ["target_sync=0", "buffer_capacity=0", "batch_size=0"] {
+            let invalid = [invalid.parse::<Override>().expect("Valid override")];
+            let config = apply_overrides(&config, &invalid).expect("Known field");
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn exported_models_load_back() {
+        let dir = std::env::temp_dir().join(format!("export-{}", std::process::id()));
+        let out = dir.join("exported.mpk");
+        std::fs::create_dir_all(&dir).expect("Writable temp dir");
+        let device = Default::default();
+        let config = ModelConfig::new(4, 16);
+        let model = config.init::<NdArray>(&device);
+        export(model, &config, &out, ExportFormat::Mpk).expect("Writable temp dir");
+
+        let args = ModelArgs {
+            algo: Algo::Dqn,
+            model: Some(out.clone()),
+        };
+        let loaded = args.config("").expect("Config next to the weights");
+        assert_eq!(loaded.to_string(), config.to_string());
+        load_weights(loaded.init::<NdArray>(&device), &args.weights(""), &device)
+            .expect("Weights of the same shape");
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -1,284 +1,433 @@
-use std::sync::{Arc, OnceLock, RwLock};
+use std::cell::RefCell;
 
 use anyhow::Result as ARes;
-use burn::{
-    data::{
-        dataloader::{DataLoader, DataLoaderBuilder, DataLoaderIterator, batcher::*},
-        dataset::vision::MnistItem,
-    },
-    prelude::*,
-    record::Record,
-    tensor::{Distribution, backend::AutodiffBackend, ops::BoolTensor},
-};
+use burn::{prelude::*, tensor::Distribution};
 
 use itertools::Itertools;
-// use itertools::Itertools;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 use snake_api_lib::{
-    api::{GameAPI, GameAPIBinaryRepr, GameAPIBuilder, SnakeTrait},
-    common::{Direction, GRID_X, GRID_Y},
-    simulator::{PlayerTrait, SimulationStep, SimulationStepReward, Simulator, SimulatorOptions},
+    api::{GameAPI, GameAPIBuilder},
+    common::{BoardSize, Direction, Topology},
+    evaluation::Evaluator,
+    food::FoodOptions,
+    level::Level,
+    ndarray::{Axis, stack},
+    observation::{FrameStack, Observation, ObservationConfig},
+    reward::CombinedReward,
+    simulator::{PlayerTrait, SimulationStep},
+    vec_env::VecEnv,
 };
-use strum::IntoEnumIterator;
 
-use crate::model::{Model, StateRepr};
+use crate::model::{Model, Policy, StateRepr};
 
 #[derive(Debug)]
-pub(crate) struct DatasetGenerator {
+pub struct DatasetGenerator {
     data_gen: DatasetGeneratorConfig,
-    current_sims: Vec<SimulationStep>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
-pub(crate) struct DatasetGeneratorConfig {
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DatasetGeneratorConfig {
     pub sim_config: SimulationConfig,
     pub rew_config: RewardConfig,
+    #[serde(default)]
+    pub observation: ObservationConfig,
     pub batch_size: usize,
 }
 
 impl DatasetGeneratorConfig {
     pub fn build(self) -> DatasetGenerator {
-        DatasetGenerator {
-            data_gen: self,
-            current_sims: vec![],
-        }
+        DatasetGenerator { data_gen: self }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SimulationConfig {
     pub number_episodes: usize,
     pub episode_limit: Option<usize>,
+    /// Exploration rate of the first episode
     pub eps_expl: f64,
+    /// Exploration rate once fully decayed
+    #[serde(default)]
+    pub eps_min: f64,
+    /// Episodes over which the exploration rate decays linearly to `eps_min`
+    #[serde(default)]
+    pub eps_decay_episodes: usize,
+    #[serde(default)]
+    pub board_size: BoardSize,
+    #[serde(default)]
+    pub topology: Topology,
+    #[serde(default)]
+    pub food: FoodOptions,
+    /// Ascii rows of the level, `#` for walls, overrides `board_size`
+    #[serde(default, with = "level_rows")]
+    pub level: Option<Level>,
+}
+
+/// Levels are written as rows of ascii in the configs and parsed once when loaded,
+/// a broken level fails the config instead of the run
+mod level_rows {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+    use snake_api_lib::level::Level;
+
+    pub fn serialize<S: Serializer>(level: &Option<Level>, s: S) -> Result<S::Ok, S::Error> {
+        level
+            .as_ref()
+            .map(|level| {
+                level
+                    .to_string()
+                    .lines()
+                    .map(str::to_owned)
+                    .collect::<Vec<_>>()
+            })
+            .serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Level>, D::Error> {
+        Option::<Vec<String>>::deserialize(d)?
+            .map(|rows| rows.join("\n").parse().map_err(D::Error::custom))
+            .transpose()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+impl SimulationConfig {
+    /// Step limit of benchmark games without an `episode_limit`
+    const EVAL_STEPS: usize = 1000;
+
+    /// Exploration rate for the given episode, counted from one
+    pub fn eps_at(&self, episode: usize) -> f64 {
+        if self.eps_decay_episodes == 0 {
+            return self.eps_expl;
+        }
+        let progress = (episode.saturating_sub(1) as f64 / self.eps_decay_episodes as f64).min(1.);
+        self.eps_expl + (self.eps_min - self.eps_expl) * progress
+    }
+
+    /// Benchmark of `episodes` games seeded from `seed`, capped by `episode_limit`
+    pub fn evaluator(&self, episodes: usize, seed: u64) -> Evaluator {
+        Evaluator::new(
+            self.game_builder(),
+            self.episode_limit.unwrap_or(Self::EVAL_STEPS),
+            episodes,
+            seed,
+        )
+    }
+
+    pub fn game_builder(&self) -> GameAPIBuilder {
+        let builder = GameAPIBuilder::default()
+            .with_board_size(self.board_size)
+            .with_topology(self.topology)
+            .with_food(self.food);
+        match &self.level {
+            Some(level) => builder.with_level(level.clone()),
+            None => builder,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RewardConfig {
-    pub step_rew: f32,
-    pub step_rew_imp: f32,
-    pub fruit_rew: f32,
-    pub win_rew: f32,
-    pub lose_rew: f32,
+    /// Reward terms summed on every step, plain sparse when left out
+    #[serde(default)]
+    pub reward_fn: CombinedReward,
     pub gamma_factor: f32,
 }
 
+/// Plays with any network scoring the moves, the Q-network by default
 #[derive(Clone, Debug)]
-pub struct PlayerModel<'a, 'b, B: Backend> {
-    pub model: &'a Model<B>,
+pub struct PlayerModel<'a, 'b, B: Backend, M = Model<B>> {
+    pub model: &'a M,
     pub eps: f64,
     pub device: &'b B::Device,
     pub active_mode: bool,
+    /// Frames of the game played through `PlayerTrait`
+    pub history: RefCell<FrameStack>,
 }
 
-impl<'a, 'b, B: Backend> PlayerModel<'a, 'b, B> {
-    fn set_mode(&mut self, is_training: bool) {
+impl<'a, 'b, B: Backend, M: Policy<B>> PlayerModel<'a, 'b, B, M> {
+    /// Greedy player seeing games through `observation`
+    pub fn new(model: &'a M, device: &'b B::Device, observation: &ObservationConfig) -> Self {
+        Self {
+            model,
+            eps: 0.,
+            device,
+            active_mode: false,
+            history: RefCell::new(observation.stack()),
+        }
+    }
+
+    pub fn set_mode(&mut self, is_training: bool) {
         self.active_mode = is_training;
     }
 
-    fn set_eps(&mut self, eps: f64) {
+    pub fn set_eps(&mut self, eps: f64) {
         self.eps = eps;
     }
 }
 
-impl<B: Backend> From<(GameAPIBinaryRepr, &B::Device)> for StateRepr<B> {
-    fn from(value: (GameAPIBinaryRepr, &B::Device)) -> Self {
-        let (GameAPIBinaryRepr(arr), dev) = value;
-        let arr = arr
-            .to_shape([1, GRID_X, GRID_Y])
-            .expect("Padding with one should nof affect it");
-        let arr = arr
-            .as_standard_layout()
-            .to_owned()
-            .into_raw_vec_and_offset()
-            .0;
-        let td = TensorData::new(arr, [1, GRID_X, GRID_Y]);
-        let td: Tensor<B, 4, Float> = Tensor::<B, 3, Int>::from_data(td, dev).one_hot(4).float();
-        let td = td.random_like(Distribution::Normal(0.0, 1e-2)) + td;
-        StateRepr(td)
+impl<B: Backend> StateRepr<B> {
+    /// Stacks a batch of encoded observations, plus a little noise
+    pub fn from_batch(observations: &[Observation], dev: &B::Device) -> Self {
+        let StateRepr(td) = Self::exact(observations, dev);
+        StateRepr(td.random_like(Distribution::Normal(0.0, 1e-2)) + td)
+    }
+
+    /// Stacks a batch of encoded observations as they are, the same batch always
+    /// gives the same outputs
+    pub fn exact(observations: &[Observation], dev: &B::Device) -> Self {
+        let views = observations.iter().map(|o| o.view()).collect_vec();
+        let arr = stack(Axis(0), &views).expect("Observations of a batch share their shape");
+        let shape = arr.shape().to_vec();
+        let arr = arr.into_raw_vec_and_offset().0;
+        StateRepr(Tensor::from_data(TensorData::new(arr, shape), dev))
     }
 }
 
-impl<'a, 'b, B: Backend> PlayerTrait for PlayerModel<'a, 'b, B> {
-    fn choose_dir(&self, game_instance: &GameAPI, with_rng: &mut dyn RngCore) -> Direction {
-        let dir = game_instance.snake.direction;
-        // dbg!(game_instance.snake.head);
-        let dir_vec = Direction::iter()
-            .map(|d| {
-                let res = (game_instance
-                    .snake
-                    .check_cell(game_instance.snake.head.add_dir(d))
-                    .is_some_and(|x| !x));
-                res
-            })
+impl<'a, 'b, B: Backend, M: Policy<B>> PlayerModel<'a, 'b, B, M> {
+    /// Outputs of the model for a batch, unsafe moves pushed below every safe one.
+    /// Boards without a safe move fall back to the raw output. Only training adds
+    /// noise, the same boards always get the same moves otherwise.
+    fn masked_scores(
+        &self,
+        observations: &[Observation],
+        masks: &[[bool; 4]],
+    ) -> (Tensor<B, 2>, Vec<[bool; 4]>) {
+        let batch = masks.len();
+        let state = match self.active_mode {
+            true => StateRepr::from_batch(observations, self.device),
+            false => StateRepr::exact(observations, self.device),
+        };
+        let out = self.model.scores(state);
+        let masks = masks
+            .iter()
+            .map(|m| if m.contains(&true) { *m } else { [true; 4] })
             .collect_vec();
-        // if self.active_mode && with_rng.random_bool(self.eps) {
-        //     return dir_vec
-        //         .iter()
-        //         .enumerate()
-        //         .filter_map(|x| {
-        //             if *x.1 {
-        //                 Some(Direction::from(x.0))
-        //             } else {
-        //                 None
-        //             }
-        //         })
-        //         .choose(with_rng)
-        //         .unwrap_or(Direction::Up);
-        // }
-        let state_repr: StateRepr<B> = (game_instance.to_game_repr(), self.device).into();
-        let out = self.model.forward(state_repr);
-        // dbg!(out.clone().to_data().to_vec::<f32>());
-        let m = Tensor::<B, 1, Bool>::from_data(
-            dir_vec.into_iter().collect_array::<4>().unwrap(),
+        let m = Tensor::<B, 2, Bool>::from_data(
+            TensorData::new(masks.concat(), [batch, 4]),
             self.device,
         );
-        let indx = if m.clone().any().into_scalar().to_bool() {
-            let v = out.clone().min().into_scalar().elem::<f32>();
-            let flipped = out.flatten(0, 1).mask_fill(m.bool_not(), v);
-            // dbg!(flipped.clone().to_data().to_vec::<f32>());
-
-            flipped.argmax(0).into_scalar().elem::<i64>() as usize
-        } else {
-            let out: Tensor<B, 1> = out.flatten(0, 1);
-            out.argmax(0).into_scalar().elem::<i64>() as usize
-        };
-        Direction::from(indx)
+        let v = out.clone().min().into_scalar().elem::<f32>() - 1.;
+        (out.mask_fill(m.bool_not(), v), masks)
+    }
+
+    /// Best move of every board, or a random safe one with probability `eps` while training
+    fn pick(
+        &self,
+        scores: Tensor<B, 2>,
+        masks: Vec<[bool; 4]>,
+        with_rng: &mut dyn RngCore,
+    ) -> Vec<Direction> {
+        scores
+            .argmax(1)
+            .into_data()
+            .iter::<i64>()
+            .zip(masks)
+            .map(|(indx, mask)| {
+                if self.active_mode && with_rng.random_bool(self.eps) {
+                    let safe = (0..4).filter(|d| mask[*d]).collect_vec();
+                    if let Some(d) = safe.choose(with_rng) {
+                        return Direction::from(*d);
+                    }
+                }
+                Direction::from(indx as usize)
+            })
+            .collect()
+    }
+
+    /// Picks a direction for every board of a batch with a single forward pass.
+    /// While training, each one is replaced by a random safe move with probability `eps`.
+    pub fn choose_dirs(
+        &self,
+        observations: &[Observation],
+        masks: &[[bool; 4]],
+        with_rng: &mut dyn RngCore,
+    ) -> Vec<Direction> {
+        let (scores, masks) = self.masked_scores(observations, masks);
+        self.pick(scores, masks, with_rng)
+    }
+
+    /// Same move as `choose_dir`, along with how sure the model is of it: its share of a
+    /// softmax over the safe moves
+    pub fn choose_dir_with_confidence(
+        &self,
+        game_instance: &GameAPI,
+        with_rng: &mut dyn RngCore,
+    ) -> (Direction, f32) {
+        let observation = self.history.borrow_mut().observe(game_instance);
+        let (scores, masks) = self.masked_scores(&[observation], &[game_instance.action_mask()]);
+        let mask = masks[0];
+        let values = scores.clone().into_data().iter::<f32>().collect_vec();
+        let dir = self.pick(scores, masks, with_rng)[0];
+        let safe = (0..4).filter(|d| mask[*d]).collect_vec();
+        let max = safe
+            .iter()
+            .map(|d| values[*d])
+            .fold(f32::NEG_INFINITY, f32::max);
+        let total = safe.iter().map(|d| (values[*d] - max).exp()).sum::<f32>();
+        (dir, (values[dir as usize] - max).exp() / total)
+    }
+}
+
+impl<'a, 'b, B: Backend, M: Policy<B>> PlayerTrait for PlayerModel<'a, 'b, B, M> {
+    fn choose_dir(&self, game_instance: &GameAPI, with_rng: &mut dyn RngCore) -> Direction {
+        let dir_vec = game_instance.action_mask();
+        let observation = self.history.borrow_mut().observe(game_instance);
+        self.choose_dirs(&[observation], &[dir_vec], with_rng)[0]
     }
 }
 
+/// How a game played by `gen_sims` went
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpisodeSummary {
+    pub episode_return: f32,
+    pub apples: u128,
+    pub steps: u128,
+}
+
+/// Transitions played by `gen_sims`, along with a summary of every finished game
+#[derive(Debug, Clone)]
+pub struct GeneratedSims {
+    pub sims: Vec<SimulationStep<Observation>>,
+    pub episodes: Vec<EpisodeSummary>,
+}
+
 impl DatasetGenerator {
-    pub fn gen_sims<'a, 'b, B: Backend, T>(
-        &mut self,
-        model: &'a Model<B>,
-        device: &'b B::Device,
+    pub fn config(&self) -> &DatasetGeneratorConfig {
+        &self.data_gen
+    }
+
+    /// Plays `number_episodes` games with `model`, exploring when `active_mode` is set
+    pub fn gen_sims<B: Backend, T>(
+        &self,
+        model: &Model<B>,
+        device: &B::Device,
         with_rng: &mut T,
         ep_iter: usize,
         active_mode: bool,
-        debug_result: bool,
-    ) -> PlayerModel<'a, 'b, B>
+    ) -> ARes<GeneratedSims>
     where
         T: RngCore + Clone + SeedableRng + Send + Sync,
     {
         let p = self.data_gen.sim_config.number_episodes;
-        let player = PlayerModel {
-            model,
-            eps: self.data_gen.sim_config.eps_expl,
-            device,
-            active_mode,
-        };
+        let mut player = PlayerModel::new(model, device, &self.data_gen.observation);
+        player.set_eps(self.data_gen.sim_config.eps_at(ep_iter));
+        player.set_mode(active_mode);
         let ep_size = 100 + ep_iter * 100;
-        let sim = Simulator::new(
-            GameAPIBuilder::default(),
-            SimulatorOptions {
-                number_of_iterations: self.data_gen.sim_config.episode_limit.unwrap_or(ep_size),
-            },
-        );
+        let mut vec_env = VecEnv::new(
+            self.data_gen.sim_config.game_builder(),
+            Some(self.data_gen.sim_config.episode_limit.unwrap_or(ep_size)),
+            p,
+            with_rng.next_u64(),
+        )
+        .with_auto_reset(false)
+        .with_reward_fn(self.data_gen.rew_config.reward_fn.clone());
 
-        let sims = tqdm::tqdm((0..p).map(|_| sim.simulation(&player, with_rng, debug_result)))
-            .collect::<ARes<Vec<_>>>()
-            .expect("Should compile")
-            .into_iter()
-            .flatten()
-            .collect::<Vec<_>>();
-        self.current_sims = sims;
-        player
-    }
-
-    pub fn iter_sims<B: Backend, T>(
-        &mut self,
-        with_rng: &mut T,
-        player: &PlayerModel<B>,
-        device: &B::Device,
-    ) -> impl Iterator<Item = BatchedSimulationStep<B>>
-    where
-        T: RngCore + Clone + SeedableRng + Send + Sync,
-    {
-        self.current_sims.shuffle(with_rng);
-        let batches = self
-            .current_sims
-            .chunks(self.data_gen.batch_size)
-            .map(|c| self.batch_sims(device, c.iter().cloned(), &player))
+        let mut stacks = vec_env
+            .games()
+            .map(|game| {
+                let mut stack = self.data_gen.observation.stack();
+                stack.reset(game);
+                stack
+            })
             .collect_vec();
-        batches.into_iter()
-    }
-
-    fn batch_sims<B: Backend>(
-        &self,
-        device: &B::Device,
-        els: impl Iterator<Item = SimulationStep>,
-        player: &PlayerModel<B>,
-    ) -> BatchedSimulationStep<B> {
-        let mut v_snapshot: Vec<Tensor<B, 4, Float>> = vec![];
-        let mut v_direction = vec![];
-        let mut v_reward = vec![];
-        let mut v_next_state_qual = vec![];
-        for SimulationStep {
-            snapshot,
-            direction,
-            reward,
-            next_state,
-        } in els
-        {
-            let (StateRepr(snap)): StateRepr<B> = (snapshot, device).into();
-            v_snapshot.push(snap);
-            v_direction.push(direction as i32);
-            match reward {
-                SimulationStepReward::Won => {
-                    v_reward.push(self.data_gen.rew_config.fruit_rew);
-                    v_next_state_qual.push(self.data_gen.rew_config.win_rew);
-                }
-                SimulationStepReward::Lost => {
-                    v_reward.push(self.data_gen.rew_config.step_rew);
-                    v_next_state_qual.push(self.data_gen.rew_config.lose_rew);
-                }
-                SimulationStepReward::Step(b) => {
-                    let st: StateRepr<B> = (
-                        next_state.expect("Should have next state in step"),
-                        player.device,
-                    )
-                        .into();
-                    v_reward.push(if b {
-                        self.data_gen.rew_config.step_rew_imp
-                    } else {
-                        self.data_gen.rew_config.step_rew
+        let mut sims = vec![];
+        let mut returns = vec![0.; p];
+        let mut episodes = vec![];
+        while !vec_env.all_done() {
+            let before = stacks.iter().map(FrameStack::observation).collect_vec();
+            let dirs = player.choose_dirs(&before, &vec_env.action_masks(), with_rng);
+            let steps = vec_env.step(&dirs)?;
+            for (indx, (snapshot, step)) in before.into_iter().zip(steps).enumerate() {
+                let Some(step) = step else {
+                    continue;
+                };
+                let game = vec_env.game(indx);
+                returns[indx] += step.reward;
+                if step.terminated || step.truncated {
+                    episodes.push(EpisodeSummary {
+                        episode_return: returns[indx],
+                        apples: step.info.num_of_apples,
+                        steps: step.info.steps,
                     });
-                    let out = player.model.forward(st).argmax(0);
-                    let el = out.max().into_scalar().elem::<f32>();
-                    v_next_state_qual.push(el);
-                }
-                SimulationStepReward::Food => {
-                    let st: StateRepr<B> = (
-                        next_state.expect("Sohuld have next state in step"),
-                        player.device,
-                    )
-                        .into();
-                    let out = player.model.forward(st).argmax(0);
-                    let el = out.max().into_scalar().elem::<f32>();
-                    v_reward.push(self.data_gen.rew_config.fruit_rew);
-                    v_next_state_qual.push(el);
                 }
+                let observation = stacks[indx].push(game);
+                sims.push(SimulationStep::from_step(
+                    snapshot,
+                    dirs[indx],
+                    step.with_observation(observation),
+                ));
             }
         }
-
-        let b_size = v_direction.len();
-        assert!(b_size == v_reward.len() && b_size == v_next_state_qual.len());
-        let snaps = Tensor::cat(v_snapshot, 0);
-        BatchedSimulationStep {
-            snapshot: snaps.clone(),
-            direction: Tensor::from_data(TensorData::new(v_direction, [b_size]), player.device),
-            reward: Tensor::from_data(TensorData::new(v_reward, [b_size]), player.device),
-            next_state_qual: Tensor::from_data(
-                TensorData::new(v_next_state_qual, [b_size]),
-                player.device,
-            ),
-        }
+        Ok(GeneratedSims { sims, episodes })
     }
 }
 
+/// Transitions of a batch, ready for a DQN update
 pub struct BatchedSimulationStep<B: Backend> {
     pub snapshot: Tensor<B, 4, Float>,
     pub direction: Tensor<B, 1, Int>,
     pub reward: Tensor<B, 1, Float>,
-    pub next_state_qual: Tensor<B, 1, Float>,
+    /// Boards after the steps, finished games repeat their last board
+    pub next_state: Tensor<B, 4, Float>,
+    /// One for steps that ended the game, their next state is not bootstrapped
+    pub done: Tensor<B, 1, Float>,
+    /// Importance sampling weights of the transitions
+    pub weights: Tensor<B, 1, Float>,
+}
+
+impl<B: Backend> BatchedSimulationStep<B> {
+    pub fn new(
+        els: &[&SimulationStep<Observation>],
+        weights: Vec<f32>,
+        device: &B::Device,
+    ) -> Self {
+        let b_size = els.len();
+        let snapshots = els.iter().map(|s| s.snapshot.clone()).collect_vec();
+        let next_states = els
+            .iter()
+            .map(|s| s.next_state.clone().unwrap_or_else(|| s.snapshot.clone()))
+            .collect_vec();
+        let v_direction = els.iter().map(|s| s.direction as i32).collect_vec();
+        let v_reward = els.iter().map(|s| s.reward).collect_vec();
+        let v_done = els
+            .iter()
+            .map(|s| s.next_state.is_none() as u8 as f32)
+            .collect_vec();
+        let StateRepr(snapshot) = StateRepr::from_batch(&snapshots, device);
+        let StateRepr(next_state) = StateRepr::from_batch(&next_states, device);
+        Self {
+            snapshot,
+            direction: Tensor::from_data(TensorData::new(v_direction, [b_size]), device),
+            reward: Tensor::from_data(TensorData::new(v_reward, [b_size]), device),
+            next_state,
+            done: Tensor::from_data(TensorData::new(v_done, [b_size]), device),
+            weights: Tensor::from_data(TensorData::new(weights, [b_size]), device),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_checked_when_loaded() {
+        let config = |rows: &str| {
+            serde_json::from_str::<SimulationConfig>(&format!(
+                r#"{{"number_episodes": 1, "episode_limit": null, "eps_expl": 0.1, "level": {rows}}}"#
+            ))
+        };
+        let level = config(r#"[".....", ".#...", ".....", "...#.", "....."]"#)
+            .expect("Valid level")
+            .level
+            .expect("Level set");
+        assert_eq!(level.board(), BoardSize::new(5, 5).expect("Large enough"));
+        let saved = serde_json::to_string(
+            &config(r#"["....", "....", "....", "...."]"#).expect("Valid level"),
+        )
+        .expect("Serializable config");
+        assert!(saved.contains(r#""level":["....","....","....","...."]"#));
+        assert!(config(r#"["...", "..."]"#).is_err());
+        assert!(config(r#"["....", "..x.", "....", "...."]"#).is_err());
+    }
 }
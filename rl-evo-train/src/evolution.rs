@@ -0,0 +1,374 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result as ARes, anyhow, bail};
+use burn::{
+    module::{ModuleMapper, ModuleVisitor, Param},
+    prelude::*,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+};
+use itertools::Itertools;
+use rand::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use snake_api_lib::{
+    api::StepResult,
+    observation::ObservationConfig,
+    simulator::{Simulator, SimulatorOptions, StepInfo},
+};
+
+use crate::{
+    data::{DatasetGeneratorConfig, PlayerModel},
+    metrics::Metrics,
+    model::{Model, ModelConfig},
+};
+
+#[derive(Debug, Config)]
+pub struct EvolutionConfig {
+    pub model: ModelConfig,
+    #[config(default = 50)]
+    pub population: usize,
+    #[config(default = 100)]
+    pub generations: usize,
+    /// Episodes every genome plays to get its fitness
+    #[config(default = 3)]
+    pub episodes: usize,
+    /// Step limit of an episode, unless the simulation config sets one
+    #[config(default = 1000)]
+    pub max_steps: usize,
+    #[config(default = 3)]
+    pub tournament_size: usize,
+    /// Best genomes copied unchanged into the next generation
+    #[config(default = 2)]
+    pub elites: usize,
+    #[config(default = 0.7)]
+    pub crossover_rate: f64,
+    /// Chance of every single weight to be mutated
+    #[config(default = 0.05)]
+    pub mutation_rate: f64,
+    #[config(default = 0.02)]
+    pub mutation_std: f32,
+    #[config(default = 1.0)]
+    pub apple_weight: f32,
+    #[config(default = 0.01)]
+    pub step_weight: f32,
+    #[config(default = 10.0)]
+    pub win_bonus: f32,
+    #[config(default = 42)]
+    pub seed: u64,
+}
+
+impl EvolutionConfig {
+    /// Catches settings the evolution loop cannot run with, before it starts
+    pub fn validate(&self) -> ARes<()> {
+        if self.population == 0 {
+            bail!("population should hold at least 1 genome");
+        }
+        if !(0. ..=1.).contains(&self.crossover_rate) {
+            bail!("crossover_rate should be a probability in [0, 1]");
+        }
+        if !(0. ..=1.).contains(&self.mutation_rate) {
+            bail!("mutation_rate should be a probability in [0, 1]");
+        }
+        Ok(())
+    }
+
+    fn fitness(&self, info: StepInfo) -> f32 {
+        let won = matches!(info.result, StepResult::Win { .. });
+        info.num_of_apples as f32 * self.apple_weight
+            + info.steps as f32 * self.step_weight
+            + if won { self.win_bonus } else { 0. }
+    }
+}
+
+/// Weights of a model flattened in parameter order, with how well they played
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Genome {
+    pub weights: Vec<f32>,
+    pub fitness: f32,
+}
+
+/// Everything evolved so far, written after every generation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Population {
+    pub generation: usize,
+    pub genomes: Vec<Genome>,
+    /// Seed the random generator restarted from before breeding the next generation
+    pub seed: u64,
+}
+
+impl Population {
+    pub fn save(&self, path: impl AsRef<Path>) -> ARes<()> {
+        fs::write(
+            path,
+            bincode::serde::encode_to_vec(self, bincode::config::standard())?,
+        )?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> ARes<Self> {
+        let (population, _) =
+            bincode::serde::decode_from_slice(&fs::read(path)?, bincode::config::standard())?;
+        Ok(population)
+    }
+
+    pub fn best(&self) -> Option<&Genome> {
+        self.genomes
+            .iter()
+            .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+    }
+}
+
+struct WeightReader {
+    weights: Vec<f32>,
+}
+
+impl<B: Backend> ModuleVisitor<B> for WeightReader {
+    fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+        self.weights.extend(param.val().into_data().iter::<f32>());
+    }
+}
+
+struct WeightWriter<'a> {
+    weights: &'a [f32],
+}
+
+impl<B: Backend> ModuleMapper<B> for WeightWriter<'_> {
+    fn map_float<const D: usize>(&mut self, param: Param<Tensor<B, D>>) -> Param<Tensor<B, D>> {
+        let (id, tensor, mapper) = param.consume();
+        let shape = tensor.shape();
+        let (head, rest) = self.weights.split_at(shape.num_elements());
+        self.weights = rest;
+        let tensor = Tensor::from_data(TensorData::new(head.to_vec(), shape), &tensor.device());
+        Param::from_mapped_value(id, tensor, mapper)
+    }
+}
+
+/// Every float parameter of `model`, flattened
+pub fn model_weights<B: Backend>(model: &Model<B>) -> Vec<f32> {
+    let mut reader = WeightReader { weights: vec![] };
+    model.visit(&mut reader);
+    reader.weights
+}
+
+/// Replaces the parameters of `model` with `weights`, as given by `model_weights`
+pub fn with_weights<B: Backend>(model: Model<B>, weights: &[f32]) -> Model<B> {
+    model.map(&mut WeightWriter { weights })
+}
+
+/// Standard normal sample, Box-Muller
+fn gaussian(rng: &mut impl Rng) -> f32 {
+    let u1 = rng.random::<f32>().max(f32::MIN_POSITIVE);
+    let u2 = rng.random::<f32>();
+    (-2. * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+fn tournament<'a>(genomes: &'a [Genome], size: usize, rng: &mut impl Rng) -> &'a Genome {
+    genomes
+        .sample(rng, size.max(1))
+        .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+        .expect("Population is not empty")
+}
+
+/// Picks a weight of either parent at random
+fn crossover(a: &[f32], b: &[f32], rng: &mut impl Rng) -> Vec<f32> {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| if rng.random_bool(0.5) { *a } else { *b })
+        .collect()
+}
+
+fn mutate(weights: &mut [f32], config: &EvolutionConfig, rng: &mut impl Rng) {
+    for w in weights.iter_mut() {
+        if rng.random_bool(config.mutation_rate) {
+            *w += gaussian(rng) * config.mutation_std;
+        }
+    }
+}
+
+/// Next generation, elites carried over and the rest bred from tournament winners
+fn breed(genomes: &[Genome], config: &EvolutionConfig, rng: &mut impl Rng) -> Vec<Genome> {
+    let mut next = genomes
+        .iter()
+        .sorted_by(|a, b| b.fitness.total_cmp(&a.fitness))
+        .take(config.elites)
+        .cloned()
+        .collect_vec();
+    while next.len() < config.population {
+        let first = tournament(genomes, config.tournament_size, rng);
+        let mut weights = if rng.random_bool(config.crossover_rate) {
+            let second = tournament(genomes, config.tournament_size, rng);
+            crossover(&first.weights, &second.weights, rng)
+        } else {
+            first.weights.clone()
+        };
+        mutate(&mut weights, config, rng);
+        next.push(Genome {
+            weights,
+            fitness: 0.,
+        });
+    }
+    next
+}
+
+/// Mean fitness of every genome, each one evaluated on its own thread.
+/// Every genome plays the same episodes, drawn from `seed`, a failed episode fails the run.
+fn evaluate<B: Backend>(
+    genomes: &mut [Genome],
+    config: &EvolutionConfig,
+    simulator: &Simulator,
+    observation: &ObservationConfig,
+    device: &B::Device,
+    seed: u64,
+) -> ARes<()> {
+    genomes.par_iter_mut().try_for_each(|genome| {
+        let model = with_weights(config.model.init::<B>(device), &genome.weights);
+        let player = PlayerModel::new(&model, device, observation);
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let total = (0..config.episodes)
+            .map(|_| {
+                let info = simulator.play_episode(&player, &mut rng)?;
+                Ok(config.fitness(info))
+            })
+            .sum::<ARes<f32>>()?;
+        genome.fitness = total / config.episodes.max(1) as f32;
+        Ok(())
+    })
+}
+
+/// Evolves a population of models on the CPU, saving the population and the best
+/// model after every generation. Resuming breeds on from the saved population.
+pub fn run<B: Backend>(
+    artifact_dir: &str,
+    device: B::Device,
+    dgb: DatasetGeneratorConfig,
+    mut config: EvolutionConfig,
+    resume: bool,
+) -> ARes<()> {
+    config.validate()?;
+    fs::create_dir_all(artifact_dir)?;
+    config.model = config.model.with_observation(&dgb.observation);
+    config.save(format!("{artifact_dir}evolution.json"))?;
+    config.model.save(format!("{artifact_dir}evo-model.json"))?;
+    let simulator = Simulator::new(
+        dgb.sim_config.game_builder(),
+        SimulatorOptions {
+            number_of_iterations: dgb.sim_config.episode_limit.unwrap_or(config.max_steps),
+        },
+    );
+    let mut rng = SmallRng::seed_from_u64(config.seed);
+    B::seed(&device, config.seed);
+
+    let (first, mut genomes) = if resume {
+        let path = format!("{artifact_dir}population.bin");
+        let population =
+            Population::load(&path).map_err(|e| anyhow!("Cannot resume from {path}: {e}"))?;
+        // Genomes of another model shape would not fit back into the networks
+        let expected = model_weights(&config.model.init::<B>(&device)).len();
+        let found = population.genomes.first().map(|g| g.weights.len());
+        if found != Some(expected) {
+            bail!(
+                "Population in {path} does not match the model, expected genomes of {expected} weights, found {found:?}"
+            );
+        }
+        rng = SmallRng::seed_from_u64(population.seed);
+        let genomes = breed(&population.genomes, &config, &mut rng);
+        (population.generation + 1, genomes)
+    } else {
+        let genomes = (0..config.population)
+            .map(|_| Genome {
+                weights: model_weights(&config.model.init::<B>(&device)),
+                fitness: 0.,
+            })
+            .collect_vec();
+        (1, genomes)
+    };
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    let resume_from = [("generation/", first as u64)];
+    let mut metrics = Metrics::named(
+        artifact_dir,
+        "evo-metrics",
+        resume.then_some(&resume_from[..]),
+    )?;
+
+    for generation in first..config.generations + 1 {
+        evaluate::<B>(
+            &mut genomes,
+            &config,
+            &simulator,
+            &dgb.observation,
+            &device,
+            rng.next_u64(),
+        )?;
+        // Restarting the generator from a saved seed lets a resumed run breed the same way
+        let seed = rng.next_u64();
+        rng = SmallRng::seed_from_u64(seed);
+        let population = Population {
+            generation,
+            genomes,
+            seed,
+        };
+        let best = population.best().expect("Population is not empty");
+        let mean = population.genomes.iter().map(|g| g.fitness).sum::<f32>()
+            / population.genomes.len() as f32;
+        println!(
+            "[Generation {}] Best fitness {:.3} Mean fitness {:.3}",
+            generation, best.fitness, mean
+        );
+        metrics.log(
+            generation as u64,
+            &[
+                ("generation/best_fitness", best.fitness as f64),
+                ("generation/mean_fitness", mean as f64),
+            ],
+        )?;
+
+        population.save(format!("{artifact_dir}population.bin"))?;
+        with_weights(config.model.init::<B>(&device), &best.weights)
+            .save_file(format!("{artifact_dir}evo-model.mpk"), &recorder)?;
+
+        genomes = breed(&population.genomes, &config, &mut rng);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use burn::backend::NdArray;
+
+    #[test]
+    fn weights_roundtrip_through_a_model() {
+        let device = Default::default();
+        let config = ModelConfig::new(4, 8);
+        let weights = model_weights(&config.init::<NdArray>(&device));
+        let shifted = weights.iter().map(|w| w + 1.).collect_vec();
+        let model = with_weights(config.init::<NdArray>(&device), &shifted);
+        assert_eq!(model_weights(&model), shifted);
+    }
+
+    #[test]
+    fn breeding_keeps_the_elites() {
+        let config = EvolutionConfig::new(ModelConfig::new(4, 8))
+            .with_population(6)
+            .with_elites(2);
+        let genomes = (0..6)
+            .map(|i| Genome {
+                weights: vec![i as f32; 4],
+                fitness: i as f32,
+            })
+            .collect_vec();
+        let next = breed(&genomes, &config, &mut SmallRng::seed_from_u64(1));
+        assert_eq!(next.len(), 6);
+        assert_eq!(next[0].weights, vec![5.; 4]);
+        assert_eq!(next[1].weights, vec![4.; 4]);
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let config = EvolutionConfig::new(ModelConfig::new(4, 8));
+        assert!(config.validate().is_ok());
+        assert!(config.clone().with_population(0).validate().is_err());
+        assert!(config.clone().with_crossover_rate(1.5).validate().is_err());
+        assert!(config.with_mutation_rate(-0.1).validate().is_err());
+    }
+}
@@ -1,3 +1,10 @@
+#![recursion_limit = "256"]
+pub mod checkpoint;
+pub mod cli;
 pub mod data;
+pub mod evolution;
+pub mod metrics;
 pub mod model;
+pub mod ppo;
+pub mod replay_buffer;
 pub mod training;
@@ -1,27 +1,8 @@
 #![recursion_limit = "256"]
-pub mod data;
-pub mod model;
-pub mod training;
-use crate::{data::DatasetGeneratorConfig, model::ModelConfig, training::TrainingConfig};
 use anyhow::Result as ARes;
-use burn::{
-    backend::{Autodiff, Wgpu, wgpu::WgpuDevice},
-    optim::AdamConfig,
-};
+use clap::Parser;
+use rl_evo_train::cli::Cli;
 
 fn main() -> ARes<()> {
-    type MyBackend = Wgpu<f32, i32>;
-    type MyAutodiffBackend = Autodiff<MyBackend>;
-
-    let device = WgpuDevice::default();
-    let artifact_dir = "/tmp/burn-tutorial/";
-    let dataset_cfg: DatasetGeneratorConfig =
-        serde_json::from_str(&std::fs::read_to_string("./config.json")?)?;
-
-    crate::training::run::<MyAutodiffBackend>(
-        artifact_dir,
-        device.clone(),
-        dataset_cfg,
-    );
-    Ok(())
+    Cli::parse().run()
 }
@@ -0,0 +1,410 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result as ARes;
+use serde_json::{Map, Value};
+
+/// Destination of the metrics of a run
+pub trait MetricsSink {
+    /// Records `values` by tag, all measured at `step`
+    fn write(&mut self, step: u64, wall_time: f64, values: &[(&str, f64)]) -> ARes<()>;
+}
+
+fn append(path: &Path) -> ARes<BufWriter<File>> {
+    Ok(BufWriter::new(
+        OpenOptions::new().create(true).append(true).open(path)?,
+    ))
+}
+
+/// One JSON object per line, holding the step, the time and every value by tag
+pub struct JsonlSink(BufWriter<File>);
+
+impl JsonlSink {
+    pub fn new(path: impl AsRef<Path>) -> ARes<Self> {
+        Ok(Self(append(path.as_ref())?))
+    }
+}
+
+impl MetricsSink for JsonlSink {
+    fn write(&mut self, step: u64, wall_time: f64, values: &[(&str, f64)]) -> ARes<()> {
+        let mut record = Map::new();
+        record.insert("step".into(), step.into());
+        record.insert("wall_time".into(), wall_time.into());
+        for (tag, value) in values {
+            record.insert(tag.to_string(), (*value).into());
+        }
+        writeln!(self.0, "{}", Value::Object(record))?;
+        Ok(self.0.flush()?)
+    }
+}
+
+/// Long format CSV, `step,wall_time,tag,value`, so that runs can log any tag
+pub struct CsvSink(BufWriter<File>);
+
+impl CsvSink {
+    pub fn new(path: impl AsRef<Path>) -> ARes<Self> {
+        let is_new = !path.as_ref().exists();
+        let mut file = append(path.as_ref())?;
+        if is_new {
+            writeln!(file, "step,wall_time,tag,value")?;
+        }
+        Ok(Self(file))
+    }
+}
+
+impl MetricsSink for CsvSink {
+    fn write(&mut self, step: u64, wall_time: f64, values: &[(&str, f64)]) -> ARes<()> {
+        for (tag, value) in values {
+            writeln!(self.0, "{step},{wall_time:.3},{tag},{value}")?;
+        }
+        Ok(self.0.flush()?)
+    }
+}
+
+/// CRC32C of `data`, as used by TFRecord files
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x82F6_3B78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xA282_EAD8)
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Length delimited protobuf field
+fn put_bytes(buf: &mut Vec<u8>, field: u8, bytes: &[u8]) {
+    buf.push(field << 3 | 2);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// TensorBoard event file, a TFRecord stream of `Event` protos holding scalar summaries
+pub struct TensorBoardSink(BufWriter<File>);
+
+impl TensorBoardSink {
+    /// New event file in `dir`, TensorBoard reads every `*tfevents*` file of a directory
+    pub fn new(dir: impl AsRef<Path>) -> ARes<Self> {
+        let wall_time = now();
+        let path = dir.as_ref().join(format!(
+            "events.out.tfevents.{}.rl-evo-train",
+            wall_time as u64
+        ));
+        let mut sink = Self(append(&path)?);
+        let mut event = Self::event(0, wall_time);
+        put_bytes(&mut event, 3, b"brain.Event:2");
+        sink.record(&event)?;
+        Ok(sink)
+    }
+
+    /// Event proto with its `wall_time` and `step` fields
+    fn event(step: u64, wall_time: f64) -> Vec<u8> {
+        let mut event = vec![1 << 3 | 1];
+        event.extend_from_slice(&wall_time.to_le_bytes());
+        event.push(2 << 3);
+        put_varint(&mut event, step);
+        event
+    }
+
+    fn record(&mut self, data: &[u8]) -> ARes<()> {
+        let len = (data.len() as u64).to_le_bytes();
+        self.0.write_all(&len)?;
+        self.0.write_all(&masked_crc32c(&len).to_le_bytes())?;
+        self.0.write_all(data)?;
+        self.0.write_all(&masked_crc32c(data).to_le_bytes())?;
+        Ok(self.0.flush()?)
+    }
+}
+
+impl MetricsSink for TensorBoardSink {
+    fn write(&mut self, step: u64, wall_time: f64, values: &[(&str, f64)]) -> ARes<()> {
+        let mut summary = vec![];
+        for (tag, value) in values {
+            let mut entry = vec![];
+            put_bytes(&mut entry, 1, tag.as_bytes());
+            entry.push(2 << 3 | 5);
+            entry.extend_from_slice(&(*value as f32).to_le_bytes());
+            put_bytes(&mut summary, 1, &entry);
+        }
+        let mut event = Self::event(step, wall_time);
+        put_bytes(&mut event, 5, &summary);
+        self.record(&event)
+    }
+}
+
+fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0., |d| d.as_secs_f64())
+}
+
+/// Step every tag prefix resumes logging from, as `("update/", 120)`. Trainers count
+/// episodes and updates apart, so each group of tags restarts at its own step.
+pub type ResumeFrom<'a> = &'a [(&'a str, u64)];
+
+/// Whether a row of `tag` at `step` was logged before the checkpoint resumed from,
+/// tags of no listed prefix are all kept
+fn before_resume(resume: ResumeFrom, tag: &str, step: u64) -> bool {
+    resume
+        .iter()
+        .find(|(prefix, _)| tag.starts_with(prefix))
+        .is_none_or(|(_, from)| step < *from)
+}
+
+/// Rewrites `path` with only the lines passing `keep`
+fn retain_lines(path: &Path, keep: impl Fn(&str) -> bool) -> ARes<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let kept = text
+        .lines()
+        .filter(|l| keep(l))
+        .map(|l| format!("{l}\n"))
+        .collect::<String>();
+    fs::write(path, kept)?;
+    Ok(())
+}
+
+/// Drops the records of a `metrics.jsonl` file logged after the checkpoint, a line
+/// cut short by a crash goes with them
+fn truncate_jsonl(path: &Path, resume: ResumeFrom) -> ARes<()> {
+    retain_lines(path, |line| {
+        let Ok(record) = serde_json::from_str::<Map<String, Value>>(line) else {
+            return false;
+        };
+        let Some(step) = record.get("step").and_then(Value::as_u64) else {
+            return false;
+        };
+        record
+            .keys()
+            .filter(|k| *k != "step" && *k != "wall_time")
+            .all(|tag| before_resume(resume, tag, step))
+    })
+}
+
+/// Drops the rows of a `metrics.csv` file logged after the checkpoint
+fn truncate_csv(path: &Path, resume: ResumeFrom) -> ARes<()> {
+    retain_lines(path, |line| {
+        let mut fields = line.splitn(4, ',');
+        let (Some(step), Some(_), Some(tag), Some(_)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return false;
+        };
+        match step.parse() {
+            Ok(step) => before_resume(resume, tag, step),
+            // The header
+            Err(_) => step == "step",
+        }
+    })
+}
+
+/// Scalar metrics of a run, written to every sink
+#[derive(Default)]
+pub struct Metrics {
+    sinks: Vec<Box<dyn MetricsSink>>,
+}
+
+impl Metrics {
+    /// `metrics.jsonl`, `metrics.csv` and a TensorBoard event file in `metrics/`.
+    /// A new run replaces the files of the last one. A resumed run first drops the
+    /// rows logged after its checkpoint, then appends to them. Event files are
+    /// append only, TensorBoard discards their orphaned steps itself once a tag
+    /// steps back.
+    pub fn in_dir(artifact_dir: impl AsRef<Path>, resume: Option<ResumeFrom>) -> ARes<Self> {
+        Self::named(artifact_dir, "metrics", resume)
+    }
+
+    /// Metrics under `name` rather than `metrics/`, for trainers sharing an
+    /// artifact dir
+    pub fn named(
+        artifact_dir: impl AsRef<Path>,
+        name: &str,
+        resume: Option<ResumeFrom>,
+    ) -> ARes<Self> {
+        let dir = &artifact_dir.as_ref().join(name);
+        fs::create_dir_all(dir)?;
+        let jsonl = dir.join("metrics.jsonl");
+        let csv = dir.join("metrics.csv");
+        match resume {
+            Some(resume) => {
+                truncate_jsonl(&jsonl, resume)?;
+                truncate_csv(&csv, resume)?;
+            }
+            None => {
+                fs::remove_file(&jsonl).ok();
+                fs::remove_file(&csv).ok();
+                for entry in fs::read_dir(dir)?.filter_map(|e| e.ok()) {
+                    if entry.file_name().to_string_lossy().contains("tfevents") {
+                        fs::remove_file(entry.path())?;
+                    }
+                }
+            }
+        }
+        Ok(Self::default()
+            .with_sink(JsonlSink::new(jsonl)?)
+            .with_sink(CsvSink::new(csv)?)
+            .with_sink(TensorBoardSink::new(dir)?))
+    }
+
+    pub fn with_sink(mut self, sink: impl MetricsSink + 'static) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Records `values` by tag, `update/loss` style tags group them in TensorBoard
+    pub fn log(&mut self, step: u64, values: &[(&str, f64)]) -> ARes<()> {
+        let wall_time = now();
+        for sink in self.sinks.iter_mut() {
+            sink.write(step, wall_time, values)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums_match_tfrecord() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        let mut buf = vec![];
+        put_varint(&mut buf, 300);
+        assert_eq!(buf, [0xAC, 0x02]);
+    }
+
+    #[test]
+    fn writes_every_format() {
+        let root = std::env::temp_dir().join(format!("metrics-{}", std::process::id()));
+        let mut metrics = Metrics::in_dir(&root, None).expect("Writable temp dir");
+        let dir = root.join("metrics");
+        metrics
+            .log(3, &[("train/loss", 0.5), ("train/epsilon", 0.1)])
+            .expect("Writable temp dir");
+        let jsonl = std::fs::read_to_string(dir.join("metrics.jsonl")).expect("Written");
+        let record: Value = serde_json::from_str(jsonl.trim()).expect("One JSON object");
+        assert_eq!(record["step"], 3);
+        assert_eq!(record["train/loss"], 0.5);
+        let csv = std::fs::read_to_string(dir.join("metrics.csv")).expect("Written");
+        assert_eq!(csv.lines().count(), 3);
+        assert!(
+            csv.lines()
+                .nth(1)
+                .is_some_and(|l| l.ends_with(",train/loss,0.5"))
+        );
+
+        let events = std::fs::read_dir(&dir)
+            .expect("Written")
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name().to_string_lossy().contains("tfevents"))
+            .expect("An event file");
+        let data = std::fs::read(events.path()).expect("Written");
+        // Two records, the file version then the scalars
+        let first = u64::from_le_bytes(data[..8].try_into().expect("Length")) as usize;
+        assert_eq!(
+            u32::from_le_bytes(data[8..12].try_into().expect("Crc")),
+            masked_crc32c(&data[..8])
+        );
+        let second = &data[16 + first..];
+        let len = u64::from_le_bytes(second[..8].try_into().expect("Length")) as usize;
+        assert_eq!(second.len(), 16 + len);
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn resumed_runs_replace_steps_after_the_checkpoint() {
+        let root = std::env::temp_dir().join(format!("metrics-resume-{}", std::process::id()));
+        let dir = root.join("metrics");
+        let mut metrics = Metrics::in_dir(&root, None).expect("Writable temp dir");
+        for step in 1..=5 {
+            metrics
+                .log(step, &[("episode/return", 1.)])
+                .expect("Writable temp dir");
+        }
+        metrics
+            .log(40, &[("update/loss", 1.)])
+            .expect("Writable temp dir");
+        drop(metrics);
+
+        // Resumed from a checkpoint after episode 3 and update 40
+        let resume = [("episode/", 4), ("update/", 41)];
+        let mut metrics = Metrics::in_dir(&root, Some(&resume)).expect("Writable temp dir");
+        for step in 4..=6 {
+            metrics
+                .log(step, &[("episode/return", 2.)])
+                .expect("Writable temp dir");
+        }
+        metrics
+            .log(41, &[("update/loss", 2.)])
+            .expect("Writable temp dir");
+        let returns = std::fs::read_to_string(dir.join("metrics.jsonl"))
+            .expect("Written")
+            .lines()
+            .filter_map(|l| serde_json::from_str::<Value>(l).ok())
+            .filter_map(|r| r.get("episode/return").and_then(Value::as_f64))
+            .collect::<Vec<_>>();
+        assert_eq!(returns, vec![1., 1., 1., 2., 2., 2.]);
+        let csv = std::fs::read_to_string(dir.join("metrics.csv")).expect("Written");
+        assert_eq!(csv.lines().count(), 1 + 6 + 2);
+
+        Metrics::in_dir(&root, None).expect("Writable temp dir");
+        let jsonl = std::fs::read_to_string(dir.join("metrics.jsonl")).expect("Written");
+        assert!(jsonl.is_empty());
+        std::fs::remove_dir_all(root).ok();
+    }
+
+    #[test]
+    fn trainers_sharing_a_dir_keep_their_own_metrics() {
+        let root = std::env::temp_dir().join(format!("metrics-shared-{}", std::process::id()));
+        let mut dqn = Metrics::in_dir(&root, None).expect("Writable temp dir");
+        dqn.log(50, &[("update/loss", 1.)])
+            .expect("Writable temp dir");
+        drop(dqn);
+        let mut ppo = Metrics::named(&root, "ppo-metrics", None).expect("Writable temp dir");
+        ppo.log(2, &[("update/loss", 2.)])
+            .expect("Writable temp dir");
+        drop(ppo);
+
+        // A resumed PPO run is not held back by the steps DQN reached
+        let mut ppo = Metrics::named(&root, "ppo-metrics", Some(&[("update/", 3)]))
+            .expect("Writable temp dir");
+        ppo.log(3, &[("update/loss", 3.)])
+            .expect("Writable temp dir");
+        let losses = |name: &str| {
+            std::fs::read_to_string(root.join(name).join("metrics.jsonl"))
+                .expect("Written")
+                .lines()
+                .filter_map(|l| serde_json::from_str::<Value>(l).ok())
+                .filter_map(|r| r.get("update/loss").and_then(Value::as_f64))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(losses("metrics"), vec![1.]);
+        assert_eq!(losses("ppo-metrics"), vec![2., 3.]);
+        std::fs::remove_dir_all(root).ok();
+    }
+}
@@ -1,15 +1,22 @@
+use std::path::Path;
+
+use anyhow::{Result as ARes, anyhow, bail};
 use burn::prelude::*;
 use burn::{
+    module::{ModuleVisitor, Param},
     nn::{
-        BatchNorm, BatchNormConfig, Dropout, DropoutConfig, Gelu, Linear, LinearConfig,
-        PaddingConfig2d, Relu, activation,
+        BatchNorm, Dropout, DropoutConfig, Gelu, Linear, LinearConfig, PaddingConfig2d,
         conv::{Conv2d, Conv2dConfig},
         pool::{AdaptiveAvgPool2d, AdaptiveAvgPool2dConfig, MaxPool2d, MaxPool2dConfig},
     },
-    tensor::{activation::gelu, backend::AutodiffBackend},
+    record::{FullPrecisionSettings, NamedMpkFileRecorder},
+    tensor::activation::gelu,
 };
-use itertools::Itertools;
-use snake_api_lib::common::{GRID_X, GRID_Y};
+use snake_api_lib::observation::ObservationConfig;
+
+// Side of the feature map fed to the linear layers, fixed so that neither the board
+// size nor its default changes the shape of the model
+const POOLED: usize = 3;
 
 #[derive(Debug, Module)]
 pub struct ResidualBlock<B: Backend, const D: usize> {
@@ -17,41 +24,37 @@ pub struct ResidualBlock<B: Backend, const D: usize> {
     pub norms: [BatchNorm<B>; D],
 }
 
+/// Convolutions turning a board into a fixed size feature map
 #[derive(Debug, Module)]
-pub struct Model<B: Backend> {
+pub struct ConvTrunk<B: Backend> {
     pool: MaxPool2d,
+    board_pool: AdaptiveAvgPool2d,
     dropout: Dropout,
     conv1: Conv2d<B>,
     conv1s: Conv2d<B>,
     conv2: Conv2d<B>,
     conv2s: Conv2d<B>,
     conv2ss: Conv2d<B>,
+    act: Gelu,
+}
+
+#[derive(Debug, Module)]
+pub struct Model<B: Backend> {
+    /// Left out for flat feature vectors, which go straight to the linear layers
+    trunk: Option<ConvTrunk<B>>,
+    dropout: Dropout,
     lin1: Linear<B>,
     lin2: Linear<B>,
-    act: Gelu,
 }
 
-impl<B: Backend, const D: usize> ResidualBlock<B, D> {
-    fn new([in_dim, middle_dim, out_dim]: [usize; 3], device: &B::Device) -> ResidualBlock<B, D> {
-        let c_start = Conv2dConfig::new([in_dim, middle_dim], [3, 3]);
-        let c_mid = Conv2dConfig::new([middle_dim, middle_dim], [3, 3]);
-        let c_end = Conv2dConfig::new([middle_dim, out_dim], [3, 3]);
-        todo!("Idk do this later");
-        // let net_configs: [Conv2d<B>; D] =
-        //     std::iter::once(Conv2dConfig::new([in_dim, middle_dim], [3, 3]))
-        //         .chain(
-        //             std::iter::repeat(Conv2dConfig::new([middle_dim, middle_dim], [3, 3]))
-        //                 .take(D - 2),
-        //         )
-        //         .chain(std::iter::once(Conv2dConfig::new(
-        //             [middle_dim, out_dim],
-        //             [3, 3],
-        //         )))
-        //         .map(|x| x.init(device))
-        //         .try_collect()
-        //         .expect("Finished");
-        // .concat([Conv2dConfig::new([in_dim, middle_dim], [3,3])]);
-    }
+/// Policy and value heads on top of the same layers as `Model`
+#[derive(Debug, Module)]
+pub struct ActorCritic<B: Backend> {
+    trunk: Option<ConvTrunk<B>>,
+    dropout: Dropout,
+    lin1: Linear<B>,
+    policy: Linear<B>,
+    value: Linear<B>,
 }
 
 #[derive(Debug, Config)]
@@ -60,12 +63,29 @@ pub struct ModelConfig {
     hidden_size: usize,
     #[config(default = "0.5")]
     dropout: f64,
+    /// Channels of the observations, see `ObservationConfig::channels`
+    #[config(default = 8)]
+    channels: usize,
+    /// Observations are boards rather than a flat vector of features
+    #[config(default = true)]
+    spatial: bool,
 }
 
 impl ModelConfig {
-    pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
-        Model {
-            conv1: Conv2dConfig::new([4, 8], [3, 3])
+    /// Sizes the input of the model for `observation`
+    pub fn with_observation(self, observation: &ObservationConfig) -> Self {
+        self.with_channels(observation.channels())
+            .with_spatial(observation.is_spatial())
+    }
+
+    /// Whether the model was sized for `observation`
+    pub fn fits(&self, observation: &ObservationConfig) -> bool {
+        self.channels == observation.channels() && self.spatial == observation.is_spatial()
+    }
+
+    fn init_trunk<B: Backend>(&self, device: &B::Device) -> ConvTrunk<B> {
+        ConvTrunk {
+            conv1: Conv2dConfig::new([self.channels, 8], [3, 3])
                 .with_padding(PaddingConfig2d::Same)
                 .init(device),
             conv1s: Conv2dConfig::new([8, 8], [3, 3])
@@ -81,37 +101,61 @@ impl ModelConfig {
                 .with_padding(PaddingConfig2d::Same)
                 .init(device),
             act: Gelu::new(),
-            lin1: LinearConfig::new(16 * (GRID_X / 4) * (GRID_Y / 4), self.hidden_size)
-                .init(device),
-            lin2: LinearConfig::new(self.hidden_size, self.num_classes).init(device),
             dropout: DropoutConfig::new(self.dropout).init(),
             pool: MaxPool2dConfig::new([2, 2]).init(),
+            board_pool: AdaptiveAvgPool2dConfig::new([POOLED, POOLED]).init(),
+        }
+    }
+
+    /// Optional trunk and the size of the features it outputs
+    fn init_features<B: Backend>(&self, device: &B::Device) -> (Option<ConvTrunk<B>>, usize) {
+        if self.spatial {
+            (Some(self.init_trunk(device)), 16 * POOLED * POOLED)
+        } else {
+            (None, self.channels)
+        }
+    }
+
+    pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
+        let (trunk, features) = self.init_features(device);
+        Model {
+            trunk,
+            lin1: LinearConfig::new(features, self.hidden_size).init(device),
+            lin2: LinearConfig::new(self.hidden_size, self.num_classes).init(device),
+            dropout: DropoutConfig::new(self.dropout).init(),
+        }
+    }
+
+    /// Policy over `num_classes` moves and a state value
+    pub fn init_actor_critic<B: Backend>(&self, device: &B::Device) -> ActorCritic<B> {
+        let (trunk, features) = self.init_features(device);
+        ActorCritic {
+            trunk,
+            lin1: LinearConfig::new(features, self.hidden_size).init(device),
+            policy: LinearConfig::new(self.hidden_size, self.num_classes).init(device),
+            value: LinearConfig::new(self.hidden_size, 1).init(device),
+            dropout: DropoutConfig::new(self.dropout).init(),
         }
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct StateRepr<B: Backend>(pub Tensor<B, 4>); // B R H [empty, snake head, snake body, food]
+pub struct StateRepr<B: Backend>(pub Tensor<B, 4>); // B R C [channels of the observation encoding]
 
-impl<B: Backend> Model<B> {
+impl<B: Backend> ConvTrunk<B> {
     /// #Shapes
-    /// - Images [batch_size, height, width]
-    /// - Output [batch_size, num_classes]
-    pub fn forward(&self, state: StateRepr<B>) -> Tensor<B, 2> {
-        let StateRepr(snapshot) = state;
-        // Create a channel at the second dimension
-
-        let [bdims, row, col, ch] = snapshot.dims();
+    /// - Boards [batch_size, channels, height, width]
+    /// - Output [batch_size, 16 * POOLED * POOLED]
+    fn forward(&self, x: Tensor<B, 4>) -> Tensor<B, 2> {
+        let [bdims, ..] = x.dims();
 
-        let x = snapshot.reshape([bdims, ch, row, col]);
-
-        let x = self.conv1.forward(x); // 8 16
+        let x = self.conv1.forward(x); // 8 channels, height x width
         let x = gelu(x);
         let x = self.dropout.forward(x);
         let x = self.conv1s.forward(x);
         let x = gelu(x);
 
-        let x = self.pool.forward(x); // 4 8
+        let x = self.pool.forward(x); // height - 1 x width - 1
 
         let x = self.conv2.forward(x);
         let x = gelu(x);
@@ -119,15 +163,151 @@ impl<B: Backend> Model<B> {
         let x = self.conv2s.forward(x);
         let x = gelu(x);
 
-        let x = self.pool.forward(x); // 2 4
+        let x = self.pool.forward(x); // 16 channels, height - 2 x width - 2
 
         let x = self.conv2ss.forward(x);
         let x = self.act.forward(x);
 
-        let x = x.reshape([bdims, 16 * (GRID_X / 4) * (GRID_Y / 4)]);
+        let x = self.board_pool.forward(x); // fixed POOLED x POOLED
+        x.reshape([bdims, 16 * POOLED * POOLED])
+    }
+}
+
+/// Flat features of the observations, through the trunk for boards
+fn features<B: Backend>(trunk: &Option<ConvTrunk<B>>, state: StateRepr<B>) -> Tensor<B, 2> {
+    let StateRepr(snapshot) = state;
+    let [bdims, row, col, ch] = snapshot.dims();
+    match trunk {
+        // Channels go to the second dimension
+        Some(trunk) => trunk.forward(snapshot.permute([0, 3, 1, 2])),
+        None => snapshot.reshape([bdims, row * col * ch]),
+    }
+}
+
+impl<B: Backend> Model<B> {
+    /// #Shapes
+    /// - Observations [batch_size, height, width, channels]
+    /// - Output [batch_size, num_classes]
+    ///
+    /// Boards of any size of at least 4x4 are accepted, the last feature map
+    /// is pooled to a fixed size before the linear layers. Feature vectors
+    /// come in as `[batch_size, 1, 1, features]`.
+    pub fn forward(&self, state: StateRepr<B>) -> Tensor<B, 2> {
+        let x = features(&self.trunk, state);
         let x = self.lin1.forward(x);
         let x = self.dropout.forward(x);
         let x = gelu(x);
         self.lin2.forward(x)
     }
 }
+
+/// Networks scoring the moves of a batch of boards, the best move scores highest
+pub trait Policy<B: Backend> {
+    /// #Shapes
+    /// - Observations [batch_size, height, width, channels]
+    /// - Output [batch_size, num_classes]
+    fn scores(&self, state: StateRepr<B>) -> Tensor<B, 2>;
+}
+
+impl<B: Backend> Policy<B> for Model<B> {
+    fn scores(&self, state: StateRepr<B>) -> Tensor<B, 2> {
+        self.forward(state)
+    }
+}
+
+impl<B: Backend> Policy<B> for ActorCritic<B> {
+    fn scores(&self, state: StateRepr<B>) -> Tensor<B, 2> {
+        self.forward(state).0
+    }
+}
+
+impl<B: Backend> ActorCritic<B> {
+    /// #Shapes
+    /// - Observations [batch_size, height, width, channels]
+    /// - Output logits [batch_size, num_classes] and values [batch_size]
+    pub fn forward(&self, state: StateRepr<B>) -> (Tensor<B, 2>, Tensor<B, 1>) {
+        let x = features(&self.trunk, state);
+        let x = self.lin1.forward(x);
+        let x = self.dropout.forward(x);
+        let x = gelu(x);
+        let value = self.value.forward(x.clone()).squeeze_dim::<1>(1);
+        (self.policy.forward(x), value)
+    }
+}
+
+/// Shapes of the float parameters of `module`, in the order they are visited
+fn param_shapes<B: Backend, M: Module<B>>(module: &M) -> Vec<Vec<usize>> {
+    struct Shapes(Vec<Vec<usize>>);
+
+    impl<B: Backend> ModuleVisitor<B> for Shapes {
+        fn visit_float<const D: usize>(&mut self, param: &Param<Tensor<B, D>>) {
+            self.0.push(param.lazy_shape().dims.to_vec());
+        }
+    }
+
+    let mut shapes = Shapes(vec![]);
+    module.visit(&mut shapes);
+    shapes.0
+}
+
+/// Loads the weights the trainers saved at `path` into `model`. Records load
+/// whatever their shapes, so weights saved for other sizes than the config of
+/// `model` are an error here rather than a panic on the first forward pass.
+pub fn load_weights<B: Backend, M: Module<B>>(
+    model: M,
+    path: &Path,
+    device: &B::Device,
+) -> ARes<M> {
+    let expected = param_shapes(&model);
+    let model = model
+        .load_file(
+            path.to_path_buf(),
+            &NamedMpkFileRecorder::<FullPrecisionSettings>::new(),
+            device,
+        )
+        .map_err(|e| anyhow!("Cannot load {}: {e}", path.display()))?;
+    if param_shapes(&model) != expected {
+        bail!("{} was saved for another model config", path.display());
+    }
+    Ok(model)
+}
+
+#[cfg(test)]
+mod tests {
+    use burn::backend::NdArray;
+
+    use super::*;
+
+    #[test]
+    fn rejects_weights_of_other_sizes() {
+        let dir = std::env::temp_dir().join(format!("weights-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("Writable temp dir");
+        let path = dir.join("model.mpk");
+        let device = Default::default();
+        ModelConfig::new(4, 16)
+            .init::<NdArray>(&device)
+            .save_file(
+                path.clone(),
+                &NamedMpkFileRecorder::<FullPrecisionSettings>::new(),
+            )
+            .expect("Writable temp dir");
+
+        assert!(
+            load_weights(
+                ModelConfig::new(4, 16).init::<NdArray>(&device),
+                &path,
+                &device
+            )
+            .is_ok()
+        );
+        assert!(
+            load_weights(
+                ModelConfig::new(4, 32).init::<NdArray>(&device),
+                &path,
+                &device
+            )
+            .is_err()
+        );
+        std::fs::remove_dir_all(dir).ok();
+    }
+}
@@ -0,0 +1,552 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result as ARes, anyhow, bail};
+use burn::{
+    module::AutodiffModule,
+    optim::{AdamConfig, GradientsParams, Optimizer},
+    prelude::*,
+    record::{FullPrecisionSettings, NamedMpkFileRecorder, Recorder},
+    tensor::{activation::log_softmax, backend::AutodiffBackend},
+};
+use itertools::Itertools;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use snake_api_lib::{
+    common::Direction,
+    observation::{FrameStack, Observation},
+    vec_env::VecEnv,
+};
+
+use crate::{
+    checkpoint::{CheckpointEntry, Checkpoints},
+    data::DatasetGeneratorConfig,
+    metrics::Metrics,
+    model::{ActorCritic, ModelConfig, StateRepr, load_weights},
+};
+
+#[derive(Debug, Config)]
+pub struct PpoConfig {
+    pub model: ModelConfig,
+    pub optimizer: AdamConfig,
+    /// Rollout and update rounds
+    #[config(default = 200)]
+    pub iterations: usize,
+    /// Steps every game plays between two updates
+    #[config(default = 128)]
+    pub horizon: usize,
+    /// Passes over each rollout
+    #[config(default = 4)]
+    pub num_epochs: usize,
+    #[config(default = 256)]
+    pub minibatch_size: usize,
+    /// Step limit of an episode, unless the simulation config sets one
+    #[config(default = 1000)]
+    pub max_steps: usize,
+    #[config(default = 0.95)]
+    pub gae_lambda: f32,
+    /// How far the probability ratio may move from one before it stops counting
+    #[config(default = 0.2)]
+    pub clip: f32,
+    #[config(default = 0.5)]
+    pub value_coef: f32,
+    #[config(default = 0.01)]
+    pub entropy_coef: f32,
+    #[config(default = 3e-4)]
+    pub learning_rate: f64,
+    #[config(default = 42)]
+    pub seed: u64,
+    /// Best checkpoints kept besides the latest one, scored on the mean return of
+    /// their iteration
+    #[config(default = 3)]
+    pub keep_best: usize,
+}
+
+impl PpoConfig {
+    /// Catches settings the training loop cannot run with, before it starts
+    pub fn validate(&self) -> ARes<()> {
+        if self.horizon == 0 {
+            bail!("horizon should be at least 1 step");
+        }
+        if self.num_epochs == 0 {
+            bail!("num_epochs should be at least 1 pass");
+        }
+        if self.minibatch_size == 0 {
+            bail!("minibatch_size should be at least 1 step");
+        }
+        if self.clip <= 0. {
+            bail!("clip should be above 0");
+        }
+        Ok(())
+    }
+}
+
+/// Everything besides the network needed to pick a run up where it stopped.
+/// Games in progress are not kept, a resumed run starts new ones.
+#[derive(Debug, Serialize, Deserialize)]
+struct PpoState {
+    iteration: usize,
+    updates: u64,
+    /// Seed the random generator and the backend restarted from when the checkpoint
+    /// was written
+    seed: u64,
+}
+
+/// Files of a checkpoint only needed to resume from it
+const RESUME_FILES: [&str; 2] = ["optim.mpk", "state.bin"];
+
+fn save_checkpoint<B: AutodiffBackend, O: Optimizer<ActorCritic<B>, B>>(
+    dir: &Path,
+    config: &PpoConfig,
+    model: &ActorCritic<B>,
+    optim: &O,
+    state: PpoState,
+) -> ARes<()> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    config.model.save(dir.join("model.json"))?;
+    model.clone().save_file(dir.join("model"), &recorder)?;
+    Recorder::<B>::record(&recorder, optim.to_record(), dir.join("optim"))?;
+    let state = bincode::serde::encode_to_vec(&state, bincode::config::standard())?;
+    fs::write(dir.join("state.bin"), state)?;
+    Ok(())
+}
+
+/// Loads the network saved by `save_checkpoint` in place, returns the optimizer and state
+fn load_checkpoint<B: AutodiffBackend, O: Optimizer<ActorCritic<B>, B>>(
+    dir: &Path,
+    model: &mut ActorCritic<B>,
+    optim: O,
+    device: &B::Device,
+) -> ARes<(O, PpoState)> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    *model = load_weights(model.clone(), &dir.join("model.mpk"), device)?;
+    let optim = optim.load_record(Recorder::<B>::load(&recorder, dir.join("optim"), device)?);
+    let (state, _) = bincode::serde::decode_from_slice(
+        &fs::read(dir.join("state.bin"))?,
+        bincode::config::standard(),
+    )?;
+    Ok((optim, state))
+}
+
+/// Steps of every game, step major, `index = step * num_envs + env`
+#[derive(Debug, Default)]
+struct Rollout {
+    observations: Vec<Observation>,
+    masks: Vec<[bool; 4]>,
+    actions: Vec<i32>,
+    log_probs: Vec<f32>,
+    values: Vec<f32>,
+    rewards: Vec<f32>,
+    /// Whether the step ended the episode, truncated ones included
+    dones: Vec<bool>,
+    /// Value an ending step bootstraps from, the value of the state a truncated game
+    /// stopped on, nothing after a real end
+    bootstraps: Vec<f32>,
+    advantages: Vec<f32>,
+    returns: Vec<f32>,
+}
+
+/// Logits of illegal moves pushed out of reach. Boards without a safe move keep all four.
+fn masked_logits<B: Backend>(
+    logits: Tensor<B, 2>,
+    masks: &[[bool; 4]],
+    device: &B::Device,
+) -> Tensor<B, 2> {
+    let batch = masks.len();
+    let masks = masks
+        .iter()
+        .map(|m| if m.contains(&true) { *m } else { [true; 4] })
+        .collect_vec();
+    let m = Tensor::<B, 2, Bool>::from_data(TensorData::new(masks.concat(), [batch, 4]), device);
+    logits.mask_fill(m.bool_not(), -1e9)
+}
+
+/// Draws a move from log probabilities
+fn sample_action(log_probs: &[f32], rng: &mut impl Rng) -> usize {
+    let mut mass = rng.random::<f32>();
+    for (action, lp) in log_probs.iter().enumerate() {
+        mass -= lp.exp();
+        if mass < 0. {
+            return action;
+        }
+    }
+    // Rounding left a little mass over, take the likeliest move
+    log_probs
+        .iter()
+        .position_max_by(|a, b| a.total_cmp(b))
+        .unwrap_or(0)
+}
+
+/// Generalized advantage estimates and returns of a step major rollout.
+/// `last_values` bootstraps the games still running at the end of it, `bootstraps`
+/// the steps that ended an episode.
+fn gae(
+    rewards: &[f32],
+    values: &[f32],
+    dones: &[bool],
+    bootstraps: &[f32],
+    last_values: &[f32],
+    gamma: f32,
+    lambda: f32,
+) -> (Vec<f32>, Vec<f32>) {
+    let num_envs = last_values.len();
+    let mut advantages = vec![0.; rewards.len()];
+    let mut next_adv = vec![0.; num_envs];
+    let mut next_value = last_values.to_vec();
+    for indx in (0..rewards.len()).rev() {
+        let env = indx % num_envs;
+        let live = if dones[indx] { 0. } else { 1. };
+        let next = if dones[indx] {
+            bootstraps[indx]
+        } else {
+            next_value[env]
+        };
+        let delta = rewards[indx] + gamma * next - values[indx];
+        next_adv[env] = delta + gamma * lambda * live * next_adv[env];
+        next_value[env] = values[indx];
+        advantages[indx] = next_adv[env];
+    }
+    let returns = advantages.iter().zip(values).map(|(a, v)| a + v).collect();
+    (advantages, returns)
+}
+
+/// Runs every game for `horizon` steps sampling moves from the policy.
+/// Finished games restart right away, their returns go to `episode_returns`.
+/// `vec_env` does not reset on its own, truncated games are valued before they restart.
+#[allow(clippy::too_many_arguments)]
+fn collect_rollout<B: Backend>(
+    model: &ActorCritic<B>,
+    device: &B::Device,
+    vec_env: &mut VecEnv,
+    stacks: &mut [FrameStack],
+    running: &mut [f32],
+    episode_returns: &mut Vec<f32>,
+    config: &PpoConfig,
+    gamma: f32,
+    rng: &mut impl Rng,
+) -> ARes<Rollout> {
+    let mut rollout = Rollout::default();
+    for _ in 0..config.horizon {
+        let observations = stacks.iter().map(FrameStack::observation).collect_vec();
+        let masks = vec_env.action_masks();
+        let (logits, values) = model.forward(StateRepr::exact(&observations, device));
+        let log_probs = log_softmax(masked_logits(logits, &masks, device), 1)
+            .into_data()
+            .iter::<f32>()
+            .collect_vec();
+        let actions = log_probs
+            .chunks(4)
+            .map(|lp| sample_action(lp, rng))
+            .collect_vec();
+        let dirs = actions.iter().map(|a| Direction::from(*a)).collect_vec();
+        let steps = vec_env.step(&dirs)?;
+        let mut truncated = vec![];
+        for (indx, step) in steps.into_iter().enumerate() {
+            let step = step.expect("Finished games restart before the next step");
+            let done = step.terminated || step.truncated;
+            running[indx] += step.reward;
+            stacks[indx].push(vec_env.game(indx));
+            if done {
+                episode_returns.push(running[indx]);
+                running[indx] = 0.;
+            }
+            if step.truncated && !step.terminated {
+                truncated.push(indx);
+            }
+            rollout.rewards.push(step.reward);
+            rollout.dones.push(done);
+            rollout.bootstraps.push(0.);
+            rollout.log_probs.push(log_probs[4 * indx + actions[indx]]);
+        }
+        // Running out of steps is no death, those games still had a future
+        if !truncated.is_empty() {
+            let last = truncated
+                .iter()
+                .map(|indx| stacks[*indx].observation())
+                .collect_vec();
+            let (_, last_values) = model.forward(StateRepr::exact(&last, device));
+            let first = rollout.bootstraps.len() - stacks.len();
+            for (indx, value) in truncated.iter().zip(last_values.into_data().iter::<f32>()) {
+                rollout.bootstraps[first + indx] = value;
+            }
+        }
+        for (indx, stack) in stacks.iter_mut().enumerate() {
+            if vec_env.is_done(indx) {
+                vec_env.reset_game(indx);
+                stack.reset(vec_env.game(indx));
+            }
+        }
+        rollout.observations.extend(observations);
+        rollout.masks.extend(masks);
+        rollout.actions.extend(actions.iter().map(|a| *a as i32));
+        rollout.values.extend(values.into_data().iter::<f32>());
+    }
+    let observations = stacks.iter().map(FrameStack::observation).collect_vec();
+    let (_, last_values) = model.forward(StateRepr::exact(&observations, device));
+    let last_values = last_values.into_data().iter::<f32>().collect_vec();
+    (rollout.advantages, rollout.returns) = gae(
+        &rollout.rewards,
+        &rollout.values,
+        &rollout.dones,
+        &rollout.bootstraps,
+        &last_values,
+        gamma,
+        config.gae_lambda,
+    );
+    Ok(rollout)
+}
+
+/// Clipped surrogate loss plus the value loss, minus the entropy bonus
+fn ppo_loss<B: AutodiffBackend>(
+    model: &ActorCritic<B>,
+    rollout: &Rollout,
+    indices: &[usize],
+    config: &PpoConfig,
+    device: &B::Device,
+) -> Tensor<B, 1> {
+    let batch = indices.len();
+    let pick = |v: &[f32]| {
+        let data = indices.iter().map(|i| v[*i]).collect_vec();
+        Tensor::<B, 1>::from_data(TensorData::new(data, [batch]), device)
+    };
+    let observations = indices
+        .iter()
+        .map(|i| rollout.observations[*i].clone())
+        .collect_vec();
+    let masks = indices.iter().map(|i| rollout.masks[*i]).collect_vec();
+    let actions = indices.iter().map(|i| rollout.actions[*i]).collect_vec();
+    let actions = Tensor::<B, 1, Int>::from_data(TensorData::new(actions, [batch]), device);
+    let old_log_probs = pick(&rollout.log_probs);
+    let returns = pick(&rollout.returns);
+    let advantages = pick(&rollout.advantages);
+
+    let (logits, values) = model.forward(StateRepr::exact(&observations, device));
+    let log_probs = log_softmax(masked_logits(logits, &masks, device), 1);
+    let new_log_probs: Tensor<B, 1> = log_probs
+        .clone()
+        .gather(1, actions.unsqueeze_dim(1))
+        .squeeze_dim::<1>(1);
+    let ratio = (new_log_probs - old_log_probs).exp();
+    let clipped = ratio.clone().clamp(1. - config.clip, 1. + config.clip) * advantages.clone();
+    let policy_loss = (ratio * advantages).min_pair(clipped).mean().neg();
+    let value_loss = (values - returns).powi_scalar(2).mean();
+    let entropy = (log_probs.clone().exp() * log_probs)
+        .sum_dim(1)
+        .mean()
+        .neg();
+    policy_loss + value_loss.mul_scalar(config.value_coef) - entropy.mul_scalar(config.entropy_coef)
+}
+
+/// Trains an actor critic with PPO, games of `sim_config` played side by side.
+/// Checkpoints every iteration, resuming continues from the latest one.
+pub fn run<B: AutodiffBackend>(
+    artifact_dir: &str,
+    device: B::Device,
+    dgb: DatasetGeneratorConfig,
+    mut config: PpoConfig,
+    resume: bool,
+) -> ARes<()> {
+    config.validate()?;
+    fs::create_dir_all(artifact_dir)?;
+    // No dropout, the policy has to match the one that played the rollout. The
+    // forward passes skip the input noise for the same reason.
+    config.model = config
+        .model
+        .with_observation(&dgb.observation)
+        .with_dropout(0.);
+    config.save(format!("{artifact_dir}ppo.json"))?;
+    config.model.save(format!("{artifact_dir}ppo-model.json"))?;
+    let gamma = dgb.rew_config.gamma_factor;
+    let sim_config = &dgb.sim_config;
+
+    B::seed(&device, config.seed);
+    let mut rng = SmallRng::seed_from_u64(config.seed);
+
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    let checkpoints = Checkpoints::named(artifact_dir, "ppo-checkpoints", config.keep_best)
+        .with_resume_only(&RESUME_FILES);
+    let mut model: ActorCritic<B> = config.model.init_actor_critic(&device);
+    let mut optim = config.optimizer.init();
+    let mut updates = 0;
+    let mut first = 1;
+    if resume {
+        let latest = checkpoints
+            .latest()?
+            .ok_or_else(|| anyhow!("No PPO checkpoint to resume from in {artifact_dir}"))?;
+        let state;
+        (optim, state) = load_checkpoint(
+            &checkpoints.path(latest.episode)?,
+            &mut model,
+            optim,
+            &device,
+        )?;
+        B::seed(&device, state.seed);
+        rng = SmallRng::seed_from_u64(state.seed);
+        updates = state.updates;
+        first = state.iteration + 1;
+        println!("Resuming after iteration {}", state.iteration);
+    } else {
+        fs::remove_dir_all(format!("{artifact_dir}ppo-checkpoints")).ok();
+    }
+    // Update rows count from one, the next update logs at `updates + 1`
+    let resume_from = [("update/", updates + 1), ("iteration/", first as u64)];
+    let mut metrics = Metrics::named(
+        artifact_dir,
+        "ppo-metrics",
+        resume.then_some(&resume_from[..]),
+    )?;
+    let mut vec_env = VecEnv::new(
+        sim_config.game_builder(),
+        Some(sim_config.episode_limit.unwrap_or(config.max_steps)),
+        sim_config.number_episodes,
+        rng.next_u64(),
+    )
+    .with_auto_reset(false)
+    .with_reward_fn(dgb.rew_config.reward_fn.clone());
+    let mut stacks = vec_env
+        .games()
+        .map(|game| {
+            let mut stack = dgb.observation.stack();
+            stack.reset(game);
+            stack
+        })
+        .collect_vec();
+    let mut running = vec![0.; vec_env.len()];
+
+    for iteration in first..config.iterations + 1 {
+        let mut episode_returns = vec![];
+        let mut rollout = collect_rollout(
+            &model.valid(),
+            &device,
+            &mut vec_env,
+            &mut stacks,
+            &mut running,
+            &mut episode_returns,
+            &config,
+            gamma,
+            &mut rng,
+        )?;
+        // Normalised advantages, the scale of the rewards does not matter
+        let n = rollout.advantages.len() as f32;
+        let mean = rollout.advantages.iter().sum::<f32>() / n;
+        let std = (rollout
+            .advantages
+            .iter()
+            .map(|a| (a - mean).powi(2))
+            .sum::<f32>()
+            / n)
+            .sqrt();
+        for a in rollout.advantages.iter_mut() {
+            *a = (*a - mean) / (std + 1e-8);
+        }
+
+        let mut indices = (0..rollout.actions.len()).collect_vec();
+        for _ in 0..config.num_epochs {
+            indices.shuffle(&mut rng);
+            for chunk in indices.chunks(config.minibatch_size) {
+                let loss = ppo_loss(&model, &rollout, chunk, &config, &device);
+                let loss_value = loss.clone().into_scalar().elem::<f64>();
+                updates += 1;
+                metrics.log(updates, &[("update/loss", loss_value)])?;
+                let grads = GradientsParams::from_grads(loss.backward(), &model);
+                model = optim.step(config.learning_rate, model, grads);
+            }
+        }
+
+        let mean_return = (!episode_returns.is_empty())
+            .then(|| episode_returns.iter().sum::<f32>() / episode_returns.len() as f32);
+        if let Some(mean_return) = mean_return {
+            println!(
+                "[Iteration {}] Episodes {} Mean return {:.3}",
+                iteration,
+                episode_returns.len(),
+                mean_return
+            );
+            metrics.log(
+                iteration as u64,
+                &[
+                    ("iteration/return", mean_return as f64),
+                    ("iteration/episodes", episode_returns.len() as f64),
+                ],
+            )?;
+        }
+
+        // Restarting the generators from a saved seed lets a resumed run draw the same numbers
+        let seed = rng.next_u64();
+        rng = SmallRng::seed_from_u64(seed);
+        B::seed(&device, seed);
+        let state = PpoState {
+            iteration,
+            updates,
+            seed,
+        };
+        save_checkpoint(
+            &checkpoints.path(iteration)?,
+            &config,
+            &model,
+            &optim,
+            state,
+        )?;
+        checkpoints.commit(CheckpointEntry {
+            episode: iteration,
+            score: mean_return.unwrap_or(f32::MIN),
+        })?;
+        model
+            .clone()
+            .save_file(format!("{artifact_dir}ppo-model.mpk"), &recorder)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advantages_stop_at_episode_ends() {
+        // Two games, the first one ends on its first step
+        let rewards = [1., 0., 0., 1.];
+        let values = [0.5, 0.5, 0.5, 0.5];
+        let dones = [true, false, false, false];
+        let (advantages, returns) = gae(&rewards, &values, &dones, &[0.; 4], &[2., 2.], 0.5, 1.);
+        assert_eq!(advantages[0], 0.5);
+        assert_eq!(advantages[3], 1. + 0.5 * 2. - 0.5);
+        assert_eq!(advantages[1], -0.5 + 0.5 * 0.5 + 0.5 * advantages[3]);
+        assert_eq!(returns[0], 1.);
+    }
+
+    #[test]
+    fn truncated_episodes_bootstrap() {
+        // An episode cut short by the step limit, then one that died
+        let rewards = [1., 1.];
+        let values = [0.5, 0.5];
+        let dones = [true, true];
+        let (truncated, _) = gae(&rewards, &values, &dones, &[2., 0.], &[0.], 0.5, 1.);
+        assert_eq!(truncated, vec![1. + 0.5 * 2. - 0.5, 0.5]);
+    }
+
+    #[test]
+    fn samples_only_legal_moves() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let log_probs = [
+            f32::NEG_INFINITY,
+            0.3f32.ln(),
+            f32::NEG_INFINITY,
+            0.7f32.ln(),
+        ];
+        let picks = (0..200)
+            .map(|_| sample_action(&log_probs, &mut rng))
+            .collect_vec();
+        assert!(picks.iter().all(|a| *a == 1 || *a == 3));
+        assert!(picks.contains(&1) && picks.contains(&3));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let config = PpoConfig::new(ModelConfig::new(4, 8), AdamConfig::new());
+        assert!(config.validate().is_ok());
+        assert!(config.clone().with_horizon(0).validate().is_err());
+        assert!(config.clone().with_num_epochs(0).validate().is_err());
+        assert!(config.clone().with_minibatch_size(0).validate().is_err());
+        assert!(config.with_clip(0.).validate().is_err());
+    }
+}
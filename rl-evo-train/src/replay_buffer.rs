@@ -0,0 +1,175 @@
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Binary tree over the priorities, every node holds the sum of its children
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SumTree {
+    nodes: Vec<f64>,
+    leaves: usize,
+}
+
+impl SumTree {
+    fn new(capacity: usize) -> Self {
+        let leaves = capacity.next_power_of_two();
+        Self {
+            nodes: vec![0.; 2 * leaves],
+            leaves,
+        }
+    }
+
+    fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    fn get(&self, indx: usize) -> f64 {
+        self.nodes[indx + self.leaves]
+    }
+
+    fn set(&mut self, indx: usize, priority: f64) {
+        let mut node = indx + self.leaves;
+        self.nodes[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// Leaf where the running sum of priorities goes past `mass`
+    fn find(&self, mut mass: f64) -> usize {
+        let mut node = 1;
+        while node < self.leaves {
+            let left = self.nodes[2 * node];
+            if mass < left {
+                node *= 2;
+            } else {
+                mass -= left;
+                node = 2 * node + 1;
+            }
+        }
+        node - self.leaves
+    }
+}
+
+/// Items drawn by `PrioritizedReplayBuffer::sample`
+#[derive(Debug)]
+pub struct PrioritizedSample<'a, T> {
+    /// Slots of the items, to give back to `update_priorities`
+    pub indices: Vec<usize>,
+    pub items: Vec<&'a T>,
+    /// Importance sampling weights, the largest one is one
+    pub weights: Vec<f32>,
+}
+
+/// Fixed size replay memory sampling items by priority, the oldest items are
+/// overwritten once full. New items get the highest priority seen so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrioritizedReplayBuffer<T> {
+    items: Vec<T>,
+    next: usize,
+    capacity: usize,
+    tree: SumTree,
+    max_priority: f64,
+    /// How much priorities matter, zero samples uniformly
+    alpha: f64,
+}
+
+impl<T> PrioritizedReplayBuffer<T> {
+    /// Smallest priority, so that every item can still be drawn
+    const MIN_PRIORITY: f64 = 1e-3;
+
+    pub fn new(capacity: usize, alpha: f64) -> Self {
+        assert!(capacity > 0, "Replay buffer needs room for an item");
+        Self {
+            items: Vec::with_capacity(capacity),
+            next: 0,
+            capacity,
+            tree: SumTree::new(capacity),
+            max_priority: 1.,
+            alpha,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn push(&mut self, item: T) {
+        if self.items.len() < self.capacity {
+            self.items.push(item);
+        } else {
+            self.items[self.next] = item;
+        }
+        self.tree.set(self.next, self.max_priority);
+        self.next = (self.next + 1) % self.capacity;
+    }
+
+    /// Draws `batch` items, one from each equal share of the total priority.
+    /// `beta` sets how much the weights correct for the skewed sampling.
+    pub fn sample(&self, batch: usize, beta: f64, rng: &mut impl Rng) -> PrioritizedSample<'_, T> {
+        assert!(!self.is_empty(), "Cannot sample an empty replay buffer");
+        let total = self.tree.total();
+        let segment = total / batch as f64;
+        let indices = (0..batch)
+            .map(|i| {
+                let mass = (i as f64 + rng.random::<f64>()) * segment;
+                self.tree.find(mass).min(self.items.len() - 1)
+            })
+            .collect::<Vec<_>>();
+        let weights = indices
+            .iter()
+            .map(|indx| (self.len() as f64 * self.tree.get(*indx) / total).powf(-beta))
+            .collect::<Vec<_>>();
+        let max_weight = weights.iter().copied().fold(f64::MIN_POSITIVE, f64::max);
+        PrioritizedSample {
+            items: indices.iter().map(|indx| &self.items[*indx]).collect(),
+            weights: weights.iter().map(|w| (w / max_weight) as f32).collect(),
+            indices,
+        }
+    }
+
+    /// Sets the priorities of sampled items from their TD errors
+    pub fn update_priorities(&mut self, indices: &[usize], td_errors: &[f32]) {
+        for (indx, err) in indices.iter().zip(td_errors) {
+            let priority = (err.abs() as f64).max(Self::MIN_PRIORITY).powf(self.alpha);
+            self.max_priority = self.max_priority.max(priority);
+            self.tree.set(*indx, priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwrites_the_oldest_items() {
+        let mut buffer = PrioritizedReplayBuffer::new(3, 0.6);
+        for i in 0..5 {
+            buffer.push(i);
+        }
+        assert_eq!(buffer.len(), 3);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let sample = buffer.sample(30, 0.4, &mut rng);
+        assert!(sample.items.iter().all(|i| (2..5).contains(*i)));
+        assert!(sample.weights.iter().all(|w| *w == 1.));
+    }
+
+    #[test]
+    fn favours_high_priorities() {
+        let mut buffer = PrioritizedReplayBuffer::new(5, 1.);
+        for i in 0..5 {
+            buffer.push(i);
+        }
+        buffer.update_priorities(&[0, 1, 2, 3, 4], &[0., 0., 0., 0., 100.]);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let sample = buffer.sample(100, 1., &mut rng);
+        let hits = sample.items.iter().filter(|i| ***i == 4).count();
+        assert!(hits > 90, "Drew the top item {hits} times");
+        let rare = sample.indices.iter().position(|i| *i != 4);
+        assert!(rare.is_none_or(|r| sample.weights[r] == 1.));
+    }
+}
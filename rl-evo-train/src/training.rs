@@ -1,26 +1,28 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
+
+use anyhow::{Result as ARes, anyhow, bail};
 use burn::{
-    data::{
-        dataloader::{self, DataLoaderBuilder},
-        dataset::vision::MnistDataset,
-    },
     module::AutodiffModule,
-    nn::loss::{self, CrossEntropyLoss, CrossEntropyLossConfig, HuberLossConfig},
     optim::{AdamConfig, GradientsParams, Optimizer},
     prelude::*,
-    record::{CompactRecorder, FullPrecisionSettings, NamedMpkFileRecorder},
+    record::{FullPrecisionSettings, NamedMpkFileRecorder, Recorder},
     tensor::backend::AutodiffBackend,
-    train::{
-        ClassificationOutput, LearnerBuilder, RegressionOutput, TrainOutput, TrainStep, ValidStep,
-        metric::{AccuracyMetric, LossMetric},
-    },
 };
 
 use rand::prelude::*;
-use tqdm::Iter;
+use serde::{Deserialize, Serialize};
+use snake_api_lib::{observation::Observation, simulator::SimulationStep};
 
 use crate::{
-    data::{BatchedSimulationStep, DatasetGenerator, DatasetGeneratorConfig},
-    model::{Model, ModelConfig, StateRepr},
+    checkpoint::{CheckpointEntry, Checkpoints},
+    data::{BatchedSimulationStep, DatasetGeneratorConfig, GeneratedSims, PlayerModel},
+    metrics::Metrics,
+    model::{Model, ModelConfig, StateRepr, load_weights},
+    replay_buffer::PrioritizedReplayBuffer,
 };
 
 #[derive(Debug, Config)]
@@ -30,6 +32,7 @@ pub struct TrainingConfig {
     // #[config(default = 100)]
     #[config(default = 100)]
     pub num_episodes: usize,
+    /// Updates per batch worth of fresh transitions
     #[config(default = 5)]
     pub num_epochs: usize,
     #[config(default = 64)]
@@ -40,101 +43,376 @@ pub struct TrainingConfig {
     pub seed: u64,
     #[config(default = 1e-4)]
     pub learning_rate: f64,
+    /// Transitions kept in the replay buffer
+    #[config(default = 50000)]
+    pub buffer_capacity: usize,
+    /// Transitions collected before the first update
+    #[config(default = 1000)]
+    pub warmup: usize,
+    /// Updates between two copies of the online network into the target network
+    #[config(default = 250)]
+    pub target_sync: usize,
+    #[config(default = 0.6)]
+    pub priority_alpha: f64,
+    /// Importance sampling exponent, annealed up to one by the last episode
+    #[config(default = 0.4)]
+    pub priority_beta: f64,
+    /// Episodes between two checkpoints, the last episode is always saved
+    #[config(default = 10)]
+    pub checkpoint_every: usize,
+    /// Checkpoints between two saves of the replay buffer, 0 never saves it. A save
+    /// writes every transition with both its observations, about 460 MB for 50000
+    /// transitions of 12x12 one hot boards, times the stacked frames. Without one a
+    /// resumed run refills the buffer from scratch.
+    #[config(default = 10)]
+    pub buffer_checkpoint_every: usize,
+    /// Benchmark games played after every episode, checkpoints are scored on them
+    #[config(default = 20)]
+    pub eval_episodes: usize,
+    /// Best scored checkpoints kept besides the latest one
+    #[config(default = 3)]
+    pub keep_best: usize,
 }
 
-fn create_artifact_dir(artifact_dir: &str) {
-    std::fs::remove_dir_all(artifact_dir).ok();
-    std::fs::create_dir_all(artifact_dir).ok();
+impl TrainingConfig {
+    /// Catches settings the training loop cannot run with, before it starts
+    pub fn validate(&self) -> ARes<()> {
+        if self.target_sync == 0 {
+            bail!("target_sync should be at least 1 update");
+        }
+        if self.buffer_capacity == 0 {
+            bail!("buffer_capacity should hold at least 1 transition");
+        }
+        if self.batch_size == 0 {
+            bail!("batch_size should be at least 1 transition");
+        }
+        Ok(())
+    }
 }
 
-pub(crate) fn run<B: AutodiffBackend>(
+type ReplayBuffer = PrioritizedReplayBuffer<SimulationStep<Observation>>;
+
+/// Everything besides the networks and the replay buffer needed to pick a run up
+/// where it stopped
+#[derive(Debug, Serialize, Deserialize)]
+struct TrainingState {
+    episode: usize,
+    updates: usize,
+    /// Seed the random generator restarted from when the checkpoint was written
+    seed: u64,
+}
+
+/// Replay buffer of a recent checkpoint, kept out of the checkpoints since it is by
+/// far the largest file
+const BUFFER_FILE: &str = "replay-buffer.bin";
+
+/// Streams the buffer to `path`, replacing the previous one only once it is written
+fn save_buffer(path: &Path, buffer: &ReplayBuffer) -> ARes<()> {
+    let tmp = path.with_extension("tmp");
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    bincode::serde::encode_into_std_write(buffer, &mut file, bincode::config::standard())?;
+    drop(file);
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn load_buffer(path: &Path) -> ARes<ReplayBuffer> {
+    let mut file = BufReader::new(File::open(path)?);
+    Ok(bincode::serde::decode_from_std_read(
+        &mut file,
+        bincode::config::standard(),
+    )?)
+}
+
+/// Files of a checkpoint only needed to resume from it
+const RESUME_FILES: [&str; 3] = ["target.mpk", "optim.mpk", "state.bin"];
+
+fn save_checkpoint<B: AutodiffBackend, O: Optimizer<Model<B>, B>>(
+    dir: &Path,
+    config: &TrainingConfig,
+    model: &Model<B>,
+    target: &Model<B::InnerBackend>,
+    optim: &O,
+    state: TrainingState,
+) -> ARes<()> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    config.model.save(dir.join("model.json"))?;
+    model.clone().save_file(dir.join("model"), &recorder)?;
+    target.clone().save_file(dir.join("target"), &recorder)?;
+    Recorder::<B>::record(&recorder, optim.to_record(), dir.join("optim"))?;
+    let state = bincode::serde::encode_to_vec(&state, bincode::config::standard())?;
+    fs::write(dir.join("state.bin"), state)?;
+    Ok(())
+}
+
+/// Loads the networks saved by `save_checkpoint` in place, returns the optimizer and state
+fn load_checkpoint<B: AutodiffBackend, O: Optimizer<Model<B>, B>>(
+    dir: &Path,
+    model: &mut Model<B>,
+    target: &mut Model<B::InnerBackend>,
+    optim: O,
+    device: &B::Device,
+) -> ARes<(O, TrainingState)> {
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    *model = load_weights(model.clone(), &dir.join("model.mpk"), device)?;
+    *target = load_weights(target.clone(), &dir.join("target.mpk"), device)?;
+    let optim = optim.load_record(Recorder::<B>::load(&recorder, dir.join("optim"), device)?);
+    let (state, _) = bincode::serde::decode_from_slice(
+        &fs::read(dir.join("state.bin"))?,
+        bincode::config::standard(),
+    )?;
+    Ok((optim, state))
+}
+
+/// Clears the checkpoints of an earlier DQN run, the PPO and evolution files
+/// sharing the artifact dir are left alone
+fn create_artifact_dir(artifact_dir: &str) -> ARes<()> {
+    fs::remove_dir_all(format!("{artifact_dir}checkpoints")).ok();
+    fs::remove_file(format!("{artifact_dir}{BUFFER_FILE}")).ok();
+    fs::create_dir_all(artifact_dir)?;
+    Ok(())
+}
+
+/// Trains a Q-network, checkpointing it to `artifact_dir` along the way.
+/// Resuming continues from the latest checkpoint.
+pub fn run<B: AutodiffBackend>(
     artifact_dir: &str,
     device: B::Device,
     dgb: DatasetGeneratorConfig,
-) {
-    create_artifact_dir(artifact_dir);
-    // Create the configuration.
-    let config_model = ModelConfig::new(4, 512);
-    let config_optimizer = AdamConfig::new();
-    let config = TrainingConfig::new(config_model, config_optimizer);
-    let mut dataloader = dgb.build();
+    mut config: TrainingConfig,
+    resume: bool,
+) -> ARes<()> {
+    config.validate()?;
+    if dgb.batch_size == 0 {
+        bail!("batch_size of the dataset config should be at least 1 transition");
+    }
+    if !resume {
+        create_artifact_dir(artifact_dir)?;
+    }
+    config.model = config.model.with_observation(&dgb.observation);
+    config.save(format!("{artifact_dir}training.json"))?;
+    config.model.save(format!("{artifact_dir}model.json"))?;
+    let gamma_factor = dgb.rew_config.gamma_factor;
+    let batch_size = dgb.batch_size;
+    let observation = dgb.observation;
+    let evaluator = dgb.sim_config.evaluator(config.eval_episodes, config.seed);
+    let dataloader = dgb.build();
 
     B::seed(&device, config.seed);
     let mut rng = SmallRng::seed_from_u64(config.seed);
 
+    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+    let checkpoints =
+        Checkpoints::new(artifact_dir, config.keep_best).with_resume_only(&RESUME_FILES);
     // Create the model and optimizer.
     let mut model: Model<B> = config.model.init::<B>(&device);
+    let mut target = model.valid();
     let mut optim = config.optimizer.init();
+    let mut buffer = PrioritizedReplayBuffer::new(config.buffer_capacity, config.priority_alpha);
+    let mut updates = 0;
+    let mut first = 1;
+    if resume {
+        let latest = checkpoints
+            .latest()?
+            .ok_or_else(|| anyhow!("No checkpoint to resume from in {artifact_dir}"))?;
+        let state;
+        (optim, state) = load_checkpoint(
+            &checkpoints.path(latest.episode)?,
+            &mut model,
+            &mut target,
+            optim,
+            &device,
+        )?;
+        B::seed(&device, state.seed);
+        rng = SmallRng::seed_from_u64(state.seed);
+        let path = Path::new(artifact_dir).join(BUFFER_FILE);
+        if path.exists() {
+            buffer = load_buffer(&path)?;
+        } else {
+            println!("No saved replay buffer, refilling it from scratch");
+        }
+        updates = state.updates;
+        first = state.episode + 1;
+        println!("Resuming after episode {}", state.episode);
+    }
+    // Episode rows count episodes, update rows count the updates done so far
+    let resume_from = [
+        ("episode/", first as u64),
+        ("eval/", first as u64),
+        ("update/", updates as u64),
+    ];
+    let mut metrics = Metrics::in_dir(artifact_dir, resume.then_some(&resume_from[..]))?;
 
-    // Iterate over our training and validation loop for X epochs.
-    for ep in 1..config.num_episodes + 1 {
-        let model_valid = model.valid();
-        let p = dataloader.gen_sims(&model_valid, &device, &mut rng, ep, true, false);
-        for epoch in 1..config.num_epochs + 1 {
-            for (iteration, batch) in dataloader.iter_sims(&mut rng, &p, &device).enumerate() {
-                let loss = forward_pass(&device, &model, batch, dgb.rew_config.gamma_factor);
-
-                println!(
-                    "[Episode - {} - Train - Epoch {} - Iteration {}] Loss {:.3}",
-                    ep,
-                    epoch,
-                    iteration,
-                    loss.clone().into_scalar(),
-                );
-
-                // Gradients for the current backward pass
-                let grads = loss.backward();
-                // Gradients linked to each parameter of the model.
-                let grads = GradientsParams::from_grads(grads, &model);
-                // Update the model using the optimizer.
-                model = optim.step(config.learning_rate, model, grads);
+    for ep in first..config.num_episodes + 1 {
+        let GeneratedSims { sims, episodes } =
+            dataloader.gen_sims(&model.valid(), &device, &mut rng, ep, true)?;
+        let played = episodes.len().max(1) as f64;
+        metrics.log(
+            ep as u64,
+            &[
+                ("episode/epsilon", dataloader.config().sim_config.eps_at(ep)),
+                (
+                    "episode/return",
+                    episodes
+                        .iter()
+                        .map(|e| e.episode_return as f64)
+                        .sum::<f64>()
+                        / played,
+                ),
+                (
+                    "episode/apples",
+                    episodes.iter().map(|e| e.apples as f64).sum::<f64>() / played,
+                ),
+                (
+                    "episode/steps",
+                    episodes.iter().map(|e| e.steps as f64).sum::<f64>() / played,
+                ),
+            ],
+        )?;
+        let num_updates = config.num_epochs * sims.len().div_ceil(batch_size);
+        for sim in sims {
+            buffer.push(sim);
+        }
+        // Only the updates wait for the buffer to fill, episodes are still evaluated and saved
+        let num_updates = match buffer.len() < config.warmup {
+            true => 0,
+            false => num_updates,
+        };
+        let progress = ep as f64 / config.num_episodes as f64;
+        let beta = config.priority_beta + (1. - config.priority_beta) * progress;
+        for _ in 0..num_updates {
+            let online = model.valid();
+            let sample = buffer.sample(batch_size, beta, &mut rng);
+            let batch = BatchedSimulationStep::new(&sample.items, sample.weights, &device);
+            let indices = sample.indices;
+            let (loss, td_errors, mean_q) =
+                forward_pass(&device, &model, &online, &target, batch, gamma_factor);
+            buffer.update_priorities(&indices, &td_errors);
+
+            let loss_value = loss.clone().into_scalar().elem::<f64>();
+            metrics.log(
+                updates as u64,
+                &[
+                    ("update/loss", loss_value),
+                    ("update/mean_q", mean_q as f64),
+                ],
+            )?;
+
+            // Gradients for the current backward pass
+            let grads = loss.backward();
+            // Gradients linked to each parameter of the model.
+            let grads = GradientsParams::from_grads(grads, &model);
+            // Update the model using the optimizer.
+            model = optim.step(config.learning_rate, model, grads);
+
+            updates += 1;
+            if updates % config.target_sync == 0 {
+                target = model.valid();
             }
         }
-        // Get the model without autodiff.
+        // Play the benchmark games greedily with the model without autodiff.
         let model_valid = model.valid();
-        let p = dataloader.gen_sims(&model_valid, &device, &mut rng, ep, false, true);
-        // Implement our validation loop.
-        for (iteration, batch) in dataloader.iter_sims(&mut rng, &p, &device).enumerate() {
-            let loss = forward_pass(&device, &model_valid, batch, dgb.rew_config.gamma_factor);
-
-            println!(
-                "[Valid - Episode {} - Iteration {}] Loss {:.3}",
-                ep,
-                iteration,
-                loss.clone().into_scalar(),
-            );
+        let report = evaluator.evaluate(&PlayerModel::new(&model_valid, &device, &observation))?;
+        println!(
+            "[Valid - Episode {}] Mean apples {:.2} Mean score {:.2} Win rate {:.2}",
+            ep, report.apples.mean, report.score.mean, report.win_rate
+        );
+        metrics.log(
+            ep as u64,
+            &[
+                ("eval/apples_mean", report.apples.mean as f64),
+                ("eval/apples_median", report.apples.median as f64),
+                ("eval/apples_max", report.apples.max as f64),
+                ("eval/steps_mean", report.steps.mean as f64),
+                ("eval/score_mean", report.score.mean as f64),
+                ("eval/win_rate", report.win_rate as f64),
+                ("eval/death_rate", report.death_rate as f64),
+                ("eval/loop_rate", report.loop_rate as f64),
+                ("eval/timeout_rate", report.timeout_rate as f64),
+            ],
+        )?;
+
+        if ep % config.checkpoint_every.max(1) == 0 || ep == config.num_episodes {
+            // Restarting the generators from a saved seed lets a resumed run draw the same numbers
+            let seed = rng.next_u64();
+            rng = SmallRng::seed_from_u64(seed);
+            B::seed(&device, seed);
+            let state = TrainingState {
+                episode: ep,
+                updates,
+                seed,
+            };
+            let buffer_every = config.checkpoint_every.max(1) * config.buffer_checkpoint_every;
+            if buffer_every > 0 && (ep % buffer_every == 0 || ep == config.num_episodes) {
+                save_buffer(&Path::new(artifact_dir).join(BUFFER_FILE), &buffer)?;
+            }
+            let dir = checkpoints.path(ep)?;
+            save_checkpoint(&dir, &config, &model, &target, &optim, state)?;
+            fs::write(dir.join("eval.json"), report.to_json()?)?;
+            checkpoints.commit(CheckpointEntry {
+                episode: ep,
+                score: report.score.mean,
+            })?;
+            model
+                .clone()
+                .save_file(format!("{artifact_dir}model.mpk"), &recorder)?;
         }
     }
 
-    // Load model in full precision from MessagePack file
-    let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
-    model
-        .save_file(format!("{}model.mpk", artifact_dir), &recorder)
-        .expect("Should be able to load the model weights from the provided file"); //
+    // Save model in full precision to MessagePack file
+    model.save_file(format!("{}model.mpk", artifact_dir), &recorder)?;
+    Ok(())
+}
+
+/// Double DQN targets, the online network picks the next move and the target
+/// network values it. Steps that ended the game keep only their reward.
+fn dqn_targets<B: Backend>(
+    online: &Model<B>,
+    target: &Model<B>,
+    next_state: Tensor<B, 4>,
+    reward: Tensor<B, 1>,
+    done: Tensor<B, 1>,
+    gamma_factor: f32,
+) -> Tensor<B, 1> {
+    let best = online.forward(StateRepr(next_state.clone())).argmax(1);
+    let next_qual: Tensor<B, 1> = target
+        .forward(StateRepr(next_state))
+        .gather(1, best)
+        .squeeze::<1>();
+    reward
+        + next_qual
+            .mul(done.neg().add_scalar(1.))
+            .mul_scalar(gamma_factor)
 }
 
-fn forward_pass<B: Backend, C: Backend>(
+/// Weighted squared TD error of a batch, along with the TD errors for the priorities
+/// and the mean Q-value of the moves played
+fn forward_pass<B: AutodiffBackend>(
     device: &B::Device,
     model: &Model<B>,
+    online: &Model<B::InnerBackend>,
+    target: &Model<B::InnerBackend>,
     BatchedSimulationStep {
         snapshot,
         direction,
         reward,
-        next_state_qual,
-    }: BatchedSimulationStep<C>,
+        next_state,
+        done,
+        weights,
+    }: BatchedSimulationStep<B::InnerBackend>,
     gamma_factor: f32,
-) -> Tensor<B, 1, Float> {
-    let attached: StateRepr<B> = StateRepr(Tensor::from_data(snapshot.to_data(), device));
+) -> (Tensor<B, 1, Float>, Vec<f32>, f32) {
+    let expected = dqn_targets(online, target, next_state, reward, done, gamma_factor);
+    let expected: Tensor<B, 1> = Tensor::from_inner(expected);
+    let attached: StateRepr<B> = StateRepr(Tensor::from_inner(snapshot));
     let direction: Tensor<B, 1, Int> = Tensor::from_data(direction.to_data(), device);
     let direction = direction.unsqueeze_dim(1);
-    let reward = Tensor::from_data(reward.to_data(), device);
-    let next_state_qual = Tensor::from_data(next_state_qual.to_data(), device);
+    let weights: Tensor<B, 1> = Tensor::from_inner(weights);
     let out = model.forward(attached);
     let selected = out.gather(1, direction);
     let sel: Tensor<B, 1, Float> = selected.squeeze::<1>();
-    loss::MseLoss::new().forward(
-        sel,
-        reward + next_state_qual.mul_scalar(gamma_factor),
-        loss::Reduction::Mean,
-    )
+    let mean_q = sel.clone().mean().into_scalar().elem::<f32>();
+    let td = sel - expected;
+    let td_errors = td.clone().into_data().iter::<f32>().collect();
+    (td.powi_scalar(2).mul(weights).mean(), td_errors, mean_q)
 }
@@ -1,17 +1,19 @@
-use std::{
-    collections::HashSet,
-    fmt::{Debug, Display},
-};
+use std::fmt::{Debug, Display};
 
 use crate::{
-    common::{Cell, Coord, Direction, GRID_X, GRID_Y},
+    common::{BoardSize, Cell, Coord, Direction, Topology},
+    food::{Food, FoodKind, FoodOptions},
+    level::Level,
     snake::ArrSnake,
 };
 use anyhow::Result as ARes;
 use ndarray::prelude::*;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum StepResult {
     Win {
         num_steps: usize,
@@ -22,9 +24,37 @@ pub enum StepResult {
         snake_size: usize,
         level_reached: Speed,
     },
+    Ate {
+        kind: FoodKind,
+    },
     Base,
 }
 
+impl StepResult {
+    pub fn is_terminal(self) -> bool {
+        matches!(self, Self::Win { .. } | Self::Lost { .. })
+    }
+}
+
+/// What the snake ran into when a game is lost
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DeathCause {
+    /// A wall or the edge of a bounded board
+    Wall,
+    /// Its own body
+    Body,
+}
+
+impl Display for DeathCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Wall => "Hit a wall",
+            Self::Body => "Bit itself",
+        };
+        write!(f, "{s}")
+    }
+}
+
 pub trait SnakeTrait: Debug + Sized {
     fn check_cell(&self, coords: Coord) -> Option<bool>;
     fn set_direction(&mut self, dir: Direction);
@@ -33,34 +63,117 @@ pub trait SnakeTrait: Debug + Sized {
     fn get_elements(&self) -> Vec<bool>;
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct GameAPI {
     pub snake: ArrSnake,
-    pub apples: Coord,
+    pub apples: Vec<Food>,
     pub steps: u128,
     pub num_of_apples: u128,
+    /// Steps since the snake last ate
+    pub steps_since_food: u128,
     pub score: u128,
     pub mode: Speed,
     pub game_options: GameOptions,
+    pub level: Level,
+    /// Session seed, every food placement is drawn from it
+    pub(crate) seed: u64,
+    pub(crate) rng: SmallRng,
+}
+
+/// Frozen copy of a game, food RNG position included, see `GameAPI::snapshot`
+#[derive(Debug, Clone)]
+pub struct GameSnapshot(GameAPI);
+
+impl GameSnapshot {
+    pub fn game(&self) -> &GameAPI {
+        &self.0
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Default)]
 pub struct GameAPIBuilder {
     selected_game_options: Option<GameOptions>,
+    selected_level: Option<Level>,
+    selected_seed: Option<u64>,
 }
 
 impl GameAPIBuilder {
+    /// Without a selected seed one is drawn from `rng`
     pub fn build(self, rng: Option<&mut dyn RngCore>) -> GameAPI {
-        GameAPI::new(rng, self.selected_game_options)
+        let seed = self.selected_seed.unwrap_or_else(|| draw_seed(rng));
+        let level = self.selected_level.unwrap_or_else(|| {
+            Level::empty(self.selected_game_options.unwrap_or_default().board())
+        });
+        GameAPI::from_seed(seed, self.selected_game_options, level)
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.selected_seed = Some(seed);
+        self
     }
 
     pub fn with_selected_game_options(mut self, selected_game_options: GameOptions) -> Self {
         self.selected_game_options = Some(selected_game_options);
         self
     }
+
+    pub fn with_board_size(mut self, board: BoardSize) -> Self {
+        self.selected_game_options = Some(
+            self.selected_game_options
+                .unwrap_or_default()
+                .with_board_size(board),
+        );
+        self
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.selected_game_options = Some(
+            self.selected_game_options
+                .unwrap_or_default()
+                .with_topology(topology),
+        );
+        self
+    }
+
+    pub fn with_food(mut self, food: FoodOptions) -> Self {
+        self.selected_game_options = Some(
+            self.selected_game_options
+                .unwrap_or_default()
+                .with_food(food),
+        );
+        self
+    }
+
+    pub fn with_start_speed(mut self, speed: Speed) -> Self {
+        self.selected_game_options = Some(
+            self.selected_game_options
+                .unwrap_or_default()
+                .with_start_speed(speed),
+        );
+        self
+    }
+
+    /// The level decides the board size, overriding any selected one
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.selected_level = Some(level);
+        self
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+    EnumIter,
+    Serialize,
+    Deserialize,
+)]
 pub enum Speed {
     #[default]
     Slow,
@@ -71,7 +184,12 @@ pub enum Speed {
 }
 
 #[derive(Debug, Clone, Default)]
-pub struct GameAPIBinaryRepr(pub Array2<i32>); // X Y [Empty, Food, Snake, Head]
+pub struct GameAPIBinaryRepr(pub Array2<i32>); // X Y [Empty, Head, Snake, Apple, Wall, Bonus, Shrink, Timed]
+
+impl GameAPIBinaryRepr {
+    /// Number of distinct cell codes, the channels of a one hot encoding
+    pub const CHANNELS: usize = 8;
+}
 
 impl Display for Speed {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -87,7 +205,7 @@ impl Display for Speed {
 }
 
 impl Speed {
-    fn to_score(self) -> u128 {
+    pub fn to_score(self) -> u128 {
         match self {
             Self::Slow => 10,
             Self::Medium => 20,
@@ -97,29 +215,80 @@ impl Speed {
         }
     }
 
+    /// Seconds between two steps
     pub fn to_time_speed(self) -> f32 {
-        0.2
-        // match self {
-        //     Self::Slow => 1.,
-        //     Self::Medium => 0.9,
-        //     Self::Hard => 0.8,
-        //     Self::VeryHard => 0.7,
-        //     Self::GodMode => 0.5,
-        // }
+        match self {
+            Self::Slow => 0.3,
+            Self::Medium => 0.22,
+            Self::Hard => 0.16,
+            Self::VeryHard => 0.12,
+            Self::GodMode => 0.08,
+        }
+    }
+}
+
+/// How a speed level is reached and how it plays
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpeedLevel {
+    /// Apples eaten to unlock the level
+    pub apples: u128,
+    /// Score unlocking the level, whichever of the two thresholds comes first
+    pub score: u128,
+    /// Seconds between two steps
+    pub tick_secs: f32,
+    /// Points per apple, before the multiplier of the food kind
+    pub points: u128,
+}
+
+/// Difficulty progression, one level per `Speed` from the slowest. Games only ever
+/// speed up, starting from `GameOptions::start_speed`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SpeedCurve(pub [SpeedLevel; 5]);
+
+impl Default for SpeedCurve {
+    fn default() -> Self {
+        let unlocks = [(0, 0), (5, 100), (12, 400), (22, 1200), (35, 3000)];
+        Self(std::array::from_fn(|indx| {
+            let speed = Speed::iter().nth(indx).expect("One level per speed");
+            let (apples, score) = unlocks[indx];
+            SpeedLevel {
+                apples,
+                score,
+                tick_secs: speed.to_time_speed(),
+                points: speed.to_score(),
+            }
+        }))
+    }
+}
+
+impl SpeedCurve {
+    pub fn level(&self, speed: Speed) -> SpeedLevel {
+        self.0[speed as usize]
+    }
+
+    /// Fastest level unlocked by `apples` eaten or by `score`
+    pub fn reached(&self, apples: u128, score: u128) -> Speed {
+        Speed::iter()
+            .zip(self.0)
+            .filter(|(_, level)| apples >= level.apples || score >= level.score)
+            .map(|(speed, _)| speed)
+            .max()
+            .unwrap_or_default()
     }
 }
 
 impl Display for GameAPI {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let board = self.board();
         writeln!(f)?;
-        for i in 0..=GRID_Y {
+        for i in 0..=board.cols {
             write!(f, "{:^3}|", i)?;
         }
         writeln!(f)?;
-        for i in 0..GRID_X {
+        for i in 0..board.rows {
             let i_print = i + 1;
             write!(f, "{i_print:^3}|")?;
-            for j in 0..GRID_Y {
+            for j in 0..board.cols {
                 let indx = Coord {
                     row: i as i16,
                     col: j as i16,
@@ -128,8 +297,10 @@ impl Display for GameAPI {
                     write!(f, "{:^3}|", 'H')?;
                 } else if indx == self.snake.tail {
                     write!(f, "{:^3}|", 'T')?;
-                } else if indx == self.apples {
+                } else if self.food_at(indx).is_some() {
                     write!(f, "{:^3}|", 'A')?;
+                } else if self.level.is_wall(indx) {
+                    write!(f, "{:^3}|", '#')?;
                 } else if self.snake.check_cell(indx).is_some_and(|x| x) {
                     write!(f, "{:^3}|", 'S')?;
                 } else {
@@ -142,69 +313,271 @@ impl Display for GameAPI {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct GameOptions {
     time_speed_del: u128,
+    board: BoardSize,
+    topology: Topology,
+    food: FoodOptions,
+    /// Difficulty of the first step
+    #[serde(default)]
+    start_speed: Speed,
+    #[serde(default)]
+    speed_curve: SpeedCurve,
 }
 
 impl Default for GameOptions {
     fn default() -> Self {
+        Self::new(BoardSize::default())
+    }
+}
+
+impl GameOptions {
+    pub fn new(board: BoardSize) -> Self {
         Self {
-            time_speed_del: ((GRID_X * GRID_Y) / 5) as u128,
+            time_speed_del: Self::time_speed_del_for(board),
+            board,
+            topology: Topology::default(),
+            food: FoodOptions::default(),
+            start_speed: Speed::default(),
+            speed_curve: SpeedCurve::default(),
         }
     }
+
+    fn time_speed_del_for(board: BoardSize) -> u128 {
+        (board.area() / 5).max(1) as u128
+    }
+
+    pub fn with_board_size(mut self, board: BoardSize) -> Self {
+        self.time_speed_del = Self::time_speed_del_for(board);
+        self.board = board;
+        self
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn with_food(mut self, food: FoodOptions) -> Self {
+        self.food = food;
+        self
+    }
+
+    pub fn with_start_speed(mut self, speed: Speed) -> Self {
+        self.start_speed = speed;
+        self
+    }
+
+    pub fn with_speed_curve(mut self, speed_curve: SpeedCurve) -> Self {
+        self.speed_curve = speed_curve;
+        self
+    }
+
+    pub fn board(&self) -> BoardSize {
+        self.board
+    }
+
+    pub fn start_speed(&self) -> Speed {
+        self.start_speed
+    }
+
+    pub fn speed_curve(&self) -> SpeedCurve {
+        self.speed_curve
+    }
+
+    /// Steps between two points lost to time
+    pub fn time_speed_del(&self) -> u128 {
+        self.time_speed_del
+    }
+
+    pub fn food(&self) -> FoodOptions {
+        self.food
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+}
+
+/// Seed taken from `rng`, or from the thread rng when none is given
+fn draw_seed(rng: Option<&mut dyn RngCore>) -> u64 {
+    match rng {
+        Some(rng) => rng.next_u64(),
+        None => rand::rng().next_u64(),
+    }
 }
 
 impl GameAPI {
     pub fn new(rng: Option<&mut dyn RngCore>, game_options: Option<GameOptions>) -> Self {
-        let rng = match rng {
-            None => &mut SmallRng::from_rng(&mut rand::rng()),
-            Some(rng) => rng,
-        };
-        let mid = Coord::middle();
-        let c = loop {
-            let c = Coord {
-                row: rng.random_range(0..GRID_X as i16),
-                col: rng.random_range(0..GRID_Y as i16),
-            };
-            if mid.l0(c) > 1 {
-                break c;
-            }
-        };
+        let game_options = game_options.unwrap_or_default();
+        let level = Level::empty(game_options.board());
+        Self::new_with_level(rng, Some(game_options), level)
+    }
+
+    pub fn new_with_level(
+        rng: Option<&mut dyn RngCore>,
+        game_options: Option<GameOptions>,
+        level: Level,
+    ) -> Self {
+        Self::from_seed(draw_seed(rng), game_options, level)
+    }
+
+    /// Same seed, options, level and moves always play out the same game
+    pub fn from_seed(seed: u64, game_options: Option<GameOptions>, level: Level) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let board = level.board();
+        let game_options = game_options.unwrap_or_default().with_board_size(board);
+        let mid = Coord::middle(board);
+        let food = game_options.food();
+        let candidates = (0..board.area())
+            .map(|indx| Coord::from_index(indx, board))
+            .filter(|c| mid.l0(*c) > 1 && !level.is_wall(*c))
+            .collect::<Vec<_>>();
+        let apples = candidates
+            .sample(&mut rng, food.count)
+            .map(|c| food.spawn(*c, &mut rng))
+            .collect();
 
         Self {
-            snake: ArrSnake::default(),
-            apples: c,
+            snake: ArrSnake::with_level(&level).with_topology(game_options.topology()),
+            apples,
             steps: 0,
             score: 0,
             num_of_apples: 0,
-            mode: Speed::default(),
-            game_options: game_options.unwrap_or_default(),
+            steps_since_food: 0,
+            mode: game_options.start_speed(),
+            game_options,
+            level,
+            seed,
+            rng,
         }
     }
 
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Captures the whole game, restoring it replays the same food spawns
+    pub fn snapshot(&self) -> GameSnapshot {
+        GameSnapshot(self.clone())
+    }
+
+    pub fn restore(&mut self, snapshot: &GameSnapshot) {
+        self.clone_from(&snapshot.0);
+    }
+
+    pub fn board(&self) -> BoardSize {
+        self.game_options.board()
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.game_options.topology()
+    }
+
+    /// Cell reached moving from `pos` towards `dir`, `None` when it leaves the board
+    pub fn neighbour(&self, pos: Coord, dir: Direction) -> Option<Coord> {
+        self.board().neighbour(pos, dir, self.topology())
+    }
+
+    /// Distance between two cells, aware of the topology of the board
+    pub fn distance(&self, a: Coord, b: Coord) -> u16 {
+        self.board().distance(a, b, self.topology())
+    }
+
     pub fn get_pos(&self, pos: Coord) -> Option<Cell> {
-        if pos.row > GRID_X as i16 && pos.col > GRID_Y as i16 {
+        if !self.board().contains(pos) {
             return None;
         }
-        if self.apples == pos {
-            return Some(Cell::Apple);
+        if let Some(food) = self.food_at(pos) {
+            return Some(Cell::Apple(food.kind));
         }
         if self.snake.check_cell(pos)? {
             Some(Cell::Snake)
+        } else if self.level.is_wall(pos) {
+            Some(Cell::Wall)
         } else {
             Some(Cell::Empty)
         }
     }
 
+    /// Whether the snake could move into the cell without dying
+    pub fn is_free(&self, pos: Coord) -> bool {
+        matches!(self.get_pos(pos), Some(Cell::Empty | Cell::Apple(_)))
+    }
+
+    /// Directions the snake can take without dying, indexed by `Direction as usize`
+    pub fn action_mask(&self) -> [bool; 4] {
+        let head = self.snake.head;
+        std::array::from_fn(|indx| {
+            self.neighbour(head, Direction::from(indx))
+                .is_some_and(|next| self.is_free(next))
+        })
+    }
+
+    pub fn food_at(&self, pos: Coord) -> Option<&Food> {
+        self.apples.iter().find(|f| f.pos == pos)
+    }
+
+    /// Closest food to `pos`, ties broken by spawn order
+    pub fn nearest_food(&self, pos: Coord) -> Option<&Food> {
+        self.apples.iter().min_by_key(|f| self.distance(pos, f.pos))
+    }
+
+    /// Tops the board back up to the configured number of food items,
+    /// returns false if there was no room left for any of them
+    fn refill_food(&mut self) -> bool {
+        let food = self.game_options.food();
+        while self.apples.len() < food.count {
+            let taken = self.apples.iter().map(|f| f.pos).collect::<Vec<_>>();
+            let Some(coord) = self.snake.get_free_spot(&mut self.rng, &taken) else {
+                return false;
+            };
+            self.apples.push(food.spawn(coord, &mut self.rng));
+        }
+        true
+    }
+
+    fn expire_food(&mut self) {
+        for food in self.apples.iter_mut() {
+            if let Some(ttl) = food.ttl.as_mut() {
+                *ttl = ttl.saturating_sub(1);
+            }
+        }
+        self.apples.retain(|f| f.ttl != Some(0));
+    }
+
     pub fn update_direction(&mut self, dir: Direction) {
         self.snake.set_direction(dir);
     }
+    /// Speeds up once the apples eaten or the score unlock the next level
     fn set_speed(&mut self) {
-        self.mode = Speed::Medium;
+        let reached = self
+            .game_options
+            .speed_curve()
+            .reached(self.num_of_apples, self.score);
+        self.mode = self.mode.max(reached);
+    }
+
+    /// Seconds between two steps at the current speed
+    pub fn tick_secs(&self) -> f32 {
+        self.game_options.speed_curve().level(self.mode).tick_secs
     }
 
-    pub fn next(&mut self, rng: &mut impl RngCore) -> ARes<StepResult> {
+    /// What the next step runs into, `None` while it is safe. Still answers after
+    /// `next` returned `StepResult::Lost` since the fatal step is never taken.
+    pub fn death_cause(&self) -> Option<DeathCause> {
+        if self.snake.is_next_valid() {
+            return None;
+        }
+        match self.snake.next_step() {
+            Ok(head) if !self.snake.is_wall(head) => Some(DeathCause::Body),
+            _ => Some(DeathCause::Wall),
+        }
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> ARes<StepResult> {
         if !self.snake.is_next_valid() {
             return Ok(StepResult::Lost {
                 num_steps: self.steps as usize,
@@ -214,29 +587,48 @@ impl GameAPI {
             });
         }
         let head = self.snake.next_step()?;
-        let with_food = head == self.apples;
-        self.snake.step(with_food)?;
-        if with_food {
-            if let Some(coord) = self.snake.get_free_spot(rng) {
-                self.num_of_apples += 1;
-                self.apples = coord;
-            } else {
-                return Ok(StepResult::Win {
-                    num_steps: self.steps as usize,
-                });
-            }
+        let eaten = self
+            .apples
+            .iter()
+            .position(|f| f.pos == head)
+            .map(|indx| self.apples.remove(indx));
+        self.snake.step(eaten.is_some_and(|f| f.kind.grows()))?;
+        if eaten.is_some_and(|f| !f.kind.grows()) {
+            self.snake.shrink()?;
+        }
+        self.expire_food();
+        if eaten.is_some() {
+            self.num_of_apples += 1;
+            self.steps_since_food = 0;
+        } else {
+            self.steps_since_food += 1;
+        }
+        if !self.refill_food() && self.apples.is_empty() {
+            return Ok(StepResult::Win {
+                num_steps: self.steps as usize,
+            });
         }
         self.steps += 1;
         self.set_speed();
-        self.score += with_food as u128 * self.mode.to_score();
-        if self.steps.is_multiple_of(self.game_options.time_speed_del) {
+        if let Some(food) = eaten {
+            self.score += food.kind.score_multiplier()
+                * self.game_options.speed_curve().level(self.mode).points;
+        }
+        if self
+            .steps
+            .is_multiple_of(self.game_options.time_speed_del())
+        {
             self.score = self.score.saturating_sub(1);
         }
-        Ok(StepResult::Base)
+        Ok(match eaten {
+            Some(food) => StepResult::Ate { kind: food.kind },
+            None => StepResult::Base,
+        })
     }
 
     pub fn to_game_repr(&self) -> GameAPIBinaryRepr {
-        let a = Array2::from_shape_fn((GRID_X, GRID_Y), |(row, col)| {
+        let board = self.board();
+        let a = Array2::from_shape_fn((board.rows, board.cols), |(row, col)| {
             let pos = Coord {
                 row: row as i16,
                 col: col as i16,
@@ -253,9 +645,119 @@ impl GameAPI {
                         2
                     }
                 }
-                Cell::Apple => 3,
+                Cell::Apple(kind) => kind.repr_code(),
+                Cell::Wall => 4,
             }
         });
         GameAPIBinaryRepr(a)
     }
+
+    /// FNV-1a hash of the board, the food timers and the counters.
+    /// Stable across runs and platforms, replays use it to catch desyncs.
+    pub fn checksum(&self) -> u32 {
+        let GameAPIBinaryRepr(cells) = self.to_game_repr();
+        let ttls = self
+            .apples
+            .iter()
+            .map(|f| f.ttl.map_or(0, |t| t as u64 + 1));
+        let counters = [
+            self.steps as u64,
+            self.score as u64,
+            self.num_of_apples as u64,
+            self.snake.direction as u64,
+        ];
+        cells
+            .iter()
+            .map(|c| *c as u64)
+            .chain(ttls)
+            .chain(counters)
+            .flat_map(u64::to_le_bytes)
+            .fold(0x811c9dc5, |hash, byte| {
+                (hash ^ byte as u32).wrapping_mul(0x01000193)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(seed: u64, moves: &[Direction]) -> Vec<Vec<Food>> {
+        let mut game = GameAPIBuilder::default()
+            .with_food(FoodOptions {
+                count: 2,
+                bonus_chance: 0.3,
+                ..Default::default()
+            })
+            .with_seed(seed)
+            .build(None);
+        let mut apples = vec![game.apples.clone()];
+        for dir in moves {
+            game.update_direction(*dir);
+            if game.next().expect("Valid step").is_terminal() {
+                break;
+            }
+            apples.push(game.apples.clone());
+        }
+        apples
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let moves = [
+            Direction::Up,
+            Direction::Left,
+            Direction::Down,
+            Direction::Right,
+        ]
+        .repeat(8);
+        assert_eq!(play(42, &moves), play(42, &moves));
+        assert_ne!(play(42, &moves)[0], play(43, &moves)[0]);
+        let game = GameAPIBuilder::default().with_seed(42).build(None);
+        assert_eq!(game.seed(), 42);
+        let game = GameAPIBuilder::default()
+            .with_start_speed(Speed::Hard)
+            .build(None);
+        assert_eq!(game.mode, Speed::Hard);
+    }
+
+    #[test]
+    fn speed_levels_unlock() {
+        let curve = SpeedCurve::default();
+        assert_eq!(curve.reached(0, 0), Speed::Slow);
+        assert_eq!(curve.reached(5, 0), Speed::Medium);
+        assert_eq!(curve.reached(5, 400), Speed::Hard);
+        assert_eq!(curve.reached(1000, 0), Speed::GodMode);
+        assert!(curve.level(Speed::GodMode).tick_secs < curve.level(Speed::Slow).tick_secs);
+
+        let mut game = GameAPIBuilder::default()
+            .with_start_speed(Speed::VeryHard)
+            .build(None);
+        game.set_speed();
+        assert_eq!(game.mode, Speed::VeryHard, "Games never slow down");
+        game.num_of_apples = 35;
+        game.set_speed();
+        assert_eq!(game.mode, Speed::GodMode);
+        assert_eq!(game.tick_secs(), Speed::GodMode.to_time_speed());
+    }
+
+    #[test]
+    fn tells_walls_from_bodies() {
+        let mut game = GameAPIBuilder::default().with_seed(7).build(None);
+        assert_eq!(game.death_cause(), None);
+        game.update_direction(Direction::Up);
+        while !game.next().expect("Valid step").is_terminal() {}
+        assert_eq!(game.death_cause(), Some(DeathCause::Wall));
+
+        let mut game = GameAPIBuilder::default().with_seed(7).build(None);
+        game.update_direction(Direction::Right);
+        for _ in 0..4 {
+            game.snake.step(true).expect("Room to grow");
+        }
+        for dir in [Direction::Down, Direction::Left, Direction::Up] {
+            game.update_direction(dir);
+            game.next().expect("Valid step");
+        }
+        assert_eq!(game.death_cause(), Some(DeathCause::Body));
+    }
 }
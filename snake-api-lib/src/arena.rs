@@ -0,0 +1,516 @@
+use anyhow::{Result as ARes, anyhow};
+use itertools::Itertools;
+use rand::prelude::*;
+
+use crate::{
+    api::{GameAPI, GameOptions, SnakeTrait, Speed, StepResult},
+    common::{BoardSize, Coord, Direction},
+    food::Food,
+    level::Level,
+    simulator::PlayerTrait,
+    snake::ArrSnake,
+};
+
+pub const MIN_SNAKES: usize = 2;
+pub const MAX_SNAKES: usize = 4;
+
+#[derive(Debug, Clone)]
+pub struct ArenaSnake {
+    pub snake: ArrSnake,
+    pub score: u128,
+    pub num_of_apples: u128,
+    /// Final result once the snake is out of the game
+    pub outcome: Option<StepResult>,
+}
+
+impl ArenaSnake {
+    pub fn is_alive(&self) -> bool {
+        self.outcome.is_none()
+    }
+}
+
+/// Board shared by 2 to 4 snakes moving simultaneously.
+///
+/// A snake dies running into a wall, the edge of a bounded board, its own body or
+/// the body of another snake. When two heads meet the longer snake survives,
+/// equal sizes kill both. The last snake standing wins.
+#[derive(Debug, Clone)]
+pub struct Arena {
+    pub snakes: Vec<ArenaSnake>,
+    pub apples: Vec<Food>,
+    pub steps: u128,
+    pub mode: Speed,
+    pub game_options: GameOptions,
+    pub level: Level,
+    /// Session seed, every food placement is drawn from it
+    seed: u64,
+    rng: SmallRng,
+}
+
+/// Starting cell and heading of each snake, one per quarter of the board
+fn spawn_points(board: BoardSize) -> [(Coord, Direction); MAX_SNAKES] {
+    let (rows, cols) = (board.rows as i16, board.cols as i16);
+    let (top, bottom) = (rows / 4, rows - 1 - rows / 4);
+    let (left, right) = (cols / 4, cols - 1 - cols / 4);
+    [
+        (
+            Coord {
+                row: top,
+                col: left,
+            },
+            Direction::Right,
+        ),
+        (
+            Coord {
+                row: bottom,
+                col: right,
+            },
+            Direction::Left,
+        ),
+        (
+            Coord {
+                row: top,
+                col: right,
+            },
+            Direction::Down,
+        ),
+        (
+            Coord {
+                row: bottom,
+                col: left,
+            },
+            Direction::Up,
+        ),
+    ]
+}
+
+impl Arena {
+    /// Same seed, options, level and moves always play out the same game
+    pub fn new(
+        seed: u64,
+        game_options: Option<GameOptions>,
+        level: Level,
+        num_snakes: usize,
+    ) -> ARes<Self> {
+        if !(MIN_SNAKES..=MAX_SNAKES).contains(&num_snakes) {
+            return Err(anyhow!(
+                "Arena needs {MIN_SNAKES} to {MAX_SNAKES} snakes, got {num_snakes}"
+            ));
+        }
+        let board = level.board();
+        let game_options = game_options.unwrap_or_default().with_board_size(board);
+        let spawns = spawn_points(board);
+        if !spawns[..num_snakes].iter().map(|(c, _)| c).all_unique() {
+            return Err(anyhow!(
+                "Board {:?} too small for {num_snakes} snakes",
+                board
+            ));
+        }
+        let snakes = spawns[..num_snakes]
+            .iter()
+            .map(|(head, dir)| {
+                if level.is_wall(*head) {
+                    return Err(anyhow!("Spawn point {:?} is a wall", head));
+                }
+                Ok(ArenaSnake {
+                    snake: ArrSnake::spawn(&level, *head, *dir)
+                        .with_topology(game_options.topology()),
+                    score: 0,
+                    num_of_apples: 0,
+                    outcome: None,
+                })
+            })
+            .collect::<ARes<Vec<_>>>()?;
+        let mut arena = Self {
+            snakes,
+            apples: vec![],
+            steps: 0,
+            mode: game_options.start_speed(),
+            game_options,
+            level,
+            seed,
+            rng: SmallRng::seed_from_u64(seed),
+        };
+        arena.refill_food();
+        Ok(arena)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.snakes.iter().all(|s| !s.is_alive())
+    }
+
+    /// Seconds between two steps at the current speed
+    pub fn tick_secs(&self) -> f32 {
+        self.game_options.speed_curve().level(self.mode).tick_secs
+    }
+
+    /// Game as seen by one snake, every other snake is an obstacle
+    pub fn view(&self, indx: usize) -> GameAPI {
+        let others = self
+            .snakes
+            .iter()
+            .enumerate()
+            .filter(|(j, s)| *j != indx && s.is_alive())
+            .flat_map(|(_, s)| s.snake.cells());
+        let level = self.level.with_extra_walls(others);
+        let me = &self.snakes[indx];
+        let mut snake = me.snake.clone();
+        snake.set_level(&level);
+        GameAPI {
+            snake,
+            apples: self.apples.clone(),
+            steps: self.steps,
+            num_of_apples: me.num_of_apples,
+            steps_since_food: 0,
+            score: me.score,
+            mode: self.mode,
+            game_options: self.game_options,
+            level,
+            // Views are only looked at, never stepped
+            seed: 0,
+            rng: SmallRng::seed_from_u64(0),
+        }
+    }
+
+    fn lost(&self, indx: usize) -> StepResult {
+        let me = &self.snakes[indx];
+        StepResult::Lost {
+            num_steps: self.steps as usize,
+            number_of_fruits: me.num_of_apples as usize,
+            snake_size: me.snake.size,
+            level_reached: self.mode,
+        }
+    }
+
+    fn refill_food(&mut self) -> bool {
+        let board = self.level.board();
+        let food = self.game_options.food();
+        let mut blocked = self.level.wall_bits().clone();
+        for cell in self
+            .snakes
+            .iter()
+            .filter(|s| s.is_alive())
+            .flat_map(|s| s.snake.cells())
+            .chain(self.apples.iter().map(|f| f.pos))
+        {
+            blocked.set(cell.into_index(board), true);
+        }
+        while self.apples.len() < food.count {
+            let Some(indx) = blocked
+                .iter_zeros()
+                .collect_vec()
+                .choose(&mut self.rng)
+                .copied()
+            else {
+                return false;
+            };
+            blocked.set(indx, true);
+            self.apples
+                .push(food.spawn(Coord::from_index(indx, board), &mut self.rng));
+        }
+        true
+    }
+
+    /// Moves every live snake one cell at once, `dirs` holds one direction per snake.
+    /// Returns the result of the step for each snake, finished snakes repeat their outcome.
+    pub fn next(&mut self, dirs: &[Direction]) -> ARes<Vec<StepResult>> {
+        if dirs.len() != self.snakes.len() {
+            return Err(anyhow!("Expected {} directions", self.snakes.len()));
+        }
+        let alive = (0..self.snakes.len())
+            .filter(|i| self.snakes[*i].is_alive())
+            .collect_vec();
+        let old_heads = self.snakes.iter().map(|s| s.snake.head).collect_vec();
+        let mut new_heads: Vec<Option<Coord>> = vec![None; self.snakes.len()];
+        let mut dead = vec![false; self.snakes.len()];
+        let mut eaten: Vec<Option<Food>> = vec![None; self.snakes.len()];
+
+        for &i in &alive {
+            let snake = &mut self.snakes[i].snake;
+            snake.set_direction(dirs[i]);
+            if snake.is_next_valid() {
+                new_heads[i] = Some(snake.next_step()?);
+            } else {
+                dead[i] = true;
+            }
+        }
+        // Heads meeting are settled on the sizes before the move, food eaten on the
+        // cell they meet on makes no difference
+        let sizes = self.snakes.iter().map(|s| s.snake.size).collect_vec();
+        for &i in &alive {
+            let Some(head) = new_heads[i] else {
+                continue;
+            };
+            for &j in alive.iter().filter(|j| **j != i) {
+                let swapped = new_heads[j] == Some(old_heads[i]) && head == old_heads[j];
+                if new_heads[j] == Some(head) || swapped {
+                    dead[i] |= sizes[i] <= sizes[j];
+                }
+            }
+        }
+        for &i in &alive {
+            let Some(head) = new_heads[i] else {
+                continue;
+            };
+            if !dead[i] {
+                eaten[i] = self
+                    .apples
+                    .iter()
+                    .position(|f| f.pos == head)
+                    .map(|indx| self.apples.remove(indx));
+            }
+            let snake = &mut self.snakes[i].snake;
+            snake.step(eaten[i].is_some_and(|f| f.kind.grows()))?;
+            if eaten[i].is_some_and(|f| !f.kind.grows()) {
+                snake.shrink()?;
+            }
+        }
+        for &i in &alive {
+            let Some(head) = new_heads[i] else {
+                continue;
+            };
+            for &j in alive.iter().filter(|j| **j != i) {
+                let met = new_heads[j] == Some(head)
+                    || new_heads[j] == Some(old_heads[i]) && head == old_heads[j];
+                if !met {
+                    dead[i] |= self.snakes[j].snake.check_cell(head) == Some(true);
+                }
+            }
+        }
+
+        self.steps += 1;
+        for &i in alive.iter().filter(|i| eaten[**i].is_some()) {
+            self.snakes[i].num_of_apples += 1;
+        }
+        // The arena speeds up with the leading snake, then scores the same way
+        // `GameAPI::next` does
+        let curve = self.game_options.speed_curve();
+        for &i in &alive {
+            let me = &self.snakes[i];
+            self.mode = self.mode.max(curve.reached(me.num_of_apples, me.score));
+        }
+        let decay = self
+            .steps
+            .is_multiple_of(self.game_options.time_speed_del());
+        for &i in &alive {
+            let me = &mut self.snakes[i];
+            if let Some(food) = eaten[i] {
+                me.score += food.kind.score_multiplier() * curve.level(self.mode).points;
+            }
+            if decay {
+                me.score = me.score.saturating_sub(1);
+            }
+        }
+        for &i in alive.iter().filter(|i| dead[**i]) {
+            self.snakes[i].outcome = Some(self.lost(i));
+        }
+        for food in self.apples.iter_mut() {
+            if let Some(ttl) = food.ttl.as_mut() {
+                *ttl = ttl.saturating_sub(1);
+            }
+        }
+        self.apples.retain(|f| f.ttl != Some(0));
+
+        let survivors = alive.iter().copied().filter(|i| !dead[*i]).collect_vec();
+        let board_full = !self.refill_food() && self.apples.is_empty();
+        if survivors.len() == 1 || board_full {
+            for &i in &survivors {
+                self.snakes[i].outcome = Some(StepResult::Win {
+                    num_steps: self.steps as usize,
+                });
+            }
+        }
+
+        Ok((0..self.snakes.len())
+            .map(|i| match self.snakes[i].outcome {
+                Some(outcome) => outcome,
+                None => match eaten[i] {
+                    Some(food) => StepResult::Ate { kind: food.kind },
+                    None => StepResult::Base,
+                },
+            })
+            .collect())
+    }
+
+    /// Lets each player drive its snake until one is left or `max_steps` run out,
+    /// `rng` only feeds the players. Returns the last result of every snake.
+    pub fn play(
+        &mut self,
+        players: &[&dyn PlayerTrait],
+        rng: &mut dyn RngCore,
+        max_steps: usize,
+    ) -> ARes<Vec<StepResult>> {
+        if players.len() != self.snakes.len() {
+            return Err(anyhow!("Expected {} players", self.snakes.len()));
+        }
+        let mut results = vec![StepResult::Base; self.snakes.len()];
+        for _ in 0..max_steps {
+            let dirs = players
+                .iter()
+                .enumerate()
+                .map(|(i, player)| {
+                    if self.snakes[i].is_alive() {
+                        player.choose_dir(&self.view(i), rng)
+                    } else {
+                        self.snakes[i].snake.direction
+                    }
+                })
+                .collect_vec();
+            results = self.next(&dirs)?;
+            if self.is_finished() {
+                break;
+            }
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{GameAPIBuilder, SpeedCurve},
+        common::Topology,
+        food::FoodKind,
+        simulator::GreedyPlayer,
+    };
+
+    fn arena(num_snakes: usize) -> Arena {
+        Arena::new(3, None, Level::default(), num_snakes).expect("Valid arena")
+    }
+
+    /// Places snake `indx` with its head on `head` heading `dir`, grown to `size`
+    fn place(arena: &mut Arena, indx: usize, head: Coord, dir: Direction, size: usize) {
+        let board = arena.level.board();
+        let tail = (0..size).fold(head, |c, _| {
+            board
+                .neighbour(c, dir.inverse(), Topology::Bounded)
+                .expect("Room behind the head")
+        });
+        let mut snake = ArrSnake::spawn(&arena.level, tail, dir);
+        for _ in 0..size {
+            snake.step(true).expect("Room to grow");
+        }
+        arena.snakes[indx].snake = snake;
+        arena.apples.clear();
+    }
+
+    #[test]
+    fn apples_score_as_in_single_games() {
+        // The first apple unlocks the next speed, which pays more per apple
+        let mut curve = SpeedCurve::default();
+        curve.0[1].apples = 1;
+        let options = GameOptions::default().with_speed_curve(curve);
+        let mut game = GameAPIBuilder::default()
+            .with_selected_game_options(options)
+            .build(None);
+        game.apples.clear();
+        game.apples.push(Food {
+            pos: game.snake.next_step().expect("Room ahead"),
+            kind: FoodKind::Apple,
+            ttl: None,
+        });
+        game.next().expect("Valid step");
+
+        let mut arena = Arena::new(3, Some(options), Level::default(), 2).expect("Valid arena");
+        place(&mut arena, 0, Coord { row: 2, col: 3 }, Direction::Right, 1);
+        place(&mut arena, 1, Coord { row: 8, col: 3 }, Direction::Right, 1);
+        arena.apples.push(Food {
+            pos: Coord { row: 2, col: 4 },
+            kind: FoodKind::Apple,
+            ttl: None,
+        });
+        arena
+            .next(&[Direction::Right, Direction::Right])
+            .expect("Valid step");
+        assert_eq!(game.num_of_apples, 1);
+        assert_eq!(arena.snakes[0].num_of_apples, 1);
+        assert_eq!(arena.snakes[0].score, game.score);
+        assert_eq!(game.score, curve.level(Speed::Medium).points);
+    }
+
+    #[test]
+    fn head_on_longer_snake_wins() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, Coord { row: 5, col: 3 }, Direction::Right, 3);
+        place(&mut arena, 1, Coord { row: 5, col: 5 }, Direction::Left, 1);
+        let res = arena
+            .next(&[Direction::Right, Direction::Left])
+            .expect("Valid step");
+        assert!(matches!(res[0], StepResult::Win { .. }));
+        assert!(matches!(res[1], StepResult::Lost { .. }));
+    }
+
+    #[test]
+    fn equal_snakes_meeting_on_food_both_die() {
+        for kind in [FoodKind::Apple, FoodKind::Shrink] {
+            let mut arena = arena(3);
+            place(&mut arena, 0, Coord { row: 5, col: 3 }, Direction::Right, 2);
+            place(&mut arena, 1, Coord { row: 5, col: 5 }, Direction::Left, 2);
+            arena.apples.push(Food {
+                pos: Coord { row: 5, col: 4 },
+                kind,
+                ttl: None,
+            });
+            let res = arena
+                .next(&[
+                    Direction::Right,
+                    Direction::Left,
+                    arena.snakes[2].snake.direction,
+                ])
+                .expect("Valid step");
+            assert!(matches!(res[0], StepResult::Lost { .. }), "{kind:?}");
+            assert!(matches!(res[1], StepResult::Lost { .. }), "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn running_into_a_body_kills() {
+        let mut arena = arena(3);
+        place(&mut arena, 0, Coord { row: 2, col: 6 }, Direction::Right, 4);
+        place(&mut arena, 1, Coord { row: 3, col: 5 }, Direction::Up, 0);
+        let res = arena
+            .next(&[
+                Direction::Right,
+                Direction::Up,
+                arena.snakes[2].snake.direction,
+            ])
+            .expect("Valid step");
+        assert_eq!(res[0], StepResult::Base);
+        assert!(matches!(res[1], StepResult::Lost { .. }));
+        assert!(arena.snakes[2].is_alive());
+    }
+
+    #[test]
+    fn greedy_bots_finish() {
+        let mut arena = arena(4);
+        let players: [&dyn PlayerTrait; 4] = [&GreedyPlayer; 4];
+        arena
+            .play(&players, &mut SmallRng::seed_from_u64(3), 2000)
+            .expect("Valid game");
+        assert!(arena.steps > 0);
+        assert!(arena.snakes.iter().filter(|s| s.is_alive()).count() <= 1 || arena.steps == 2000);
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let play = |seed| {
+            let mut arena = Arena::new(seed, None, Level::default(), 2).expect("Valid arena");
+            let mut apples = vec![arena.apples.clone()];
+            for _ in 0..30 {
+                let dirs = [0, 1].map(|i| {
+                    GreedyPlayer.choose_dir(&arena.view(i), &mut SmallRng::seed_from_u64(0))
+                });
+                arena.next(&dirs).expect("Valid step");
+                apples.push(arena.apples.clone());
+            }
+            apples
+        };
+        assert_eq!(play(7), play(7));
+        assert_ne!(play(7), play(8));
+    }
+}
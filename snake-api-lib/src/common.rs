@@ -1,13 +1,115 @@
+use crate::food::FoodKind;
+use anyhow::{Result as ARes, anyhow};
+use bitvec::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
     fmt::Display,
     ops::{Add, Sub},
 };
 use strum_macros::EnumIter;
 
+/// Default number of rows of the board
 pub const GRID_X: usize = 12;
+/// Default number of columns of the board
 pub const GRID_Y: usize = 12;
+/// Fewest rows and columns of a board, smaller ones leave no room for food around
+/// the snake and are too small for the convolutions of the models
+pub const MIN_BOARD_SIDE: usize = 4;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+// Bit array sized to the board at runtime, one bit per cell
+pub(crate) type GridBits = BitVec<u64, Msb0>;
+
+/// How the edges of the board behave
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Topology {
+    /// Leaving the board is a collision
+    #[default]
+    Bounded,
+    /// Leaving one edge re-enters on the opposite edge
+    Toroidal,
+}
+
+/// Dimensions of the board, rows by columns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "BoardDims")]
+pub struct BoardSize {
+    pub rows: usize,
+    pub cols: usize,
+}
+
+/// Unchecked `BoardSize` as it is written in configs
+#[derive(Deserialize)]
+struct BoardDims {
+    rows: usize,
+    cols: usize,
+}
+
+impl TryFrom<BoardDims> for BoardSize {
+    type Error = anyhow::Error;
+
+    fn try_from(dims: BoardDims) -> ARes<Self> {
+        Self::new(dims.rows, dims.cols)
+    }
+}
+
+impl Default for BoardSize {
+    fn default() -> Self {
+        Self {
+            rows: GRID_X,
+            cols: GRID_Y,
+        }
+    }
+}
+
+impl BoardSize {
+    /// Fails below `MIN_BOARD_SIDE` rows or columns
+    pub fn new(rows: usize, cols: usize) -> ARes<Self> {
+        if rows < MIN_BOARD_SIDE || cols < MIN_BOARD_SIDE {
+            return Err(anyhow!(
+                "Board {rows}x{cols} is smaller than {MIN_BOARD_SIDE}x{MIN_BOARD_SIDE}"
+            ));
+        }
+        Ok(Self { rows, cols })
+    }
+
+    pub fn area(self) -> usize {
+        self.rows * self.cols
+    }
+
+    pub fn contains(self, coord: Coord) -> bool {
+        (0..self.rows as i16).contains(&coord.row) && (0..self.cols as i16).contains(&coord.col)
+    }
+
+    /// Neighbouring cell in the given direction, `None` when leaving a bounded board
+    pub fn neighbour(self, coord: Coord, dir: Direction, topology: Topology) -> Option<Coord> {
+        let next = coord.add_dir(dir);
+        match topology {
+            Topology::Bounded => self.contains(next).then_some(next),
+            Topology::Toroidal => Some(Coord {
+                row: next.row.rem_euclid(self.rows as i16),
+                col: next.col.rem_euclid(self.cols as i16),
+            }),
+        }
+    }
+
+    /// Manhattan distance, going through the edges when the board wraps around
+    pub fn distance(self, a: Coord, b: Coord, topology: Topology) -> u16 {
+        match topology {
+            Topology::Bounded => a.l1(b),
+            Topology::Toroidal => {
+                let row = a.row.abs_diff(b.row);
+                let col = a.col.abs_diff(b.col);
+                row.min(self.rows as u16 - row) + col.min(self.cols as u16 - col)
+            }
+        }
+    }
+
+    pub(crate) fn empty_bits(self) -> GridBits {
+        bitvec![u64, Msb0; 0; self.area()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct Coord {
     pub row: i16,
     pub col: i16,
@@ -15,7 +117,7 @@ pub struct Coord {
 
 impl Default for Coord {
     fn default() -> Self {
-        Self::middle()
+        Self::middle(BoardSize::default())
     }
 }
 
@@ -29,14 +131,14 @@ impl Coord {
             .max(self.col.abs_diff(other.col))
     }
 
-    pub fn into_index(self) -> usize {
-        (self.row as usize) * GRID_Y + self.col as usize
+    pub fn into_index(self, board: BoardSize) -> usize {
+        (self.row as usize) * board.cols + self.col as usize
     }
 
-    pub fn from_index(other: usize) -> Self {
+    pub fn from_index(other: usize, board: BoardSize) -> Self {
         Self {
-            row: (other % GRID_Y) as i16,
-            col: (other / GRID_Y) as i16,
+            row: (other / board.cols) as i16,
+            col: (other % board.cols) as i16,
         }
     }
 
@@ -56,10 +158,10 @@ impl Coord {
         }
     }
 
-    pub fn middle() -> Self {
+    pub fn middle(board: BoardSize) -> Self {
         Self {
-            row: GRID_Y as i16 / 2,
-            col: GRID_X as i16 / 2,
+            row: board.rows as i16 / 2,
+            col: board.cols as i16 / 2,
         }
     }
 }
@@ -86,7 +188,9 @@ impl Sub for Coord {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumIter, Default)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, EnumIter, Default, Serialize, Deserialize,
+)]
 pub enum Direction {
     #[default]
     Left = 0,
@@ -132,6 +236,7 @@ impl From<usize> for Direction {
 
 pub enum Cell {
     Snake,
-    Apple,
+    Apple(FoodKind),
+    Wall,
     Empty,
 }
@@ -0,0 +1,280 @@
+use std::fmt::Display;
+
+use anyhow::Result as ARes;
+use itertools::Itertools;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{GameAPIBuilder, StepResult},
+    simulator::{Environment, PlayerTrait, SnakeEnv},
+};
+
+/// How a benchmark game ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Win,
+    Died,
+    /// Went a whole board worth of steps without eating, stuck in a loop
+    Looped,
+    /// Reached the step limit while still eating
+    TimedOut,
+}
+
+/// Result of a single benchmark game
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GameOutcome {
+    pub seed: u64,
+    pub outcome: Outcome,
+    pub apples: u64,
+    pub steps: u64,
+    pub score: u64,
+}
+
+/// Summary of one metric over every game
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct Stats {
+    pub mean: f32,
+    pub median: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Stats {
+    fn new(values: impl Iterator<Item = f32>) -> Self {
+        let values = values.sorted_by(f32::total_cmp).collect_vec();
+        let Some((min, max)) = values.first().zip(values.last()) else {
+            return Self::default();
+        };
+        let mid = values.len() / 2;
+        let median = if values.len() % 2 == 0 {
+            (values[mid - 1] + values[mid]) / 2.
+        } else {
+            values[mid]
+        };
+        Self {
+            mean: values.iter().sum::<f32>() / values.len() as f32,
+            median,
+            min: *min,
+            max: *max,
+        }
+    }
+}
+
+/// Games with a score in `[low, high)`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScoreBin {
+    pub low: u64,
+    pub high: u64,
+    pub games: usize,
+}
+
+/// Benchmark results of an agent, as JSON or as a table through `Display`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationReport {
+    pub games: usize,
+    pub apples: Stats,
+    pub steps: Stats,
+    pub score: Stats,
+    pub win_rate: f32,
+    pub death_rate: f32,
+    pub loop_rate: f32,
+    pub timeout_rate: f32,
+    pub score_distribution: Vec<ScoreBin>,
+    pub outcomes: Vec<GameOutcome>,
+}
+
+impl EvaluationReport {
+    /// Number of bins of the score distribution
+    const BINS: u64 = 10;
+
+    pub fn new(outcomes: Vec<GameOutcome>) -> Self {
+        let n = outcomes.len().max(1) as f32;
+        let rate = |o: Outcome| outcomes.iter().filter(|g| g.outcome == o).count() as f32 / n;
+        let max_score = outcomes.iter().map(|g| g.score).max().unwrap_or(0);
+        let width = (max_score / Self::BINS + 1).max(1);
+        let score_distribution = (0..Self::BINS)
+            .map(|bin| ScoreBin {
+                low: bin * width,
+                high: (bin + 1) * width,
+                games: outcomes.iter().filter(|g| g.score / width == bin).count(),
+            })
+            .filter(|bin| bin.low <= max_score)
+            .collect();
+        Self {
+            games: outcomes.len(),
+            apples: Stats::new(outcomes.iter().map(|g| g.apples as f32)),
+            steps: Stats::new(outcomes.iter().map(|g| g.steps as f32)),
+            score: Stats::new(outcomes.iter().map(|g| g.score as f32)),
+            win_rate: rate(Outcome::Win),
+            death_rate: rate(Outcome::Died),
+            loop_rate: rate(Outcome::Looped),
+            timeout_rate: rate(Outcome::TimedOut),
+            score_distribution,
+            outcomes,
+        }
+    }
+
+    pub fn to_json(&self) -> ARes<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+impl Display for EvaluationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} games", self.games)?;
+        writeln!(
+            f,
+            "{:<8}|{:>9} |{:>9} |{:>9} |{:>9} |",
+            "", "mean", "median", "min", "max"
+        )?;
+        for (name, stats) in [
+            ("apples", self.apples),
+            ("steps", self.steps),
+            ("score", self.score),
+        ] {
+            writeln!(
+                f,
+                "{:<8}|{:>9.2} |{:>9.2} |{:>9.2} |{:>9.2} |",
+                name, stats.mean, stats.median, stats.min, stats.max
+            )?;
+        }
+        writeln!(
+            f,
+            "win {:.1}% | died {:.1}% | looped {:.1}% | timed out {:.1}%",
+            self.win_rate * 100.,
+            self.death_rate * 100.,
+            self.loop_rate * 100.,
+            self.timeout_rate * 100.
+        )?;
+        let widest = self
+            .score_distribution
+            .iter()
+            .map(|b| b.games)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        for bin in &self.score_distribution {
+            let range = format!("[{}, {})", bin.low, bin.high);
+            let bar = "#".repeat(bin.games * 40 / widest);
+            writeln!(f, "{range:>12} {bar} {}", bin.games)?;
+        }
+        Ok(())
+    }
+}
+
+/// Plays agents through the same fixed set of seeded games, so that their reports
+/// can be compared
+#[derive(Debug, Clone)]
+pub struct Evaluator {
+    game_builder: GameAPIBuilder,
+    max_steps: usize,
+    seeds: Vec<u64>,
+    /// Steps without food after which a game counts as looping, the board size by default
+    loop_patience: Option<u64>,
+}
+
+impl Evaluator {
+    /// `episodes` games with seeds drawn from `seed`, each stopped after `max_steps` steps
+    pub fn new(game_builder: GameAPIBuilder, max_steps: usize, episodes: usize, seed: u64) -> Self {
+        let mut rng = SmallRng::seed_from_u64(seed);
+        Self {
+            game_builder,
+            max_steps,
+            seeds: (0..episodes).map(|_| rng.next_u64()).collect(),
+            loop_patience: None,
+        }
+    }
+
+    pub fn with_loop_patience(mut self, patience: u64) -> Self {
+        self.loop_patience = Some(patience);
+        self
+    }
+
+    pub fn seeds(&self) -> &[u64] {
+        &self.seeds
+    }
+
+    /// Plays the game of `seed`, the player draws from an rng seeded the same way
+    pub fn play(&self, player: &impl PlayerTrait, seed: u64) -> ARes<GameOutcome> {
+        let mut env = SnakeEnv::new(self.game_builder.clone(), Some(self.max_steps));
+        env.reset(seed);
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let board = env.game().board();
+        let patience = self
+            .loop_patience
+            .unwrap_or((board.rows * board.cols) as u64);
+        loop {
+            let step = env.step(player.choose_dir(env.game(), &mut rng))?;
+            let outcome = match step.info.result {
+                StepResult::Win { .. } => Some(Outcome::Win),
+                StepResult::Lost { .. } => Some(Outcome::Died),
+                _ if env.game().steps_since_food > patience as u128 => Some(Outcome::Looped),
+                _ if step.truncated => Some(Outcome::TimedOut),
+                _ => None,
+            };
+            if let Some(outcome) = outcome {
+                return Ok(GameOutcome {
+                    seed,
+                    outcome,
+                    apples: step.info.num_of_apples as u64,
+                    steps: step.info.steps as u64,
+                    score: step.info.score as u64,
+                });
+            }
+        }
+    }
+
+    pub fn evaluate(&self, player: &impl PlayerTrait) -> ARes<EvaluationReport> {
+        let outcomes = self
+            .seeds
+            .iter()
+            .map(|seed| self.play(player, *seed))
+            .collect::<ARes<Vec<_>>>()?;
+        Ok(EvaluationReport::new(outcomes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulator::GreedyPlayer;
+
+    #[test]
+    fn greedy_player_benchmark_is_reproducible() {
+        let evaluator = Evaluator::new(GameAPIBuilder::default(), 300, 4, 7);
+        let report = evaluator.evaluate(&GreedyPlayer).expect("Valid games");
+        assert_eq!(
+            report,
+            evaluator.evaluate(&GreedyPlayer).expect("Valid games")
+        );
+        assert_eq!(report.games, 4);
+        assert!(report.apples.mean > 0.);
+        assert!(report.apples.min <= report.apples.median);
+        assert!(report.apples.median <= report.apples.max);
+        let rates = report.win_rate + report.death_rate + report.loop_rate + report.timeout_rate;
+        assert!((rates - 1.).abs() < 1e-6);
+        let binned = report
+            .score_distribution
+            .iter()
+            .map(|b| b.games)
+            .sum::<usize>();
+        assert_eq!(binned, 4);
+        assert!(report.to_string().contains("apples"));
+    }
+
+    #[test]
+    fn stats_of_an_even_count() {
+        let stats = Stats::new([4., 1., 3., 2.].into_iter());
+        assert_eq!(
+            stats,
+            Stats {
+                mean: 2.5,
+                median: 2.5,
+                min: 1.,
+                max: 4.,
+            }
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{Result as ARes, anyhow};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::common::Coord;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize,
+)]
+pub enum FoodKind {
+    /// Grows the snake by one
+    #[default]
+    Apple,
+    /// Grows the snake by one and is worth extra score
+    Bonus,
+    /// Shrinks the snake by one
+    Shrink,
+    /// Grows the snake by one, despawns if not eaten in time
+    Timed,
+}
+
+impl FoodKind {
+    pub fn score_multiplier(self) -> u128 {
+        match self {
+            Self::Apple | Self::Shrink => 1,
+            Self::Timed => 2,
+            Self::Bonus => 3,
+        }
+    }
+
+    pub fn grows(self) -> bool {
+        self != Self::Shrink
+    }
+
+    /// Code of the cell in `GameAPIBinaryRepr`
+    pub(crate) fn repr_code(self) -> i32 {
+        match self {
+            Self::Apple => 3,
+            Self::Bonus => 5,
+            Self::Shrink => 6,
+            Self::Timed => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Food {
+    pub pos: Coord,
+    pub kind: FoodKind,
+    /// Steps left before the food despawns, only set for timed food
+    pub ttl: Option<usize>,
+}
+
+/// How many food items are kept on the board and how likely each kind is to spawn.
+/// Regular apples take whatever chance is left.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "FoodFields")]
+pub struct FoodOptions {
+    pub count: usize,
+    pub bonus_chance: f64,
+    pub shrink_chance: f64,
+    pub timed_chance: f64,
+    pub timed_steps: usize,
+}
+
+/// Unchecked `FoodOptions` as they are written in configs
+#[derive(Deserialize)]
+#[serde(default)]
+struct FoodFields {
+    count: usize,
+    bonus_chance: f64,
+    shrink_chance: f64,
+    timed_chance: f64,
+    timed_steps: usize,
+}
+
+impl Default for FoodFields {
+    fn default() -> Self {
+        let FoodOptions {
+            count,
+            bonus_chance,
+            shrink_chance,
+            timed_chance,
+            timed_steps,
+        } = FoodOptions::default();
+        Self {
+            count,
+            bonus_chance,
+            shrink_chance,
+            timed_chance,
+            timed_steps,
+        }
+    }
+}
+
+impl TryFrom<FoodFields> for FoodOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(fields: FoodFields) -> ARes<Self> {
+        Self::new(
+            fields.count,
+            fields.bonus_chance,
+            fields.shrink_chance,
+            fields.timed_chance,
+            fields.timed_steps,
+        )
+    }
+}
+
+impl Default for FoodOptions {
+    fn default() -> Self {
+        Self {
+            count: 1,
+            bonus_chance: 0.,
+            shrink_chance: 0.,
+            timed_chance: 0.,
+            timed_steps: 20,
+        }
+    }
+}
+
+impl FoodOptions {
+    /// Fails without any food on the board, since such a game could never be won, or
+    /// when the chances are not probabilities adding up to at most one
+    pub fn new(
+        count: usize,
+        bonus_chance: f64,
+        shrink_chance: f64,
+        timed_chance: f64,
+        timed_steps: usize,
+    ) -> ARes<Self> {
+        if count == 0 {
+            return Err(anyhow!("At least 1 food item should be on the board"));
+        }
+        let chances = [bonus_chance, shrink_chance, timed_chance];
+        if !chances.iter().all(|c| (0. ..=1.).contains(c)) {
+            return Err(anyhow!("Food chances {chances:?} should be in [0, 1]"));
+        }
+        if chances.iter().sum::<f64>() > 1. {
+            return Err(anyhow!("Food chances {chances:?} add up to more than 1"));
+        }
+        Ok(Self {
+            count,
+            bonus_chance,
+            shrink_chance,
+            timed_chance,
+            timed_steps,
+        })
+    }
+
+    pub(crate) fn spawn(&self, pos: Coord, rng: &mut dyn RngCore) -> Food {
+        let special = self.bonus_chance + self.shrink_chance + self.timed_chance;
+        let kind = if special <= 0. {
+            FoodKind::Apple
+        } else {
+            let roll = rng.random::<f64>();
+            if roll < self.bonus_chance {
+                FoodKind::Bonus
+            } else if roll < self.bonus_chance + self.shrink_chance {
+                FoodKind::Shrink
+            } else if roll < special {
+                FoodKind::Timed
+            } else {
+                FoodKind::Apple
+            }
+        };
+        Food {
+            pos,
+            kind,
+            ttl: (kind == FoodKind::Timed).then_some(self.timed_steps),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::{GameAPI, GameOptions, StepResult},
+        level::Level,
+    };
+
+    fn game(food: FoodOptions) -> GameAPI {
+        GameAPI::from_seed(
+            7,
+            Some(GameOptions::default().with_food(food)),
+            Level::default(),
+        )
+    }
+
+    fn place_ahead(game: &mut GameAPI, kind: FoodKind, ttl: Option<usize>) {
+        let pos = game.snake.next_step().expect("Space ahead");
+        game.apples[0] = Food { pos, kind, ttl };
+    }
+
+    #[test]
+    fn keeps_food_count_on_board() {
+        let mut game = game(FoodOptions {
+            count: 3,
+            ..Default::default()
+        });
+        assert_eq!(game.apples.len(), 3);
+        place_ahead(&mut game, FoodKind::Bonus, None);
+        let res = game.next().expect("Valid step");
+        assert_eq!(
+            res,
+            StepResult::Ate {
+                kind: FoodKind::Bonus
+            }
+        );
+        assert_eq!(game.apples.len(), 3);
+        assert_eq!(game.snake.size, 1);
+        assert_eq!(game.score, 3 * game.mode.to_score());
+    }
+
+    #[test]
+    fn shrink_food_shortens_snake() {
+        let mut game = game(FoodOptions::default());
+        place_ahead(&mut game, FoodKind::Apple, None);
+        game.next().expect("Valid step");
+        assert_eq!(game.snake.size, 1);
+        place_ahead(&mut game, FoodKind::Shrink, None);
+        game.next().expect("Valid step");
+        assert_eq!(game.snake.size, 0);
+        assert_eq!(game.snake.head, game.snake.tail);
+    }
+
+    #[test]
+    fn timed_food_despawns() {
+        let mut game = game(FoodOptions::default());
+        let pos = Coord { row: 0, col: 0 };
+        game.apples[0] = Food {
+            pos,
+            kind: FoodKind::Timed,
+            ttl: Some(2),
+        };
+        game.next().expect("Valid step");
+        assert_eq!(game.apples[0].pos, pos);
+        game.next().expect("Valid step");
+        assert_eq!(game.apples.len(), 1);
+        assert_ne!(game.apples[0].pos, pos);
+    }
+
+    #[test]
+    fn reject_invalid_options() {
+        assert!(FoodOptions::new(0, 0., 0., 0., 20).is_err());
+        assert!(FoodOptions::new(1, -0.1, 0., 0., 20).is_err());
+        assert!(FoodOptions::new(1, 0.5, 0.3, 0.3, 20).is_err());
+        assert!(serde_json::from_str::<FoodOptions>(r#"{"count": 0}"#).is_err());
+        assert_eq!(
+            serde_json::from_str::<FoodOptions>(r#"{"count": 3, "bonus_chance": 0.5}"#).ok(),
+            FoodOptions::new(3, 0.5, 0., 0., 20).ok()
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::{fs, path::Path};
+
+use anyhow::Result as ARes;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{api::Speed, common::BoardSize};
+
+/// Bumped whenever the layout of `HighScores` changes
+pub const HIGHSCORE_VERSION: u16 = 1;
+
+/// Entries kept per table
+pub const MAX_ENTRIES: usize = 10;
+
+/// Games only compete with games of the same board, mode and starting difficulty
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ScoreKey {
+    pub board: BoardSize,
+    /// How the game was played, named by the front end
+    pub mode: String,
+    pub speed: Speed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreEntry {
+    pub name: String,
+    pub score: u128,
+    pub apples: u128,
+    pub steps: u128,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScoreTable {
+    pub key: ScoreKey,
+    /// Best first
+    pub entries: Vec<ScoreEntry>,
+}
+
+/// High score tables and the players who set them.
+///
+/// Loading never fails on a damaged file, every table and entry that can still be
+/// read is kept and the rest dropped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HighScores {
+    pub version: u16,
+    /// Names entered so far, the last one first
+    pub players: Vec<String>,
+    pub tables: Vec<ScoreTable>,
+}
+
+impl Default for HighScores {
+    fn default() -> Self {
+        Self {
+            version: HIGHSCORE_VERSION,
+            players: vec![],
+            tables: vec![],
+        }
+    }
+}
+
+impl HighScores {
+    /// Reads what can be read of `json`, anything else starts empty
+    pub fn from_json(json: &str) -> Self {
+        let Ok(Value::Object(mut fields)) = serde_json::from_str::<Value>(json) else {
+            return Self::default();
+        };
+        let version = fields.get("version").and_then(Value::as_u64);
+        if version.is_none_or(|v| v > HIGHSCORE_VERSION as u64) {
+            return Self::default();
+        }
+        let items = |value: Option<Value>| match value {
+            Some(Value::Array(items)) => items,
+            _ => vec![],
+        };
+        let players = items(fields.remove("players"))
+            .into_iter()
+            .filter_map(|p| serde_json::from_value(p).ok())
+            .collect();
+        let tables = items(fields.remove("tables"))
+            .into_iter()
+            .filter_map(|mut table| {
+                let key = serde_json::from_value(table.get_mut("key")?.take()).ok()?;
+                let mut entries = items(table.get_mut("entries").map(Value::take))
+                    .into_iter()
+                    .filter_map(|e| serde_json::from_value::<ScoreEntry>(e).ok())
+                    .collect::<Vec<_>>();
+                entries.sort_by_key(|e| std::cmp::Reverse(e.score));
+                entries.truncate(MAX_ENTRIES);
+                Some(ScoreTable { key, entries })
+            })
+            .collect();
+        Self {
+            version: HIGHSCORE_VERSION,
+            players,
+            tables,
+        }
+    }
+
+    /// Empty when there is no file yet. A damaged file is kept next to it with a
+    /// `.bak` extension before anything overwrites it.
+    pub fn load(path: impl AsRef<Path>) -> ARes<Self> {
+        let path = path.as_ref();
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => String::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let scores = Self::from_json(&json);
+        if serde_json::from_str::<Self>(&json).ok().as_ref() != Some(&scores) {
+            let backup = path.with_extension("bak");
+            // What was salvaged still loads, even without a copy of the damaged file
+            if let Err(e) = fs::copy(path, &backup) {
+                eprintln!(
+                    "Cannot back up {} to {}: {e}",
+                    path.display(),
+                    backup.display()
+                );
+            }
+        }
+        Ok(scores)
+    }
+
+    /// Written to a temporary file first, a crash never leaves a half written table
+    pub fn save(&self, path: impl AsRef<Path>) -> ARes<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn table(&self, key: &ScoreKey) -> &[ScoreEntry] {
+        self.tables
+            .iter()
+            .find(|t| t.key == *key)
+            .map_or(&[], |t| &t.entries)
+    }
+
+    /// Whether `score` would make it into the table of `key`
+    pub fn qualifies(&self, key: &ScoreKey, score: u128) -> bool {
+        let table = self.table(key);
+        score > 0 && (table.len() < MAX_ENTRIES || table.iter().any(|e| score > e.score))
+    }
+
+    /// Adds `entry` to the table of `key`, returning its rank from 0 if it was kept,
+    /// as `qualifies` tells. The player of a kept entry becomes the last one.
+    pub fn insert(&mut self, key: ScoreKey, entry: ScoreEntry) -> Option<usize> {
+        if !self.qualifies(&key, entry.score) {
+            return None;
+        }
+        self.players.retain(|p| *p != entry.name);
+        self.players.insert(0, entry.name.clone());
+        let indx = match self.tables.iter().position(|t| t.key == key) {
+            Some(indx) => indx,
+            None => {
+                self.tables.push(ScoreTable {
+                    key,
+                    entries: vec![],
+                });
+                self.tables.len() - 1
+            }
+        };
+        let entries = &mut self.tables[indx].entries;
+        // Ties go to the older entry
+        let rank = entries.partition_point(|e| e.score >= entry.score);
+        entries.insert(rank, entry);
+        entries.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+
+    pub fn last_player(&self) -> Option<&str> {
+        self.players.first().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, score: u128) -> ScoreEntry {
+        ScoreEntry {
+            name: name.to_owned(),
+            score,
+            apples: score / 10,
+            steps: score,
+            timestamp: 0,
+        }
+    }
+
+    #[test]
+    fn keeps_the_best_scores_per_key() {
+        let key = ScoreKey {
+            board: BoardSize::default(),
+            mode: "Human".to_owned(),
+            speed: Speed::Slow,
+        };
+        let mut scores = HighScores::default();
+        for score in 1..=MAX_ENTRIES as u128 {
+            scores.insert(key.clone(), entry("a", score * 10));
+        }
+        assert!(!scores.qualifies(&key, 5));
+        assert_eq!(scores.insert(key.clone(), entry("b", 55)), Some(5));
+        assert_eq!(scores.insert(key.clone(), entry("c", 5)), None);
+        assert_eq!(scores.table(&key).len(), MAX_ENTRIES);
+        assert_eq!(scores.table(&key)[0].score, 100);
+        assert_eq!(
+            scores.last_player(),
+            Some("b"),
+            "Dropped entries add no player"
+        );
+
+        let other = ScoreKey {
+            speed: Speed::Hard,
+            ..key.clone()
+        };
+        assert!(scores.table(&other).is_empty());
+        assert!(scores.qualifies(&other, 5));
+        assert_eq!(scores.insert(other.clone(), entry("d", 0)), None);
+        assert!(scores.table(&other).is_empty());
+    }
+
+    #[test]
+    fn salvages_damaged_files() {
+        let mut scores = HighScores::default();
+        let key = ScoreKey {
+            board: BoardSize::new(8, 8).expect("Large enough"),
+            mode: "Human".to_owned(),
+            speed: Speed::Medium,
+        };
+        scores.insert(key.clone(), entry("a", 30));
+        scores.insert(key.clone(), entry("b", 20));
+        let mut json: Value = serde_json::to_value(&scores).expect("Serializable");
+        json["tables"][0]["entries"][1]["score"] = "lots".into();
+        json["tables"]
+            .as_array_mut()
+            .expect("Tables")
+            .push(3.into());
+        let salvaged = HighScores::from_json(&json.to_string());
+        assert_eq!(salvaged.table(&key), &[entry("a", 30)]);
+        assert_eq!(salvaged.tables.len(), 1);
+
+        assert_eq!(
+            HighScores::from_json("{\"version\": 1, \"tab"),
+            HighScores::default()
+        );
+        json["version"] = (HIGHSCORE_VERSION + 1).into();
+        assert_eq!(
+            HighScores::from_json(&json.to_string()),
+            HighScores::default()
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+
+use anyhow::Result as ARes;
+
+use crate::{
+    api::{GameAPI, GameSnapshot, StepResult},
+    common::Direction,
+};
+
+/// Snapshots of the last few steps of a game, the oldest are dropped past `capacity`
+#[derive(Debug, Clone)]
+pub struct UndoHistory {
+    snapshots: VecDeque<GameSnapshot>,
+    capacity: usize,
+}
+
+impl UndoHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    /// Remembers the current state of `game`
+    pub fn push(&mut self, game: &GameAPI) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(game.snapshot());
+    }
+
+    /// Saves `game` then steps it towards `dir`
+    pub fn step(&mut self, game: &mut GameAPI, dir: Direction) -> ARes<StepResult> {
+        self.push(game);
+        game.update_direction(dir);
+        game.next()
+    }
+
+    /// Rewinds `game` by one step, false when there is nothing left to undo
+    pub fn undo(&mut self, game: &mut GameAPI) -> bool {
+        self.undo_n(game, 1) == 1
+    }
+
+    /// Rewinds `game` by up to `n` steps, returns how many were undone
+    pub fn undo_n(&mut self, game: &mut GameAPI, n: usize) -> usize {
+        let n = n.min(self.snapshots.len());
+        if n == 0 {
+            return 0;
+        }
+        let keep = self.snapshots.len() - n;
+        if let Some(snapshot) = self.snapshots.get(keep) {
+            game.restore(snapshot);
+        }
+        self.snapshots.truncate(keep);
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::GameAPIBuilder, food::FoodOptions};
+
+    fn game() -> GameAPI {
+        GameAPIBuilder::default()
+            .with_food(FoodOptions {
+                count: 3,
+                bonus_chance: 0.5,
+                ..Default::default()
+            })
+            .with_seed(9)
+            .build(None)
+    }
+
+    const MOVES: [Direction; 6] = [
+        Direction::Up,
+        Direction::Left,
+        Direction::Down,
+        Direction::Down,
+        Direction::Right,
+        Direction::Right,
+    ];
+
+    fn play(game: &mut GameAPI) -> Vec<u32> {
+        MOVES
+            .iter()
+            .map(|dir| {
+                game.update_direction(*dir);
+                game.next().expect("Valid step");
+                game.checksum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn restore_replays_the_same_branch() {
+        let mut game = game();
+        let snapshot = game.snapshot();
+        let first = play(&mut game);
+        game.restore(&snapshot);
+        assert_eq!(game.checksum(), snapshot.game().checksum());
+        assert_eq!(play(&mut game), first);
+    }
+
+    #[test]
+    fn undo_is_bounded() {
+        let mut game = game();
+        let start = game.checksum();
+        let mut history = UndoHistory::new(4);
+        let checksums = MOVES
+            .iter()
+            .map(|dir| {
+                let sum = game.checksum();
+                history.step(&mut game, *dir).expect("Valid step");
+                sum
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(history.len(), 4);
+        assert!(history.undo(&mut game));
+        assert_eq!(game.checksum(), checksums[5]);
+        assert_eq!(history.undo_n(&mut game, 10), 3);
+        assert_eq!(game.checksum(), checksums[2]);
+        assert_ne!(game.checksum(), start);
+        assert!(!history.undo(&mut game));
+    }
+}
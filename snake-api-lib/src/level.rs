@@ -0,0 +1,188 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{Result as ARes, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::common::{BoardSize, Coord, GridBits};
+
+/// Static layout of a board, the walls and obstacles the snake has to avoid.
+///
+/// The snake always starts in the middle of the board, so a level cannot place
+/// a wall there.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Level {
+    board: BoardSize,
+    walls: GridBits,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self::empty(BoardSize::default())
+    }
+}
+
+impl Level {
+    pub fn empty(board: BoardSize) -> Self {
+        Self {
+            board,
+            walls: board.empty_bits(),
+        }
+    }
+
+    pub fn new(board: BoardSize, walls: impl IntoIterator<Item = Coord>) -> ARes<Self> {
+        let mut level = Self::empty(board);
+        for wall in walls {
+            if !board.contains(wall) {
+                return Err(anyhow!("Wall {:?} out of bounds for {:?}", wall, board));
+            }
+            level.walls.set(wall.into_index(board), true);
+        }
+        if level.is_wall(Coord::middle(board)) {
+            return Err(anyhow!(
+                "Starting cell {:?} is a wall",
+                Coord::middle(board)
+            ));
+        }
+        Ok(level)
+    }
+
+    /// Box with walls on every edge cell
+    pub fn bordered(board: BoardSize) -> Self {
+        let (rows, cols) = (board.rows as i16, board.cols as i16);
+        let edges = (0..rows)
+            .flat_map(|row| [Coord { row, col: 0 }, Coord { row, col: cols - 1 }])
+            .chain((0..cols).flat_map(|col| [Coord { row: 0, col }, Coord { row: rows - 1, col }]));
+        Self::new(board, edges).expect("Edges are in bounds and never the middle")
+    }
+
+    pub fn board(&self) -> BoardSize {
+        self.board
+    }
+
+    pub fn is_wall(&self, coord: Coord) -> bool {
+        self.board.contains(coord) && self.walls[coord.into_index(self.board)]
+    }
+
+    pub fn walls(&self) -> impl Iterator<Item = Coord> + '_ {
+        self.walls
+            .iter_ones()
+            .map(|indx| Coord::from_index(indx, self.board))
+    }
+
+    /// Copy of the level with more obstacles, skipping the start cell checks
+    pub(crate) fn with_extra_walls(&self, walls: impl IntoIterator<Item = Coord>) -> Self {
+        let mut level = self.clone();
+        for wall in walls.into_iter().filter(|c| self.board.contains(*c)) {
+            level.walls.set(wall.into_index(self.board), true);
+        }
+        level
+    }
+
+    pub(crate) fn wall_bits(&self) -> &GridBits {
+        &self.walls
+    }
+}
+
+/// Parses an ascii layout, one line per row, `#` for walls and `.` for empty cells
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>();
+        let cols = rows.first().map(|r| r.chars().count()).unwrap_or(0);
+        if cols == 0 || rows.iter().any(|r| r.chars().count() != cols) {
+            return Err(anyhow!(
+                "Level rows should be non empty and of equal length"
+            ));
+        }
+        let mut walls = vec![];
+        for (row, line) in rows.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                match c {
+                    '#' => walls.push(Coord {
+                        row: row as i16,
+                        col: col as i16,
+                    }),
+                    '.' => {}
+                    _ => return Err(anyhow!("Unknown level cell {c:?}")),
+                }
+            }
+        }
+        Self::new(BoardSize::new(rows.len(), cols)?, walls)
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in 0..self.board.rows {
+            for col in 0..self.board.cols {
+                let c = Coord {
+                    row: row as i16,
+                    col: col as i16,
+                };
+                write!(f, "{}", if self.is_wall(c) { '#' } else { '.' })?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Level {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Level> for String {
+    fn from(value: Level) -> Self {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_roundtrip() {
+        let layout = "#####\n#...#\n#...#\n#...#\n#####\n";
+        let level: Level = layout.parse().expect("Valid layout");
+        let board = BoardSize::new(5, 5).expect("Large enough");
+        assert_eq!(level.board(), board);
+        assert_eq!(level.walls().count(), 16);
+        assert_eq!(level, Level::bordered(board));
+        assert_eq!(level.to_string(), layout);
+    }
+
+    #[test]
+    fn reject_invalid_layouts() {
+        assert!("###\n##\n".parse::<Level>().is_err());
+        assert!("...\n.x.\n...\n".parse::<Level>().is_err());
+        assert!(
+            ".....\n.....\n..#..\n.....\n.....\n"
+                .parse::<Level>()
+                .is_err()
+        );
+        let board = BoardSize::new(4, 4).expect("Large enough");
+        assert!(Level::new(board, [Coord { row: 4, col: 0 }]).is_err());
+    }
+
+    #[test]
+    fn reject_tiny_boards() {
+        assert!("....\n....\n....\n".parse::<Level>().is_err());
+        assert!(BoardSize::new(0, 12).is_err());
+        assert!(serde_json::from_str::<BoardSize>(r#"{"rows": 3, "cols": 3}"#).is_err());
+        assert_eq!(
+            serde_json::from_str::<BoardSize>(r#"{"rows": 4, "cols": 6}"#).ok(),
+            BoardSize::new(4, 6).ok()
+        );
+    }
+}
@@ -1,7 +1,20 @@
 pub mod api;
-pub mod simulator;
+pub mod arena;
 pub mod common;
+pub mod evaluation;
+pub mod food;
+pub mod highscore;
+pub mod history;
+pub mod level;
+pub mod observation;
+pub mod replay;
+pub mod reward;
+pub mod simulator;
+pub mod vec_env;
 
 mod snake;
 
 pub mod prelude;
+
+/// Version of ndarray used by `GameAPIBinaryRepr` and `VecEnv`
+pub use ndarray;
@@ -0,0 +1,448 @@
+use std::{collections::VecDeque, fmt::Debug};
+
+use anyhow::{Result as ARes, anyhow};
+use ndarray::{Array2, Array3, Axis, concatenate};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{GameAPI, GameAPIBinaryRepr},
+    common::{Coord, Direction, Topology},
+};
+
+/// Encoded game fed to agents, shaped `[rows, cols, channels]`
+pub type Observation = Array3<f32>;
+
+/// Cell code of walls in `GameAPIBinaryRepr`
+const WALL_CODE: i32 = 4;
+
+/// Turns a game into what an agent sees of it
+pub trait ObservationEncoder: Debug + Send + Sync {
+    /// Channels of every encoded observation
+    fn channels(&self) -> usize;
+    fn encode(&self, game: &GameAPI) -> Observation;
+}
+
+/// One channel per cell code, plus `extra` zeroed channels after them
+fn one_hot(codes: &Array2<i32>, extra: usize) -> Observation {
+    let (rows, cols) = codes.dim();
+    let mut obs = Array3::zeros((rows, cols, GameAPIBinaryRepr::CHANNELS + extra));
+    for ((row, col), code) in codes.indexed_iter() {
+        obs[[row, col, *code as usize]] = 1.;
+    }
+    obs
+}
+
+/// The whole board one hot encoded, the original encoding
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OneHotEncoder;
+
+impl ObservationEncoder for OneHotEncoder {
+    fn channels(&self) -> usize {
+        GameAPIBinaryRepr::CHANNELS
+    }
+
+    fn encode(&self, game: &GameAPI) -> Observation {
+        one_hot(&game.to_game_repr().0, 0)
+    }
+}
+
+/// One hot board plus the age of every body cell, one at the head down to
+/// `1 / len` at the tail
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BodyAgeEncoder;
+
+impl ObservationEncoder for BodyAgeEncoder {
+    fn channels(&self) -> usize {
+        GameAPIBinaryRepr::CHANNELS + 1
+    }
+
+    fn encode(&self, game: &GameAPI) -> Observation {
+        let mut obs = one_hot(&game.to_game_repr().0, 1);
+        let body = game.snake.body();
+        let len = body.len() as f32;
+        for (age, cell) in body.into_iter().enumerate() {
+            obs[[
+                cell.row as usize,
+                cell.col as usize,
+                GameAPIBinaryRepr::CHANNELS,
+            ]] = (age + 1) as f32 / len;
+        }
+        obs
+    }
+}
+
+/// One hot board plus the cell the head moves into next
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectionEncoder;
+
+impl ObservationEncoder for DirectionEncoder {
+    fn channels(&self) -> usize {
+        GameAPIBinaryRepr::CHANNELS + 1
+    }
+
+    fn encode(&self, game: &GameAPI) -> Observation {
+        let mut obs = one_hot(&game.to_game_repr().0, 1);
+        if let Some(next) = game.neighbour(game.snake.head, game.snake.direction) {
+            obs[[
+                next.row as usize,
+                next.col as usize,
+                GameAPIBinaryRepr::CHANNELS,
+            ]] = 1.;
+        }
+        obs
+    }
+}
+
+/// Smallest window radius, the convolutional trunk pools a 3x3 window down to one cell
+pub const MIN_EGO_RADIUS: usize = 1;
+
+/// Square window of `2 * radius + 1` cells around the head, turned so the snake
+/// always heads up. Cells past a bounded board read as walls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "EgoCentricRadius")]
+pub struct EgoCentricEncoder {
+    pub radius: usize,
+}
+
+/// Unchecked `EgoCentricEncoder` as it is written in configs
+#[derive(Deserialize)]
+struct EgoCentricRadius {
+    radius: usize,
+}
+
+impl TryFrom<EgoCentricRadius> for EgoCentricEncoder {
+    type Error = anyhow::Error;
+
+    fn try_from(window: EgoCentricRadius) -> ARes<Self> {
+        Self::new(window.radius)
+    }
+}
+
+impl EgoCentricEncoder {
+    /// Fails below `MIN_EGO_RADIUS`
+    pub fn new(radius: usize) -> ARes<Self> {
+        if radius < MIN_EGO_RADIUS {
+            return Err(anyhow!(
+                "Ego centric radius {radius} is smaller than {MIN_EGO_RADIUS}"
+            ));
+        }
+        Ok(Self { radius })
+    }
+}
+
+impl ObservationEncoder for EgoCentricEncoder {
+    fn channels(&self) -> usize {
+        GameAPIBinaryRepr::CHANNELS
+    }
+
+    fn encode(&self, game: &GameAPI) -> Observation {
+        let GameAPIBinaryRepr(codes) = game.to_game_repr();
+        let board = game.board();
+        let head = game.snake.head;
+        let radius = self.radius as i16;
+        let side = 2 * self.radius + 1;
+        // Clockwise quarter turns from heading up to the actual heading
+        let turns = (game.snake.direction as usize + 3) % 4;
+        let window = Array2::from_shape_fn((side, side), |(row, col)| {
+            let (mut d_row, mut d_col) = (row as i16 - radius, col as i16 - radius);
+            for _ in 0..turns {
+                (d_row, d_col) = (d_col, -d_row);
+            }
+            let mut cell = head
+                + Coord {
+                    row: d_row,
+                    col: d_col,
+                };
+            if game.topology() == Topology::Toroidal {
+                cell.row = cell.row.rem_euclid(board.rows as i16);
+                cell.col = cell.col.rem_euclid(board.cols as i16);
+            }
+            if board.contains(cell) {
+                codes[[cell.row as usize, cell.col as usize]]
+            } else {
+                WALL_CODE
+            }
+        });
+        one_hot(&window, 0)
+    }
+}
+
+/// The classic 11 features, danger straight, right and left of the head, the
+/// direction of travel and where the nearest food is, shaped `[1, 1, 11]`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeatureEncoder;
+
+impl FeatureEncoder {
+    pub const FEATURES: usize = 11;
+}
+
+impl ObservationEncoder for FeatureEncoder {
+    fn channels(&self) -> usize {
+        Self::FEATURES
+    }
+
+    fn encode(&self, game: &GameAPI) -> Observation {
+        let mask = game.action_mask();
+        let dir = game.snake.direction as usize;
+        let head = game.snake.head;
+        let food = game.nearest_food(head).map(|f| f.pos);
+        // Closer after a step that way, through the edges when the board wraps around
+        let towards = |d: Direction| {
+            food.is_some_and(|f| {
+                game.neighbour(head, d)
+                    .is_some_and(|next| game.distance(next, f) < game.distance(head, f))
+            })
+        };
+        let danger = [dir, (dir + 1) % 4, (dir + 3) % 4].map(|d| !mask[d]);
+        let heading = std::array::from_fn::<_, 4, _>(|d| d == dir);
+        let food = std::array::from_fn::<_, 4, _>(|d| towards(Direction::from(d)));
+        let features = danger
+            .into_iter()
+            .chain(heading)
+            .chain(food)
+            .map(|b| b as u8 as f32)
+            .collect::<Vec<_>>();
+        Array3::from_shape_vec((1, 1, Self::FEATURES), features)
+            .expect("Features fill the whole shape")
+    }
+}
+
+/// Any of the built in encoders, tagged by `kind` in configs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    OneHot,
+    BodyAge,
+    Direction,
+    EgoCentric(EgoCentricEncoder),
+    Features,
+}
+
+impl Encoding {
+    /// Whether observations are boards, rather than a flat vector of features
+    pub fn is_spatial(self) -> bool {
+        !matches!(self, Self::Features)
+    }
+}
+
+impl ObservationEncoder for Encoding {
+    fn channels(&self) -> usize {
+        match self {
+            Self::OneHot => OneHotEncoder.channels(),
+            Self::BodyAge => BodyAgeEncoder.channels(),
+            Self::Direction => DirectionEncoder.channels(),
+            Self::EgoCentric(e) => e.channels(),
+            Self::Features => FeatureEncoder.channels(),
+        }
+    }
+
+    fn encode(&self, game: &GameAPI) -> Observation {
+        match self {
+            Self::OneHot => OneHotEncoder.encode(game),
+            Self::BodyAge => BodyAgeEncoder.encode(game),
+            Self::Direction => DirectionEncoder.encode(game),
+            Self::EgoCentric(e) => e.encode(game),
+            Self::Features => FeatureEncoder.encode(game),
+        }
+    }
+}
+
+/// How agents observe games, an encoding and how many of the last frames are stacked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObservationConfig {
+    pub encoding: Encoding,
+    pub frames: usize,
+}
+
+impl Default for ObservationConfig {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::default(),
+            frames: 1,
+        }
+    }
+}
+
+impl ObservationConfig {
+    /// Channels of the stacked observations
+    pub fn channels(&self) -> usize {
+        self.encoding.channels() * self.frames.max(1)
+    }
+
+    pub fn is_spatial(&self) -> bool {
+        self.encoding.is_spatial()
+    }
+
+    /// Empty history for a single game
+    pub fn stack(&self) -> FrameStack {
+        FrameStack {
+            encoding: self.encoding,
+            capacity: self.frames.max(1),
+            frames: VecDeque::new(),
+        }
+    }
+}
+
+/// Last frames of a single game stacked on the channel axis, oldest first
+#[derive(Debug, Clone)]
+pub struct FrameStack {
+    encoding: Encoding,
+    capacity: usize,
+    frames: VecDeque<Observation>,
+}
+
+impl FrameStack {
+    /// Starts over from `game`, repeating its frame to fill the stack
+    pub fn reset(&mut self, game: &GameAPI) -> Observation {
+        let frame = self.encoding.encode(game);
+        self.frames = std::iter::repeat_n(frame, self.capacity).collect();
+        self.observation()
+    }
+
+    /// Adds the frame of `game` after a step, dropping the oldest one
+    pub fn push(&mut self, game: &GameAPI) -> Observation {
+        if self.frames.is_empty() {
+            return self.reset(game);
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(self.encoding.encode(game));
+        self.observation()
+    }
+
+    /// Same as `push`, except that games which were not stepped yet start a new stack
+    pub fn observe(&mut self, game: &GameAPI) -> Observation {
+        if game.steps == 0 {
+            self.reset(game)
+        } else {
+            self.push(game)
+        }
+    }
+
+    pub fn observation(&self) -> Observation {
+        let views = self.frames.iter().map(|f| f.view()).collect::<Vec<_>>();
+        concatenate(Axis(2), &views).expect("Frames of a game share their shape")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::GameAPIBuilder,
+        food::{Food, FoodKind},
+    };
+
+    fn stepped(dirs: &[Direction]) -> GameAPI {
+        let mut game = GameAPIBuilder::default().with_seed(3).build(None);
+        for dir in dirs {
+            game.update_direction(*dir);
+            game.next().expect("Valid step");
+        }
+        game
+    }
+
+    #[test]
+    fn encodings_match_their_channels() {
+        let game = stepped(&[Direction::Up, Direction::Right]);
+        let board = game.board();
+        for encoding in [
+            Encoding::OneHot,
+            Encoding::BodyAge,
+            Encoding::Direction,
+            Encoding::EgoCentric(EgoCentricEncoder { radius: 3 }),
+            Encoding::Features,
+        ] {
+            let obs = encoding.encode(&game);
+            assert_eq!(obs.dim().2, encoding.channels());
+            let cells = obs.dim().0 * obs.dim().1;
+            if encoding.is_spatial() {
+                // Every cell has exactly one code
+                let codes = obs.slice(ndarray::s![.., .., ..GameAPIBinaryRepr::CHANNELS]);
+                assert_eq!(codes.sum() as usize, cells);
+            }
+            if matches!(encoding, Encoding::OneHot | Encoding::BodyAge) {
+                assert_eq!((obs.dim().0, obs.dim().1), (board.rows, board.cols));
+            }
+        }
+    }
+
+    #[test]
+    fn ego_centric_turns_with_the_snake() {
+        let encoder = EgoCentricEncoder { radius: 1 };
+        let up = stepped(&[Direction::Up]);
+        let mut left = up.clone();
+        left.update_direction(Direction::Left);
+        // The cell ahead of the head is the top middle of the window either way
+        for game in [&up, &left] {
+            let ahead = game
+                .neighbour(game.snake.head, game.snake.direction)
+                .expect("Room ahead");
+            let code = game.to_game_repr().0[[ahead.row as usize, ahead.col as usize]];
+            assert_eq!(encoder.encode(game)[[0, 1, code as usize]], 1.);
+        }
+        assert_eq!(encoder.encode(&up)[[1, 1, 1]], 1., "Head in the middle");
+    }
+
+    #[test]
+    fn reject_empty_ego_centric_windows() {
+        assert!(EgoCentricEncoder::new(0).is_err());
+        let parse = |json| serde_json::from_str::<Encoding>(json).ok();
+        assert_eq!(parse(r#"{"kind": "ego_centric", "radius": 0}"#), None);
+        assert_eq!(
+            parse(r#"{"kind": "ego_centric", "radius": 2}"#),
+            Some(Encoding::EgoCentric(EgoCentricEncoder { radius: 2 }))
+        );
+    }
+
+    #[test]
+    fn frames_stack_oldest_first() {
+        let config = ObservationConfig {
+            encoding: Encoding::Features,
+            frames: 3,
+        };
+        let mut stack = config.stack();
+        let start = stepped(&[]);
+        let obs = stack.observe(&start);
+        assert_eq!(obs.dim(), (1, 1, config.channels()));
+        let next = stepped(&[Direction::Up]);
+        let obs = stack.observe(&next);
+        let features = FeatureEncoder::FEATURES;
+        let first = FeatureEncoder.encode(&start);
+        let last = FeatureEncoder.encode(&next);
+        assert_eq!(obs.slice(ndarray::s![.., .., ..features]), first);
+        assert_eq!(obs.slice(ndarray::s![.., .., 2 * features..]), last);
+    }
+
+    #[test]
+    fn features_point_through_wrapping_edges() {
+        let food_features = |topology| {
+            let mut game = GameAPIBuilder::default()
+                .with_topology(topology)
+                .with_seed(3)
+                .build(None);
+            game.apples.clear();
+            for _ in 0..3 {
+                game.update_direction(Direction::Left);
+                game.next().expect("Valid step");
+            }
+            // Three cells to the left through the edge, more the other way
+            let head = game.snake.head;
+            game.apples = vec![Food {
+                pos: Coord {
+                    row: head.row,
+                    col: game.board().cols as i16 - 1,
+                },
+                kind: FoodKind::Apple,
+                ttl: None,
+            }];
+            let obs = FeatureEncoder.encode(&game);
+            [Direction::Left, Direction::Right].map(|d| obs[[0, 0, 7 + d as usize]])
+        };
+        assert_eq!(food_features(Topology::Bounded), [0., 1.]);
+        assert_eq!(food_features(Topology::Toroidal), [1., 0.]);
+    }
+}
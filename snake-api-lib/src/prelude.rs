@@ -1,4 +1,13 @@
 pub use crate::api;
+pub use crate::arena;
 pub use crate::common;
+pub use crate::evaluation;
+pub use crate::food;
+pub use crate::highscore;
+pub use crate::history;
+pub use crate::level;
+pub use crate::observation;
+pub use crate::replay;
+pub use crate::reward;
 pub use crate::simulator;
-pub(crate) use crate::snake;
+pub use crate::vec_env;
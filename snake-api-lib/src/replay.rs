@@ -0,0 +1,307 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result as ARes, anyhow};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::{GameAPI, GameOptions, StepResult},
+    common::Direction,
+    level::Level,
+};
+
+/// Bumped whenever the layout of `Replay` changes
+pub const REPLAY_VERSION: u16 = 3;
+
+/// First bytes of every binary replay
+const MAGIC: &[u8; 4] = b"SNKR";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayStep {
+    pub direction: Direction,
+    pub result: StepResult,
+    /// `GameAPI::checksum` right after the step
+    pub checksum: u32,
+}
+
+/// Everything needed to play a game again, the session seed, the options and the moves.
+///
+/// Stored either as JSON or as a compact binary, a 4 byte magic and the version
+/// followed by the bincode encoded replay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u16,
+    pub seed: u64,
+    pub game_options: GameOptions,
+    pub level: Level,
+    /// `GameAPI::checksum` before the first step
+    pub initial_checksum: u32,
+    pub steps: Vec<ReplayStep>,
+}
+
+impl Replay {
+    /// Fresh copy of the game as it was before the first step
+    pub fn initial_game(&self) -> GameAPI {
+        GameAPI::from_seed(self.seed, Some(self.game_options), self.level.clone())
+    }
+
+    /// Result of the last recorded step
+    pub fn outcome(&self) -> Option<StepResult> {
+        self.steps.last().map(|s| s.result)
+    }
+
+    /// Every state of the game from the start, each one checked against the recording.
+    /// Stops after the first mismatch.
+    pub fn play(&self) -> ReplayPlayback<'_> {
+        ReplayPlayback {
+            replay: self,
+            game: None,
+            next_step: 0,
+            failed: false,
+        }
+    }
+
+    pub fn to_json(&self) -> ARes<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    pub fn from_json(s: &str) -> ARes<Self> {
+        let replay: Self = serde_json::from_str(s)?;
+        check_version(replay.version)?;
+        Ok(replay)
+    }
+
+    pub fn to_bytes(&self) -> ARes<Vec<u8>> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(self.version.to_le_bytes());
+        bytes.extend(bincode::serde::encode_to_vec(
+            self,
+            bincode::config::standard(),
+        )?);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> ARes<Self> {
+        let payload = bytes
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| anyhow!("Not a replay file"))?;
+        let (version, payload) = payload
+            .split_first_chunk::<2>()
+            .ok_or_else(|| anyhow!("Replay header is truncated"))?;
+        check_version(u16::from_le_bytes(*version))?;
+        let (replay, _) = bincode::serde::decode_from_slice(payload, bincode::config::standard())?;
+        Ok(replay)
+    }
+
+    /// Writes JSON for `.json` files and the binary format otherwise
+    pub fn save(&self, path: impl AsRef<Path>) -> ARes<()> {
+        let path = path.as_ref();
+        if is_json(path) {
+            fs::write(path, self.to_json()?)?;
+        } else {
+            fs::write(path, self.to_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> ARes<Self> {
+        let path = path.as_ref();
+        if is_json(path) {
+            Self::from_json(&fs::read_to_string(path)?)
+        } else {
+            Self::from_bytes(&fs::read(path)?)
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+fn check_version(version: u16) -> ARes<()> {
+    if version != REPLAY_VERSION {
+        return Err(anyhow!(
+            "Unsupported replay version {version}, expected {REPLAY_VERSION}"
+        ));
+    }
+    Ok(())
+}
+
+/// Iterator returned by `Replay::play`
+#[derive(Debug)]
+pub struct ReplayPlayback<'a> {
+    replay: &'a Replay,
+    game: Option<GameAPI>,
+    next_step: usize,
+    failed: bool,
+}
+
+impl ReplayPlayback<'_> {
+    fn advance(&mut self) -> Option<ARes<GameAPI>> {
+        let Some(game) = self.game.as_mut() else {
+            let game = self.replay.initial_game();
+            if game.checksum() != self.replay.initial_checksum {
+                return Some(Err(anyhow!("Initial state does not match the replay")));
+            }
+            self.game = Some(game.clone());
+            return Some(Ok(game));
+        };
+        let step = self.replay.steps.get(self.next_step)?;
+        self.next_step += 1;
+        game.update_direction(step.direction);
+        let result = match game.next() {
+            Ok(result) => result,
+            Err(e) => return Some(Err(e)),
+        };
+        if result != step.result {
+            return Some(Err(anyhow!(
+                "Step {} ended in {:?}, recorded {:?}",
+                self.next_step,
+                result,
+                step.result
+            )));
+        }
+        if game.checksum() != step.checksum {
+            return Some(Err(anyhow!("Step {} checksum mismatch", self.next_step)));
+        }
+        Some(Ok(game.clone()))
+    }
+}
+
+impl Iterator for ReplayPlayback<'_> {
+    type Item = ARes<GameAPI>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let item = self.advance();
+        self.failed = matches!(item, Some(Err(_)));
+        item
+    }
+}
+
+/// Records the moves of a game as it is played
+#[derive(Debug, Clone)]
+pub struct ReplayRecorder {
+    replay: Replay,
+}
+
+impl ReplayRecorder {
+    /// Starts recording `game`, which should not have been stepped yet
+    pub fn new(game: &GameAPI) -> Self {
+        Self {
+            replay: Replay {
+                version: REPLAY_VERSION,
+                seed: game.seed(),
+                game_options: game.game_options,
+                level: game.level.clone(),
+                initial_checksum: game.checksum(),
+                steps: vec![],
+            },
+        }
+    }
+
+    /// Records a step already applied to `game`
+    pub fn record(&mut self, game: &GameAPI, result: StepResult) {
+        self.replay.steps.push(ReplayStep {
+            direction: game.snake.direction,
+            result,
+            checksum: game.checksum(),
+        });
+    }
+
+    /// Steps `game` towards `dir` and records it
+    pub fn step(&mut self, game: &mut GameAPI, dir: Direction) -> ARes<StepResult> {
+        game.update_direction(dir);
+        let result = game.next()?;
+        self.record(game, result);
+        Ok(result)
+    }
+
+    /// Forgets the last `steps` steps, for games rewound with `UndoHistory`
+    pub fn rewind(&mut self, steps: usize) {
+        let keep = self.replay.steps.len().saturating_sub(steps);
+        self.replay.steps.truncate(keep);
+    }
+
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    pub fn finish(self) -> Replay {
+        self.replay
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::GameAPIBuilder,
+        food::FoodOptions,
+        simulator::{GreedyPlayer, PlayerTrait},
+    };
+    use rand::prelude::*;
+
+    fn recorded_game() -> Replay {
+        let mut rng = SmallRng::seed_from_u64(5);
+        let mut game = GameAPIBuilder::default()
+            .with_food(FoodOptions {
+                count: 2,
+                timed_chance: 0.5,
+                ..Default::default()
+            })
+            .with_level(Level::bordered(Default::default()))
+            .with_seed(11)
+            .build(None);
+        let mut recorder = ReplayRecorder::new(&game);
+        for _ in 0..200 {
+            let dir = GreedyPlayer.choose_dir(&game, &mut rng);
+            if recorder
+                .step(&mut game, dir)
+                .expect("Valid step")
+                .is_terminal()
+            {
+                break;
+            }
+        }
+        recorder.finish()
+    }
+
+    #[test]
+    fn playback_matches_recording() {
+        let replay = recorded_game();
+        let states = replay
+            .play()
+            .collect::<ARes<Vec<_>>>()
+            .expect("Replay in sync");
+        assert_eq!(states.len(), replay.steps.len() + 1);
+        assert_eq!(
+            states.last().map(GameAPI::checksum),
+            replay.steps.last().map(|s| s.checksum)
+        );
+    }
+
+    #[test]
+    fn binary_and_json_roundtrip() {
+        let replay = recorded_game();
+        let bytes = replay.to_bytes().expect("Encodes");
+        assert_eq!(Replay::from_bytes(&bytes).expect("Decodes"), replay);
+        let json = replay.to_json().expect("Encodes");
+        assert_eq!(Replay::from_json(&json).expect("Decodes"), replay);
+
+        let mut future = bytes.clone();
+        future[4] = 99;
+        assert!(Replay::from_bytes(&future).is_err());
+        assert!(Replay::from_bytes(b"nope").is_err());
+    }
+
+    #[test]
+    fn detects_tampering() {
+        let mut replay = recorded_game();
+        replay.steps[0].checksum ^= 1;
+        let results = replay.play().collect::<Vec<_>>();
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+    }
+}
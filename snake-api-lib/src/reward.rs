@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+use crate::api::{GameAPI, StepResult};
+
+/// Scores a single step of a game from the states around it
+pub trait RewardFn: Debug + Send + Sync {
+    fn reward(&self, before: &GameAPI, after: &GameAPI, result: StepResult) -> f32;
+}
+
+/// Distance from the head to the closest food, `None` once the board is empty
+fn food_distance(game: &GameAPI) -> Option<f32> {
+    let head = game.snake.head;
+    game.nearest_food(head)
+        .map(|f| game.distance(head, f.pos) as f32)
+}
+
+/// Only rewards the outcome, eating, winning or losing
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SparseReward {
+    pub food: f32,
+    pub win: f32,
+    pub lose: f32,
+}
+
+impl Default for SparseReward {
+    fn default() -> Self {
+        Self {
+            food: 1.,
+            win: 1.,
+            lose: -1.,
+        }
+    }
+}
+
+impl RewardFn for SparseReward {
+    fn reward(&self, _before: &GameAPI, _after: &GameAPI, result: StepResult) -> f32 {
+        match result {
+            StepResult::Ate { .. } => self.food,
+            StepResult::Win { .. } => self.win,
+            StepResult::Lost { .. } => self.lose,
+            StepResult::Base => 0.,
+        }
+    }
+}
+
+/// Rewards plain steps that bring the head closer to the nearest food
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DistanceReward {
+    pub closer: f32,
+    pub further: f32,
+    /// Stops shaping once the snake is this long
+    #[serde(default)]
+    pub max_size: Option<usize>,
+}
+
+impl RewardFn for DistanceReward {
+    fn reward(&self, before: &GameAPI, after: &GameAPI, result: StepResult) -> f32 {
+        if result != StepResult::Base || self.max_size.is_some_and(|m| before.snake.size >= m) {
+            return 0.;
+        }
+        match (food_distance(before), food_distance(after)) {
+            (Some(b), Some(a)) if a < b => self.closer,
+            (Some(_), Some(_)) => self.further,
+            _ => 0.,
+        }
+    }
+}
+
+/// Flat bonus for every step the snake lives through
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SurvivalReward {
+    pub bonus: f32,
+}
+
+impl RewardFn for SurvivalReward {
+    fn reward(&self, _before: &GameAPI, _after: &GameAPI, result: StepResult) -> f32 {
+        match result {
+            StepResult::Lost { .. } => 0.,
+            _ => self.bonus,
+        }
+    }
+}
+
+/// Penalises every step after `patience` steps without food, against looping forever
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StarvationReward {
+    pub patience: u64,
+    pub penalty: f32,
+}
+
+impl RewardFn for StarvationReward {
+    fn reward(&self, _before: &GameAPI, after: &GameAPI, result: StepResult) -> f32 {
+        if result == StepResult::Base && after.steps_since_food > self.patience as u128 {
+            self.penalty
+        } else {
+            0.
+        }
+    }
+}
+
+/// Potential based shaping, `gamma * phi(after) - phi(before)` with `phi` the negated
+/// food distance scaled by the board. Leaves the optimal policy unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PotentialReward {
+    pub scale: f32,
+    pub gamma: f32,
+}
+
+impl PotentialReward {
+    fn potential(&self, game: &GameAPI) -> f32 {
+        let board = game.board();
+        food_distance(game).map_or(0., |d| -self.scale * d / (board.rows + board.cols) as f32)
+    }
+}
+
+impl RewardFn for PotentialReward {
+    fn reward(&self, before: &GameAPI, after: &GameAPI, result: StepResult) -> f32 {
+        // Terminal states have no potential
+        let after = if result.is_terminal() {
+            0.
+        } else {
+            self.potential(after)
+        };
+        self.gamma * after - self.potential(before)
+    }
+}
+
+/// Any of the built in rewards, tagged by `kind` in configs
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RewardTerm {
+    Sparse(SparseReward),
+    Distance(DistanceReward),
+    Survival(SurvivalReward),
+    Starvation(StarvationReward),
+    Potential(PotentialReward),
+}
+
+impl RewardFn for RewardTerm {
+    fn reward(&self, before: &GameAPI, after: &GameAPI, result: StepResult) -> f32 {
+        match self {
+            Self::Sparse(r) => r.reward(before, after, result),
+            Self::Distance(r) => r.reward(before, after, result),
+            Self::Survival(r) => r.reward(before, after, result),
+            Self::Starvation(r) => r.reward(before, after, result),
+            Self::Potential(r) => r.reward(before, after, result),
+        }
+    }
+}
+
+/// Sum of several terms, plain sparse when empty
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CombinedReward(pub Vec<RewardTerm>);
+
+impl RewardFn for CombinedReward {
+    fn reward(&self, before: &GameAPI, after: &GameAPI, result: StepResult) -> f32 {
+        if self.0.is_empty() {
+            return SparseReward::default().reward(before, after, result);
+        }
+        self.0.iter().map(|r| r.reward(before, after, result)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::GameAPIBuilder, common::Direction};
+
+    /// Steps a fresh game towards `dir`, returning the states around the step
+    fn step(dir: Direction) -> (GameAPI, GameAPI, StepResult) {
+        let before = GameAPIBuilder::default().with_seed(3).build(None);
+        let mut after = before.clone();
+        after.update_direction(dir);
+        let result = after.next().expect("Valid step");
+        (before, after, result)
+    }
+
+    #[test]
+    fn shaping_follows_the_food() {
+        let distance = DistanceReward {
+            closer: 1.,
+            further: -1.,
+            max_size: None,
+        };
+        let potential = PotentialReward {
+            scale: 1.,
+            gamma: 1.,
+        };
+        for dir in [Direction::Left, Direction::Up, Direction::Right] {
+            let (before, after, result) = step(dir);
+            assert_eq!(result, StepResult::Base);
+            let closer = food_distance(&after) < food_distance(&before);
+            let shaped = distance.reward(&before, &after, result);
+            assert_eq!(shaped, if closer { 1. } else { -1. });
+            assert_eq!(potential.reward(&before, &after, result) > 0., closer);
+        }
+    }
+
+    #[test]
+    fn parses_and_sums_terms() {
+        let reward: CombinedReward = serde_json::from_str(
+            r#"[
+                {"kind": "sparse", "food": 5, "win": 10, "lose": -10},
+                {"kind": "survival", "bonus": 0.5},
+                {"kind": "starvation", "patience": 0, "penalty": -2}
+            ]"#,
+        )
+        .expect("Valid config");
+        let (before, after, result) = step(Direction::Up);
+        assert_eq!(reward.reward(&before, &after, result), -1.5);
+        let lost = StepResult::Lost {
+            num_steps: 0,
+            number_of_fruits: 0,
+            snake_size: 1,
+            level_reached: Default::default(),
+        };
+        assert_eq!(reward.reward(&before, &after, lost), -10.);
+        assert_eq!(CombinedReward::default().reward(&before, &after, lost), -1.);
+    }
+}
@@ -3,20 +3,161 @@
  * Ideally the following API should be optimised such that each player has its own optimised output
  * Can be refactored later down the line so we will denote this as a TODO task
  */
-use crate::prelude::{api::*, common::*, snake::*};
+use crate::prelude::{api::*, common::*, reward::*};
 use anyhow::Result as ARes;
 use itertools::Itertools;
 use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
 use strum::IntoEnumIterator;
 
 pub trait PlayerTrait {
     fn choose_dir(&self, game_instance: &GameAPI, with_rng: &mut dyn RngCore) -> Direction;
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Heads for the nearest food along a safe cell, keeps going if boxed in
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GreedyPlayer;
+
+impl PlayerTrait for GreedyPlayer {
+    fn choose_dir(&self, game_instance: &GameAPI, with_rng: &mut dyn RngCore) -> Direction {
+        let head = game_instance.snake.head;
+        let target = game_instance.nearest_food(head).map(|f| f.pos);
+        let safe = Direction::iter()
+            .filter_map(|d| {
+                game_instance
+                    .neighbour(head, d)
+                    .filter(|next| game_instance.is_free(*next))
+                    .map(|next| (d, next))
+            })
+            .collect_vec();
+        match target {
+            Some(target) => safe
+                .iter()
+                .min_by_key(|(_, next)| game_instance.distance(*next, target))
+                .map(|(d, _)| *d),
+            None => safe.choose(with_rng).map(|(d, _)| *d),
+        }
+        .unwrap_or(game_instance.snake.direction)
+    }
+}
+
+/// Extra details about a step, beyond what agents learn from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepInfo {
+    pub result: StepResult,
+    pub steps: u128,
+    pub score: u128,
+    pub num_of_apples: u128,
+}
+
+/// Outcome of `Environment::step`
+#[derive(Debug, Clone)]
+pub struct EnvStep<O> {
+    pub observation: O,
+    pub reward: f32,
+    /// The game ended, won or lost
+    pub terminated: bool,
+    /// The episode hit its step limit before the game ended
+    pub truncated: bool,
+    pub info: StepInfo,
+}
+
+impl<O> EnvStep<O> {
+    /// Same step seen through another observation, e.g. an `ObservationEncoder`
+    pub fn with_observation<P>(self, observation: P) -> EnvStep<P> {
+        EnvStep {
+            observation,
+            reward: self.reward,
+            terminated: self.terminated,
+            truncated: self.truncated,
+            info: self.info,
+        }
+    }
+}
+
+/// Gym style interface, agents drive the game one action at a time
+pub trait Environment {
+    type Observation;
+
+    /// Starts a new episode, the same seed always gives the same episode
+    fn reset(&mut self, seed: u64) -> Self::Observation;
+    fn step(&mut self, action: Direction) -> ARes<EnvStep<Self::Observation>>;
+    /// Actions that do not kill the snake right away, indexed by `Direction as usize`
+    fn action_mask(&self) -> [bool; 4];
+}
+
+/// Single snake game as an `Environment`, observed through `GameAPIBinaryRepr`
+#[derive(Debug)]
+pub struct SnakeEnv {
+    game_builder: GameAPIBuilder,
+    game: GameAPI,
+    reward_fn: Box<dyn RewardFn>,
+    max_steps: Option<usize>,
+    episode_steps: usize,
+}
+
+impl SnakeEnv {
+    /// Episodes are truncated after `max_steps` steps when set
+    pub fn new(game_builder: GameAPIBuilder, max_steps: Option<usize>) -> Self {
+        Self {
+            game: game_builder.clone().build(None),
+            game_builder,
+            reward_fn: Box::new(SparseReward::default()),
+            max_steps,
+            episode_steps: 0,
+        }
+    }
+
+    pub fn with_reward_fn(mut self, reward_fn: impl RewardFn + 'static) -> Self {
+        self.reward_fn = Box::new(reward_fn);
+        self
+    }
+
+    pub fn game(&self) -> &GameAPI {
+        &self.game
+    }
+}
+
+impl Environment for SnakeEnv {
+    type Observation = GameAPIBinaryRepr;
+
+    fn reset(&mut self, seed: u64) -> Self::Observation {
+        self.game = self.game_builder.clone().with_seed(seed).build(None);
+        self.episode_steps = 0;
+        self.game.to_game_repr()
+    }
+
+    fn step(&mut self, action: Direction) -> ARes<EnvStep<Self::Observation>> {
+        let before = self.game.clone();
+        self.game.update_direction(action);
+        let result = self.game.next()?;
+        self.episode_steps += 1;
+        let terminated = result.is_terminal();
+        Ok(EnvStep {
+            observation: self.game.to_game_repr(),
+            reward: self.reward_fn.reward(&before, &self.game, result),
+            terminated,
+            truncated: !terminated && self.max_steps.is_some_and(|m| self.episode_steps >= m),
+            info: StepInfo {
+                result,
+                steps: self.game.steps,
+                score: self.game.score,
+                num_of_apples: self.game.num_of_apples,
+            },
+        })
+    }
+
+    fn action_mask(&self) -> [bool; 4] {
+        self.game.action_mask()
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Simulator {
     game_builder: GameAPIBuilder,
     pub simulator_options: SimulatorOptions,
+    reward_fn: CombinedReward,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -24,20 +165,25 @@ pub struct SimulatorOptions {
     pub number_of_iterations: usize,
 }
 
-#[derive(Clone, Debug)]
-pub struct SimulationStep {
-    pub snapshot: GameAPIBinaryRepr,
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SimulationStep<O = GameAPIBinaryRepr> {
+    pub snapshot: O,
     pub direction: Direction,
-    pub reward: SimulationStepReward,
-    pub next_state: Option<GameAPIBinaryRepr>,
+    pub reward: f32,
+    /// Board after the step, `None` once the game ended
+    pub next_state: Option<O>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum SimulationStepReward {
-    Step(bool),
-    Food,
-    Won,
-    Lost,
+impl<O> SimulationStep<O> {
+    /// Labels a step taken towards `dir` from the board `snapshot`
+    pub fn from_step(snapshot: O, dir: Direction, step: EnvStep<O>) -> Self {
+        Self {
+            snapshot,
+            direction: dir,
+            reward: step.reward,
+            next_state: (!step.terminated).then_some(step.observation),
+        }
+    }
 }
 
 impl Simulator {
@@ -45,99 +191,93 @@ impl Simulator {
         Self {
             game_builder,
             simulator_options,
+            reward_fn: CombinedReward::default(),
         }
     }
 
-    fn handle_next_step(
+    pub fn with_reward_fn(mut self, reward_fn: CombinedReward) -> Self {
+        self.reward_fn = reward_fn;
+        self
+    }
+
+    /// Plays and records a single episode, along with the info of its last step
+    pub fn simulation(
         &self,
-        before_step: u128,
-        before_step_repr: GameAPIBinaryRepr,
-        dir: Direction,
-        next_step: StepResult,
-        game_instance: &GameAPI,
-    ) -> SimulationStep {
-        match next_step {
-            StepResult::Lost { .. } => SimulationStep {
-                snapshot: before_step_repr,
-                direction: dir,
-                reward: SimulationStepReward::Lost,
-                next_state: None,
-            },
-            StepResult::Win { .. } => SimulationStep {
-                snapshot: before_step_repr,
-                direction: dir,
-                reward: SimulationStepReward::Won,
-                next_state: None,
-            },
-            StepResult::Base => {
-                let step_rew = if game_instance.snake.size >= 10 {
-                    false
-                } else {
-                    let sn_head = game_instance.snake.head;
-                    let ap_head = game_instance.apples;
-                    let s = Direction::iter().collect_array::<4>().unwrap().map(|d| {
-                        let t1 = game_instance
-                            .snake
-                            .check_cell(sn_head.add_dir(d))
-                            .is_some_and(|x| !x);
-                        t1 && sn_head.l1(ap_head) > sn_head.add_dir(d).l1(ap_head)
-                    });
-                    s[dir as usize]
-                };
-                // let measure_optimal = _;
-                SimulationStep {
-                    snapshot: before_step_repr,
-                    direction: dir,
-                    reward: if before_step == game_instance.num_of_apples {
-                        SimulationStepReward::Step(step_rew)
-                    } else {
-                        SimulationStepReward::Food
-                    },
-                    next_state: Some(game_instance.to_game_repr()),
-                }
+        player: &impl PlayerTrait,
+        rng: &mut impl RngCore,
+    ) -> ARes<(Vec<SimulationStep>, StepInfo)> {
+        let mut env = SnakeEnv::new(
+            self.game_builder.clone(),
+            Some(self.simulator_options.number_of_iterations),
+        )
+        .with_reward_fn(self.reward_fn.clone());
+        env.reset(rng.next_u64());
+        let mut snapshots = vec![];
+        loop {
+            let snapshot = env.game().to_game_repr();
+            let dir = player.choose_dir(env.game(), rng);
+            let step = env.step(dir)?;
+            let (done, info) = (step.terminated || step.truncated, step.info);
+            snapshots.push(SimulationStep::from_step(snapshot, dir, step));
+            if done {
+                return Ok((snapshots, info));
             }
         }
     }
 
-    pub fn simulation(
+    /// Plays a single episode without recording it, returns the info of its last step
+    pub fn play_episode(
         &self,
         player: &impl PlayerTrait,
         rng: &mut impl RngCore,
-        with_summary: bool,
-    ) -> ARes<Vec<SimulationStep>> {
-        let mut game_instance = self.game_builder.build(Some(rng));
-        let mut snapshots = vec![];
-        let mut num_iter = 0usize;
+    ) -> ARes<StepInfo> {
+        let mut env = SnakeEnv::new(
+            self.game_builder.clone(),
+            Some(self.simulator_options.number_of_iterations),
+        );
+        env.reset(rng.next_u64());
         loop {
-            num_iter += 1;
-            let before_step = game_instance.num_of_apples;
-            let before_step_repr = game_instance.to_game_repr();
-            let dir = player.choose_dir(&game_instance, rng);
-            game_instance.update_direction(dir);
-            let next_step = game_instance.next(rng)?;
-            let otp = self.handle_next_step(
-                before_step,
-                before_step_repr,
-                dir,
-                next_step,
-                &game_instance,
-            );
-            snapshots.push(otp);
-            if matches!(next_step, StepResult::Win { .. } | StepResult::Lost { .. }) {
-                if with_summary {
-                    dbg!((game_instance.num_of_apples, game_instance.steps));
-                }
-                break;
-            } else if num_iter >= self.simulator_options.number_of_iterations {
-                snapshots.push(SimulationStep {
-                    snapshot: game_instance.to_game_repr(),
-                    direction: dir,
-                    reward: SimulationStepReward::Lost,
-                    next_state: None,
-                });
-                break;
+            let dir = player.choose_dir(env.game(), rng);
+            let step = env.step(dir)?;
+            if step.terminated || step.truncated {
+                return Ok(step.info);
             }
         }
-        ARes::Ok(snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn env_reset_is_seeded_and_truncates() {
+        let mut env = SnakeEnv::new(GameAPIBuilder::default(), Some(3));
+        let first = env.reset(4);
+        assert_eq!(env.reset(4).0, first.0);
+        assert_eq!(env.action_mask(), [true; 4]);
+        let dirs = [Direction::Up, Direction::Right, Direction::Down];
+        let steps = dirs
+            .iter()
+            .map(|d| env.step(*d).expect("Valid step"))
+            .collect::<Vec<_>>();
+        assert!(steps[..2].iter().all(|s| !s.truncated && !s.terminated));
+        assert!(steps[2].truncated);
+        assert_eq!(steps[2].info.steps, 3);
+    }
+
+    #[test]
+    fn greedy_episode_eats() {
+        let simulator = Simulator::new(
+            GameAPIBuilder::default(),
+            SimulatorOptions {
+                number_of_iterations: 200,
+            },
+        );
+        let info = simulator
+            .play_episode(&GreedyPlayer, &mut SmallRng::seed_from_u64(2))
+            .expect("Valid episode");
+        assert!(info.num_of_apples > 0);
+        assert!(info.steps <= 200);
     }
 }
@@ -2,20 +2,20 @@ use std::fmt::{Debug, Display};
 
 use crate::{
     api::SnakeTrait,
-    common::{Coord, Direction, GRID_X, GRID_Y},
+    common::{BoardSize, Coord, Direction, GridBits, Topology},
+    level::Level,
 };
 use anyhow::{Result as AResult, anyhow};
-use bitvec::prelude::*;
 use itertools::Itertools;
 use rand::prelude::*;
 use strum::IntoEnumIterator;
 
-// Fixed-size bit array
-type GridBits = BitArr!(for GRID_X * GRID_Y, in u64, Msb0); // 768 bits using u64 storage
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct ArrSnake {
     maps: [GridBits; 4],
+    walls: GridBits,
+    pub board: BoardSize,
+    pub topology: Topology,
     pub direction: Direction,
     pub head: Coord,
     pub tail: Coord,
@@ -24,17 +24,7 @@ pub struct ArrSnake {
 
 impl Default for ArrSnake {
     fn default() -> Self {
-        let mut maps: [GridBits; 4] = Default::default();
-        let middle = Coord::middle();
-        let def_dir: Direction = Default::default();
-        maps[def_dir as usize].set(middle.into_index(), true);
-        Self {
-            maps,
-            direction: def_dir,
-            head: middle,
-            tail: middle,
-            size: 0,
-        }
+        Self::new(BoardSize::default())
     }
 }
 
@@ -42,19 +32,19 @@ impl Display for ArrSnake {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         writeln!(f, "\n Head {:?} Tail {:?}", self.head, self.tail)?;
         write!(f, " \\|")?;
-        for i in 0..GRID_Y {
+        for i in 0..self.board.cols {
             write!(f, "{:^3}|", i + 1)?;
         }
         writeln!(f)?;
-        for i in 0..GRID_X {
+        for i in 0..self.board.rows {
             let i_print = i + 1;
             write!(f, "{i_print:^2}|")?;
-            for j in 0..GRID_Y {
+            for j in 0..self.board.cols {
                 let indx = Coord {
                     row: i as i16,
                     col: j as i16,
                 }
-                .into_index();
+                .into_index(self.board);
                 let direction_vecs = [
                     self.maps[Direction::Left as usize][indx],
                     self.maps[Direction::Right as usize][indx],
@@ -77,46 +67,150 @@ impl Display for ArrSnake {
     }
 }
 
-fn add_direction(coord: Coord, direction: Direction) -> AResult<Coord> {
-    if coord.row >= GRID_X as i16 || coord.col >= GRID_Y as i16 {
+fn add_direction(
+    board: BoardSize,
+    topology: Topology,
+    coord: Coord,
+    direction: Direction,
+) -> AResult<Coord> {
+    if !board.contains(coord) {
         return Err(anyhow!("Out of bounds"));
     }
 
-    match (coord, direction) {
-        (Coord { col: 0, .. }, Direction::Left) | (Coord { row: 0, .. }, Direction::Up) => {
-            Err(anyhow!("Invalid coordinate {:?}, {:?}", coord, direction))
+    board.neighbour(coord, direction, topology).ok_or(anyhow!(
+        "Invalid coordinate {:?}, {:?}",
+        coord,
+        direction
+    ))
+}
+
+impl ArrSnake {
+    pub fn new(board: BoardSize) -> Self {
+        Self::with_level(&Level::empty(board))
+    }
+
+    pub fn with_level(level: &Level) -> Self {
+        Self::spawn(level, Coord::middle(level.board()), Default::default())
+    }
+
+    /// Snake of a single cell at `head` heading towards `direction`
+    pub fn spawn(level: &Level, head: Coord, direction: Direction) -> Self {
+        let board = level.board();
+        let mut maps: [GridBits; 4] = std::array::from_fn(|_| board.empty_bits());
+        maps[direction as usize].set(head.into_index(board), true);
+        Self {
+            maps,
+            walls: level.wall_bits().clone(),
+            board,
+            topology: Topology::default(),
+            direction,
+            head,
+            tail: head,
+            size: 0,
         }
-        (Coord { col, .. }, Direction::Right) if col == GRID_Y as i16 - 1 => {
-            Err(anyhow!("Invalid coordinate {:?}, {:?}", coord, direction))
+    }
+
+    pub fn with_topology(mut self, topology: Topology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Replaces the obstacles the snake collides with
+    pub(crate) fn set_level(&mut self, level: &Level) {
+        self.walls = level.wall_bits().clone();
+    }
+
+    /// Every cell covered by the snake, head and tail included
+    pub fn cells(&self) -> Vec<Coord> {
+        self.occupied()
+            .iter_ones()
+            .map(|indx| Coord::from_index(indx, self.board))
+            .collect()
+    }
+
+    /// Cells of the snake in order, from the tail up to the head
+    pub fn body(&self) -> Vec<Coord> {
+        let mut body = vec![self.tail];
+        let mut cell = self.tail;
+        while cell != self.head && body.len() <= self.size {
+            let indx = cell.into_index(self.board);
+            let Some(dir) = Direction::iter().find(|d| self.maps[*d as usize][indx]) else {
+                break;
+            };
+            let Ok(next) = add_direction(self.board, self.topology, cell, dir) else {
+                break;
+            };
+            body.push(next);
+            cell = next;
         }
-        (Coord { row, .. }, Direction::Down) if row == GRID_X as i16 - 1 => {
-            Err(anyhow!("Invalid coordinate {:?}, {:?}", coord, direction))
+        body
+    }
+
+    fn occupied(&self) -> GridBits {
+        let mut occ = self.maps[0].clone();
+        for map in &self.maps[1..] {
+            occ |= map.as_bitslice();
         }
-        (c, d) => Ok(c.add_dir(d)),
+        occ
     }
-}
 
-impl ArrSnake {
     pub fn next_step(&self) -> AResult<Coord> {
-        add_direction(self.head, self.direction)
+        add_direction(self.board, self.topology, self.head, self.direction)
     }
-    pub fn get_free_spot(&self, rng: &mut dyn RngCore) -> Option<Coord> {
-        let empty_locs = (self.maps[0] | self.maps[1] | self.maps[2] | self.maps[3])
-            .iter_zeros()
-            .filter(|x| *x < GRID_X * GRID_Y)
-            .collect_vec();
-        let filtered: Box<dyn Iterator<Item = usize>> = if self.size < 10 {
-            Box::new(empty_locs.into_iter().filter(|x| {
-                let dist = self.head.l1(Coord::from_index(*x));
+
+    pub fn is_wall(&self, coords: Coord) -> bool {
+        self.board.contains(coords) && self.walls[coords.into_index(self.board)]
+    }
+
+    /// Random empty cell, not a wall nor one of the `taken` ones. Short snakes get
+    /// the food close to their head whenever there is room for it.
+    pub fn get_free_spot(&self, rng: &mut dyn RngCore, taken: &[Coord]) -> Option<Coord> {
+        let mut blocked = self.occupied();
+        blocked |= self.walls.as_bitslice();
+        for coord in taken.iter().filter(|c| self.board.contains(**c)) {
+            blocked.set(coord.into_index(self.board), true);
+        }
+        let empty_locs = blocked.iter_zeros().collect_vec();
+        let near_locs = empty_locs
+            .iter()
+            .copied()
+            .filter(|x| {
+                let dist = self.board.distance(
+                    self.head,
+                    Coord::from_index(*x, self.board),
+                    self.topology,
+                );
                 (2..=5).contains(&dist)
-            }))
+            })
+            .collect_vec();
+        let filtered = if self.size < 10 && !near_locs.is_empty() {
+            near_locs
         } else {
-            Box::new(empty_locs.into_iter())
+            empty_locs
         };
-        filtered.choose(rng).map(|x| Coord {
-            row: (x / GRID_Y) as i16,
-            col: (x % GRID_Y) as i16,
-        })
+        filtered
+            .choose(rng)
+            .map(|x| Coord::from_index(*x, self.board))
+    }
+
+    /// Drops the last cell of the tail, a snake of size zero keeps its head
+    pub fn shrink(&mut self) -> AResult<()> {
+        if self.size == 0 {
+            return Ok(());
+        }
+        self.size -= 1;
+        self.advance_tail()
+    }
+
+    fn advance_tail(&mut self) -> AResult<()> {
+        let tail_index = self.tail.into_index(self.board);
+        for dir in Direction::iter() {
+            if self.maps[dir as usize][tail_index] {
+                self.tail = add_direction(self.board, self.topology, self.tail, dir)?;
+            }
+            self.maps[dir as usize].set(tail_index, false);
+        }
+        Ok(())
     }
 }
 
@@ -124,39 +218,35 @@ impl SnakeTrait for ArrSnake {
     fn is_next_valid(&self) -> bool {
         self.next_step()
             .ok()
+            .filter(|e| !self.is_wall(*e))
             .and_then(|e| self.check_cell(e).map(|x| !x))
             .is_some_and(|x| x)
     }
 
     fn check_cell(&self, coords: Coord) -> Option<bool> {
-        if !(0..GRID_X as i16).contains(&coords.row) || !(0..GRID_Y as i16).contains(&coords.col) {
+        if !self.board.contains(coords) {
             return None;
         }
-        let indx = coords.into_index();
+        let indx = coords.into_index(self.board);
         Some(self.maps.iter().any(|arr| arr[indx]))
     }
 
     fn set_direction(&mut self, dir: Direction) {
         self.direction = dir;
+        let head_index = self.head.into_index(self.board);
         for map in self.maps.iter_mut() {
-            map.set(self.head.into_index(), false);
+            map.set(head_index, false);
         }
-        self.maps[dir as usize].set(self.head.into_index(), true);
+        self.maps[dir as usize].set(head_index, true);
     }
 
     fn step(&mut self, with_food: bool) -> AResult<()> {
         self.size += with_food as usize;
         if !with_food {
-            let tail_index = self.tail.into_index();
-            for dir in Direction::iter() {
-                if self.maps[dir as usize][tail_index] {
-                    self.tail = add_direction(self.tail, dir)?;
-                }
-                self.maps[dir as usize].set(tail_index, false);
-            }
+            self.advance_tail()?;
         }
         let res = self.next_step()?;
-        let index = res.into_index();
+        let index = res.into_index(self.board);
         {
             let mut ind = self.maps[self.direction as usize]
                 .get_mut(index)
@@ -169,9 +259,7 @@ impl SnakeTrait for ArrSnake {
     }
 
     fn get_elements(&self) -> Vec<bool> {
-        (self.maps[0] | self.maps[1] | self.maps[2] | self.maps[3])
-            .into_iter()
-            .collect_vec()
+        self.occupied().into_iter().collect_vec()
     }
 }
 
@@ -183,56 +271,71 @@ mod tests {
     fn should_contain_in_middle_start() {
         let snake = ArrSnake::default();
         let els = snake.get_elements();
-        assert_eq!(els.len(), GRID_X * GRID_Y);
-        let middle = Coord::middle();
+        assert_eq!(els.len(), snake.board.area());
+        let middle = Coord::middle(snake.board);
         let rest = els
             .iter()
             .enumerate()
-            .filter(|(ind, _)| *ind != middle.into_index())
-            .all(|(_, b)| *b == false);
+            .filter(|(ind, _)| *ind != middle.into_index(snake.board))
+            .all(|(_, b)| !*b);
         assert!(rest);
         assert_eq!(snake.direction, Direction::Left);
-        assert!(els[middle.into_index()], "Middle should be true on init");
+        assert!(
+            els[middle.into_index(snake.board)],
+            "Middle should be true on init"
+        );
     }
 
     #[test]
     fn step_one_left_from_beginning_no_food() {
         let mut snake = ArrSnake::default();
         snake.step(false).expect("Should step normally");
-        let middle = Coord::middle() - Coord { row: 0, col: 1 };
+        let middle = Coord::middle(snake.board) - Coord { row: 0, col: 1 };
         let els = snake.get_elements();
-        assert_eq!(els.len(), GRID_X * GRID_Y);
+        assert_eq!(els.len(), snake.board.area());
         let rest = els
             .iter()
             .enumerate()
-            .filter(|(ind, _)| *ind != middle.into_index())
-            .all(|(_, b)| *b == false);
+            .filter(|(ind, _)| *ind != middle.into_index(snake.board))
+            .all(|(_, b)| !*b);
         assert_eq!(snake.head, middle);
         assert_eq!(snake.tail, middle);
         assert!(rest);
         assert_eq!(snake.direction, Direction::Left);
-        assert!(els[middle.into_index()], "Middle should be true on init");
+        assert!(
+            els[middle.into_index(snake.board)],
+            "Middle should be true on init"
+        );
     }
 
     #[test]
     fn step_one_left_from_beginning_food() {
         let mut snake = ArrSnake::default();
         snake.step(true).expect("Should step normally");
-        let middle = Coord::middle() - Coord { row: 0, col: 1 };
+        let middle = Coord::middle(snake.board) - Coord { row: 0, col: 1 };
         let els = snake.get_elements();
-        assert_eq!(els.len(), GRID_X * GRID_Y);
+        assert_eq!(els.len(), snake.board.area());
         let rest = els
             .iter()
             .enumerate()
-            .filter(|(ind, _)| ![Coord::middle().into_index(), middle.into_index()].contains(ind))
+            .filter(|(ind, _)| {
+                ![
+                    Coord::middle(snake.board).into_index(snake.board),
+                    middle.into_index(snake.board),
+                ]
+                .contains(ind)
+            })
             .all(|(_, b)| !*b);
         assert_eq!(snake.head, middle);
-        assert_eq!(snake.tail, Coord::middle());
+        assert_eq!(snake.tail, Coord::middle(snake.board));
         assert!(rest);
         assert_eq!(snake.direction, Direction::Left);
-        assert!(els[middle.into_index()], "Middle should be true on init");
         assert!(
-            els[Coord::middle().into_index()],
+            els[middle.into_index(snake.board)],
+            "Middle should be true on init"
+        );
+        assert!(
+            els[Coord::middle(snake.board).into_index(snake.board)],
             "Middle should be true on init"
         );
         println!("{}", snake);
@@ -244,21 +347,29 @@ mod tests {
         snake.step(true).expect("Should step normally");
         snake.set_direction(Direction::Up);
         snake.step(false).expect("Should step normally");
-        let left = Coord::middle() - Coord { row: 0, col: 1 };
+        let left = Coord::middle(snake.board) - Coord { row: 0, col: 1 };
         let up = left - Coord { row: 1, col: 0 };
         let els = snake.get_elements();
-        assert_eq!(els.len(), GRID_X * GRID_Y);
+        assert_eq!(els.len(), snake.board.area());
         let rest = els
             .iter()
             .enumerate()
-            .filter(|(ind, _)| ![left.into_index(), up.into_index()].contains(ind))
+            .filter(|(ind, _)| {
+                ![left.into_index(snake.board), up.into_index(snake.board)].contains(ind)
+            })
             .all(|(_, b)| !*b);
         assert_eq!(snake.head, up);
         assert_eq!(snake.tail, left);
         assert!(rest);
         assert_eq!(snake.direction, Direction::Up);
-        assert!(els[up.into_index()], "Middle should be true on init");
-        assert!(els[left.into_index()], "Middle should be true on init");
+        assert!(
+            els[up.into_index(snake.board)],
+            "Middle should be true on init"
+        );
+        assert!(
+            els[left.into_index(snake.board)],
+            "Middle should be true on init"
+        );
         println!("{}", snake);
     }
 
@@ -271,5 +382,60 @@ mod tests {
         snake.set_direction(Direction::Right);
         snake.step(false).expect("Should step normally");
         println!("{}", snake);
+        let left = Coord::middle(snake.board) - Coord { row: 0, col: 1 };
+        let up = left - Coord { row: 1, col: 0 };
+        assert_eq!(snake.body(), vec![left, up, up + Coord { row: 0, col: 1 }]);
+    }
+
+    #[test]
+    fn step_on_non_square_board() {
+        let mut snake = ArrSnake::new(BoardSize::new(8, 20).expect("Large enough"));
+        assert_eq!(snake.get_elements().len(), 160);
+        assert_eq!(snake.head, Coord { row: 4, col: 10 });
+        snake.set_direction(Direction::Down);
+        for _ in 0..3 {
+            snake.step(false).expect("Should step normally");
+        }
+        assert_eq!(snake.head, Coord { row: 7, col: 10 });
+        assert!(!snake.is_next_valid(), "Bottom edge should stop the snake");
+        assert!(snake.step(false).is_err());
+    }
+
+    #[test]
+    fn wall_blocks_next_step() {
+        let board = BoardSize::default();
+        let middle = Coord::middle(board);
+        let level = Level::new(board, [middle - Coord { row: 0, col: 2 }]).expect("Valid level");
+        let mut snake = ArrSnake::with_level(&level);
+        assert!(snake.is_next_valid());
+        snake.step(false).expect("Should step normally");
+        assert!(!snake.is_next_valid(), "Wall ahead should stop the snake");
+        snake.set_direction(Direction::Up);
+        assert!(snake.is_next_valid());
+    }
+
+    #[test]
+    fn wrap_around_edges() {
+        let board = BoardSize::new(5, 5).expect("Large enough");
+        let mut snake = ArrSnake::new(board).with_topology(Topology::Toroidal);
+        snake.step(true).expect("Should step normally");
+        snake.step(false).expect("Should step normally");
+        assert_eq!(snake.head, Coord { row: 2, col: 0 });
+        assert!(snake.is_next_valid(), "Left edge should wrap around");
+        snake.step(false).expect("Should wrap around");
+        assert_eq!(snake.head, Coord { row: 2, col: 4 });
+        snake
+            .step(false)
+            .expect("Tail should follow through the edge");
+        assert_eq!(snake.tail, Coord { row: 2, col: 4 });
+        assert_eq!(snake.head, Coord { row: 2, col: 3 });
+        assert_eq!(
+            board.distance(
+                Coord { row: 0, col: 0 },
+                Coord { row: 4, col: 4 },
+                snake.topology
+            ),
+            2
+        );
     }
 }
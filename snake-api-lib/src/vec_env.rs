@@ -0,0 +1,224 @@
+use anyhow::{Result as ARes, anyhow};
+use ndarray::{Array3, Axis, stack};
+use rand::prelude::*;
+use rayon::prelude::*;
+
+use crate::{
+    api::{GameAPI, GameAPIBinaryRepr, GameAPIBuilder},
+    common::Direction,
+    reward::RewardFn,
+    simulator::{EnvStep, Environment, SnakeEnv},
+};
+
+/// One game of the batch and the stream of seeds its episodes start from
+#[derive(Debug)]
+struct EnvSlot {
+    env: SnakeEnv,
+    seeds: SmallRng,
+    done: bool,
+}
+
+impl EnvSlot {
+    fn reset(&mut self) {
+        self.env.reset(self.seeds.next_u64());
+        self.done = false;
+    }
+}
+
+/// Batch of `SnakeEnv`s stepped in parallel.
+///
+/// With auto reset a finished game starts its next episode within the same `step`,
+/// otherwise it is left alone until `reset`. Every game draws its episode seeds from
+/// its own stream, so runs do not depend on thread scheduling.
+#[derive(Debug)]
+pub struct VecEnv {
+    slots: Vec<EnvSlot>,
+    auto_reset: bool,
+}
+
+impl VecEnv {
+    pub fn new(
+        game_builder: GameAPIBuilder,
+        max_steps: Option<usize>,
+        num_envs: usize,
+        seed: u64,
+    ) -> Self {
+        let mut seeds = SmallRng::seed_from_u64(seed);
+        let mut vec_env = Self {
+            slots: (0..num_envs)
+                .map(|_| EnvSlot {
+                    env: SnakeEnv::new(game_builder.clone(), max_steps),
+                    seeds: SmallRng::seed_from_u64(seeds.next_u64()),
+                    done: false,
+                })
+                .collect(),
+            auto_reset: true,
+        };
+        vec_env.reset();
+        vec_env
+    }
+
+    pub fn with_reward_fn(mut self, reward_fn: impl RewardFn + Clone + 'static) -> Self {
+        self.slots = self
+            .slots
+            .into_iter()
+            .map(|slot| EnvSlot {
+                env: slot.env.with_reward_fn(reward_fn.clone()),
+                ..slot
+            })
+            .collect();
+        self
+    }
+
+    pub fn with_auto_reset(mut self, auto_reset: bool) -> Self {
+        self.auto_reset = auto_reset;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Starts a new episode in every game
+    pub fn reset(&mut self) -> Vec<GameAPIBinaryRepr> {
+        self.slots.par_iter_mut().for_each(EnvSlot::reset);
+        self.observations()
+    }
+
+    /// Starts a new episode in game `indx` only, for batches without auto reset
+    pub fn reset_game(&mut self, indx: usize) {
+        self.slots[indx].reset();
+    }
+
+    pub fn game(&self, indx: usize) -> &GameAPI {
+        self.slots[indx].env.game()
+    }
+
+    pub fn games(&self) -> impl Iterator<Item = &GameAPI> {
+        self.slots.iter().map(|slot| slot.env.game())
+    }
+
+    /// Whether the game finished and waits for a reset, never set with auto reset
+    pub fn is_done(&self, indx: usize) -> bool {
+        self.slots[indx].done
+    }
+
+    pub fn all_done(&self) -> bool {
+        self.slots.iter().all(|slot| slot.done)
+    }
+
+    pub fn observations(&self) -> Vec<GameAPIBinaryRepr> {
+        self.slots
+            .par_iter()
+            .map(|slot| slot.env.game().to_game_repr())
+            .collect()
+    }
+
+    /// Observations of every game stacked on the first axis
+    pub fn batched_observations(&self) -> Array3<i32> {
+        let observations = self.observations();
+        let views = observations.iter().map(|o| o.0.view()).collect::<Vec<_>>();
+        stack(Axis(0), &views).expect("Games of a batch share the board size")
+    }
+
+    pub fn action_masks(&self) -> Vec<[bool; 4]> {
+        self.slots
+            .iter()
+            .map(|slot| slot.env.action_mask())
+            .collect()
+    }
+
+    /// Steps every game still running with its action.
+    ///
+    /// Finished games get the observation they ended on, with auto reset `observations`
+    /// already shows their next episode. Games left done are skipped and return `None`.
+    pub fn step(&mut self, actions: &[Direction]) -> ARes<Vec<Option<EnvStep<GameAPIBinaryRepr>>>> {
+        if actions.len() != self.slots.len() {
+            return Err(anyhow!("Expected {} actions", self.slots.len()));
+        }
+        let auto_reset = self.auto_reset;
+        self.slots
+            .par_iter_mut()
+            .zip(actions.par_iter())
+            .map(|(slot, action)| {
+                if slot.done {
+                    return Ok(None);
+                }
+                let step = slot.env.step(*action)?;
+                if step.terminated || step.truncated {
+                    if auto_reset {
+                        slot.reset();
+                    } else {
+                        slot.done = true;
+                    }
+                }
+                Ok(Some(step))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_and_resets_in_parallel() {
+        let mut vec_env = VecEnv::new(GameAPIBuilder::default(), Some(2), 8, 1);
+        assert_eq!(vec_env.batched_observations().dim(), (8, 12, 12));
+        let actions = vec![Direction::Up; vec_env.len()];
+        let steps = vec_env.step(&actions).expect("Valid step");
+        assert!(
+            steps
+                .iter()
+                .all(|s| s.as_ref().is_some_and(|s| !s.truncated))
+        );
+        let steps = vec_env.step(&actions).expect("Valid step");
+        assert!(
+            steps
+                .iter()
+                .all(|s| s.as_ref().is_some_and(|s| s.truncated))
+        );
+        assert!(vec_env.games().all(|g| g.steps == 0));
+    }
+
+    #[test]
+    fn done_games_wait_without_auto_reset() {
+        let mut vec_env =
+            VecEnv::new(GameAPIBuilder::default(), Some(1), 4, 1).with_auto_reset(false);
+        let actions = vec![Direction::Left; vec_env.len()];
+        vec_env.step(&actions).expect("Valid step");
+        assert!(vec_env.all_done());
+        assert!(
+            vec_env
+                .step(&actions)
+                .expect("Valid step")
+                .iter()
+                .all(Option::is_none)
+        );
+        vec_env.reset();
+        assert!(!vec_env.is_done(0));
+    }
+
+    #[test]
+    fn reset_game_restarts_one_game() {
+        let mut vec_env =
+            VecEnv::new(GameAPIBuilder::default(), Some(1), 2, 1).with_auto_reset(false);
+        vec_env.step(&[Direction::Left; 2]).expect("Valid step");
+        vec_env.reset_game(1);
+        assert!(vec_env.is_done(0));
+        assert!(!vec_env.is_done(1));
+        assert_eq!(vec_env.game(1).steps, 0);
+    }
+
+    #[test]
+    fn same_seed_same_batch() {
+        let first = VecEnv::new(GameAPIBuilder::default(), None, 4, 7).batched_observations();
+        let second = VecEnv::new(GameAPIBuilder::default(), None, 4, 7).batched_observations();
+        assert_eq!(first, second);
+    }
+}